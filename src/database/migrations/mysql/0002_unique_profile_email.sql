@@ -0,0 +1,4 @@
+-- One account per email address; NULL emails remain allowed on any number of profiles.
+-- Deduplicate existing user_profiles.email values before applying.
+
+CREATE UNIQUE INDEX idx_profile_email ON user_profiles(email);
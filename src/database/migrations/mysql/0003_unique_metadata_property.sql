@@ -0,0 +1,34 @@
+-- At most one row per property and position under the same parent for a user. A
+-- multi-valued property such as `hobby` has one row per value, numbered from 0 in
+-- `position`. NULL parents would not collide in a plain unique index, so the index uses a
+-- generated column mapping them to ''. Existing repeats are kept and numbered in insertion
+-- order.
+--
+-- MySQL commits each DDL statement on its own, so a failure part way through leaves the
+-- columns behind without recording the migration. Adding them is skipped when they exist,
+-- and the other two statements can be repeated, so a rerun finishes the job.
+
+SET @add_columns = IF(
+    EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = DATABASE() AND table_name = 'user_metadata' AND column_name = 'position'
+    ),
+    'DO 0',
+    'ALTER TABLE user_metadata
+         ADD COLUMN position INT NOT NULL DEFAULT 0 AFTER property,
+         ADD COLUMN parent_key VARCHAR(255) AS (COALESCE(parent_property, '''')) STORED'
+);
+PREPARE add_columns FROM @add_columns;
+EXECUTE add_columns;
+DEALLOCATE PREPARE add_columns;
+
+UPDATE user_metadata m
+JOIN (
+    SELECT id,
+           ROW_NUMBER() OVER (PARTITION BY user_id, COALESCE(parent_property, ''), property ORDER BY id) - 1 AS position
+    FROM user_metadata
+) numbered ON numbered.id = m.id
+SET m.position = numbered.position;
+
+ALTER TABLE user_metadata
+    ADD UNIQUE INDEX idx_metadata_property (user_id, parent_key, property, position);
@@ -0,0 +1,8 @@
+-- Indexes for searching users by metadata (POST /api/users/search)
+-- Every search condition names a property, so the index leads with it; TEXT values are
+-- indexed by their first 191 characters. The binary collation makes value comparisons
+-- case-sensitive as on the other backends; case-insensitive searches compare LOWER(value).
+
+ALTER TABLE user_metadata
+    MODIFY value TEXT CHARACTER SET utf8mb4 COLLATE utf8mb4_bin,
+    ADD INDEX idx_metadata_search (property, value(191));
@@ -0,0 +1,9 @@
+-- Name parts for display-name construction: middle name, preferred name, honorific prefix,
+-- suffix and a display name that overrides the constructed one
+
+ALTER TABLE user_profiles
+    ADD COLUMN middle_name VARCHAR(255) NULL AFTER first_name,
+    ADD COLUMN nickname VARCHAR(255) NULL AFTER last_name,
+    ADD COLUMN honorific VARCHAR(255) NULL AFTER nickname,
+    ADD COLUMN suffix VARCHAR(255) NULL AFTER honorific,
+    ADD COLUMN display_name VARCHAR(255) NULL AFTER suffix;
@@ -0,0 +1,16 @@
+-- At most one row per property and position under the same parent for a user
+-- (PostgreSQL dialect of mysql/0003_unique_metadata_property.sql)
+
+ALTER TABLE user_metadata ADD COLUMN IF NOT EXISTS position INTEGER NOT NULL DEFAULT 0;
+
+UPDATE user_metadata m
+SET position = numbered.position
+FROM (
+    SELECT id,
+           ROW_NUMBER() OVER (PARTITION BY user_id, COALESCE(parent_property, ''), property ORDER BY id) - 1 AS position
+    FROM user_metadata
+) numbered
+WHERE numbered.id = m.id;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_metadata_property
+    ON user_metadata(user_id, COALESCE(parent_property, ''), property, position);
@@ -0,0 +1,5 @@
+-- Indexes for searching users by metadata
+-- (PostgreSQL dialect of mysql/0004_metadata_search.sql). Values are left out of the index:
+-- a B-tree entry cannot exceed about 2.7 kB and metadata values are unbounded TEXT.
+
+CREATE INDEX IF NOT EXISTS idx_metadata_search ON user_metadata(property, parent_property);
@@ -0,0 +1,9 @@
+-- Name parts for display-name construction
+-- (PostgreSQL dialect of mysql/0005_profile_names.sql)
+
+ALTER TABLE user_profiles
+    ADD COLUMN IF NOT EXISTS middle_name VARCHAR(255),
+    ADD COLUMN IF NOT EXISTS nickname VARCHAR(255),
+    ADD COLUMN IF NOT EXISTS honorific VARCHAR(255),
+    ADD COLUMN IF NOT EXISTS suffix VARCHAR(255),
+    ADD COLUMN IF NOT EXISTS display_name VARCHAR(255);
@@ -0,0 +1,3 @@
+-- One account per email address (SQLite dialect of mysql/0002_unique_profile_email.sql)
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_profile_email ON user_profiles(email);
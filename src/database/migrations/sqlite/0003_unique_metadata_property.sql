@@ -0,0 +1,18 @@
+-- At most one row per property and position under the same parent for a user
+-- (SQLite dialect of mysql/0003_unique_metadata_property.sql)
+
+ALTER TABLE user_metadata ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+
+UPDATE user_metadata
+SET position = (
+    SELECT numbered.position
+    FROM (
+        SELECT id,
+               ROW_NUMBER() OVER (PARTITION BY user_id, COALESCE(parent_property, ''), property ORDER BY id) - 1 AS position
+        FROM user_metadata
+    ) numbered
+    WHERE numbered.id = user_metadata.id
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_metadata_property
+    ON user_metadata(user_id, COALESCE(parent_property, ''), property, position);
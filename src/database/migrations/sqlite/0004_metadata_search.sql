@@ -0,0 +1,4 @@
+-- Indexes for searching users by metadata
+-- (SQLite dialect of mysql/0004_metadata_search.sql; values already compare case-sensitively)
+
+CREATE INDEX IF NOT EXISTS idx_metadata_search ON user_metadata(property, value);
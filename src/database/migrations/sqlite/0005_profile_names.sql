@@ -0,0 +1,8 @@
+-- Name parts for display-name construction
+-- (SQLite dialect of mysql/0005_profile_names.sql; SQLite adds one column per statement)
+
+ALTER TABLE user_profiles ADD COLUMN middle_name VARCHAR(255);
+ALTER TABLE user_profiles ADD COLUMN nickname VARCHAR(255);
+ALTER TABLE user_profiles ADD COLUMN honorific VARCHAR(255);
+ALTER TABLE user_profiles ADD COLUMN suffix VARCHAR(255);
+ALTER TABLE user_profiles ADD COLUMN display_name VARCHAR(255);
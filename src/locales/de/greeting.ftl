@@ -0,0 +1,15 @@
+# Greeting messages for GET /api/users/{user_id}
+
+greeting-welcome = Hallo { $name }, willkommen!
+greeting-welcome-title = Hallo { $title } { $name }, willkommen!
+greeting-news = {" "}Wenn wir Interessantes über { $hobby } hören, sagen wir Ihnen Bescheid!
+greeting-news-email = {" "}Wenn wir Interessantes über { $hobby } hören, sagen wir Ihnen unter { $email } Bescheid!
+
+# Aufzählungen: "Schach, Wandern und Jazz", "Schach, Wandern und 2 weitere"
+list-two = { $first } und { $second }
+list-middle = { $first }, { $rest }
+list-end = { $first } und { $last }
+list-more = { $count ->
+    [one] ein weiteres
+   *[other] { $count } weitere
+}
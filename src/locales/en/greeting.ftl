@@ -0,0 +1,16 @@
+# Greeting messages for GET /api/users/{user_id}
+# {" "} keeps the space that separates the two sentences
+
+greeting-welcome = Hello { $name }, welcome!
+greeting-welcome-title = Hello { $title } { $name }, welcome!
+greeting-news = {" "}If we hear interesting news about { $hobby }, we will let you know!
+greeting-news-email = {" "}If we hear interesting news about { $hobby }, we will let you know at { $email }!
+
+# Lists of titles and hobbies: "chess, hiking and jazz", "chess, hiking and 2 more"
+list-two = { $first } and { $second }
+list-middle = { $first }, { $rest }
+list-end = { $first } and { $last }
+list-more = { $count ->
+    [one] one more
+   *[other] { $count } more
+}
@@ -0,0 +1,13 @@
+# Greeting messages for GET /api/users/{user_id}
+# Japanese sentences are not separated by a space
+
+greeting-welcome = { $name }さん、ようこそ！
+greeting-welcome-title = { $title } { $name }さん、ようこそ！
+greeting-news = { $hobby }について面白いニュースがあればお知らせします！
+greeting-news-email = { $hobby }について面白いニュースがあれば{ $email }にお知らせします！
+
+# 列挙: 「チェス、ハイキング、ジャズ」「チェス、ハイキング、他2件」
+list-two = { $first }と{ $second }
+list-middle = { $first }、{ $rest }
+list-end = { $first }、{ $last }
+list-more = 他{ $count }件
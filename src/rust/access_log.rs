@@ -0,0 +1,265 @@
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, HeaderName, FORWARDED, X_FORWARDED_FOR};
+use std::future::{ready, Future, Ready};
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::logger::{dual_log, LogLevel};
+
+/// Output format for access log lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// Common Log Format with latency appended in milliseconds
+    Common,
+    /// One JSON object per request
+    Json,
+}
+
+impl AccessLogFormat {
+    /// Read `ACCESS_LOG_FORMAT` (`common` or `json`, default: `common`)
+    pub fn from_env() -> Self {
+        match std::env::var("ACCESS_LOG_FORMAT") {
+            Ok(value) if value.trim().eq_ignore_ascii_case("json") => AccessLogFormat::Json,
+            _ => AccessLogFormat::Common,
+        }
+    }
+}
+
+/// Proxies whose `Forwarded` and `X-Forwarded-For` headers name the client. Requests from
+/// anywhere else are logged with their peer address, so clients can't spoof the logged IP.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Arc<Vec<IpAddr>>);
+
+impl TrustedProxies {
+    /// Read `ACCESS_LOG_TRUSTED_PROXIES`, a comma-separated list of IP addresses (default: none)
+    pub fn from_env() -> Result<Self, String> {
+        let value = std::env::var("ACCESS_LOG_TRUSTED_PROXIES").unwrap_or_default();
+        let proxies = value
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse().map_err(|_| format!("ACCESS_LOG_TRUSTED_PROXIES: '{}' is not an IP address", v)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(proxies))
+    }
+
+    pub fn new(proxies: Vec<IpAddr>) -> Self {
+        TrustedProxies(Arc::new(proxies))
+    }
+
+    fn trusts(&self, ip: &IpAddr) -> bool {
+        self.0.contains(ip)
+    }
+
+    /// Address of the client behind `peer`: the peer itself unless it is a trusted proxy,
+    /// otherwise the nearest forwarded hop that is not one
+    pub fn client_ip(&self, peer: Option<SocketAddr>, headers: &HeaderMap) -> String {
+        let Some(peer) = peer else {
+            return "-".to_string();
+        };
+        if !self.trusts(&peer.ip()) {
+            return peer.ip().to_string();
+        }
+
+        // Each proxy appends the address it received the request from, so walk from the
+        // nearest hop back and stop at the first one we don't operate
+        let hops = forwarded_hops(headers);
+        for hop in hops.iter().rev() {
+            match hop.parse::<IpAddr>() {
+                Ok(ip) if self.trusts(&ip) => continue,
+                Ok(ip) => return ip.to_string(),
+                Err(_) => return hop.clone(),
+            }
+        }
+        hops.first().cloned().unwrap_or_else(|| peer.ip().to_string())
+    }
+}
+
+/// Client addresses from the `for=` parameters of `Forwarded`, or else from `X-Forwarded-For`,
+/// farthest first, without ports, quotes or IPv6 brackets
+fn forwarded_hops(headers: &HeaderMap) -> Vec<String> {
+    let values = |name: HeaderName| {
+        headers
+            .get_all(name)
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+
+    let forwarded: Vec<String> = values(FORWARDED)
+        .iter()
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for").then(|| value.trim_matches('"').to_string())
+            })
+        })
+        .collect();
+    let hops = if forwarded.is_empty() { values(X_FORWARDED_FOR) } else { forwarded };
+
+    hops.into_iter().filter(|hop| !hop.is_empty()).map(|hop| strip_port(&hop)).collect()
+}
+
+/// `hop` without its port: `[2001:db8::1]:4711` becomes `2001:db8::1`, `192.0.2.1:80` becomes `192.0.2.1`
+fn strip_port(hop: &str) -> String {
+    if let Some(rest) = hop.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or_default().to_string();
+    }
+    match hop.split_once(':') {
+        Some((host, port)) if !port.contains(':') => host.to_string(),
+        _ => hop.to_string(),
+    }
+}
+
+/// Data captured for a single request/response pair
+#[derive(Debug, Clone)]
+pub struct AccessLogEntry {
+    pub client_ip: String,
+    pub method: String,
+    pub path: String,
+    pub protocol: String,
+    pub status: u16,
+    pub size: Option<u64>,
+    pub latency_ms: f64,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+impl AccessLogEntry {
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        match format {
+            AccessLogFormat::Common => format!(
+                "{} - - [{}] \"{} {} {}\" {} {} {:.3}ms",
+                self.client_ip,
+                self.timestamp.format("%d/%b/%Y:%H:%M:%S %z"),
+                self.method,
+                self.path,
+                self.protocol,
+                self.status,
+                self.size.map(|s| s.to_string()).unwrap_or_else(|| "-".to_string()),
+                self.latency_ms,
+            ),
+            AccessLogFormat::Json => serde_json::json!({
+                "timestamp": self.timestamp.to_rfc3339(),
+                "client_ip": self.client_ip,
+                "method": self.method,
+                "path": self.path,
+                "protocol": self.protocol,
+                "status": self.status,
+                "size": self.size,
+                "latency_ms": self.latency_ms,
+            })
+            .to_string(),
+        }
+    }
+}
+
+/// Middleware that writes one access log line per request through the dual-logging pipeline
+pub struct AccessLog {
+    client: reqwest::Client,
+    format: AccessLogFormat,
+    proxies: TrustedProxies,
+}
+
+impl AccessLog {
+    pub fn new(client: reqwest::Client, format: AccessLogFormat) -> Self {
+        AccessLog { client, format, proxies: TrustedProxies::default() }
+    }
+
+    /// Take the client IP from forwarding headers set by `proxies`
+    pub fn with_trusted_proxies(mut self, proxies: TrustedProxies) -> Self {
+        self.proxies = proxies;
+        self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AccessLog
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = AccessLogMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AccessLogMiddleware {
+            service: Rc::new(service),
+            client: self.client.clone(),
+            format: self.format,
+            proxies: self.proxies.clone(),
+        }))
+    }
+}
+
+pub struct AccessLogMiddleware<S> {
+    service: Rc<S>,
+    client: reqwest::Client,
+    format: AccessLogFormat,
+    proxies: TrustedProxies,
+}
+
+impl<S, B> Service<ServiceRequest> for AccessLogMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let timestamp = chrono::Utc::now();
+        let client_ip = self.proxies.client_ip(req.peer_addr(), req.headers());
+        let method = req.method().to_string();
+        let protocol = format!("{:?}", req.version());
+        let raw_path = req.path().to_string();
+
+        let service = Rc::clone(&self.service);
+        let client = self.client.clone();
+        let format = self.format;
+
+        Box::pin(async move {
+            let result = service.call(req).await;
+
+            // Log the route template so ids and other path parameters don't explode cardinality.
+            // An error carries no request, so it is logged with the raw path.
+            let (path, status, size) = match &result {
+                Ok(res) => {
+                    let size = match res.response().body().size() {
+                        BodySize::Sized(n) => Some(n),
+                        BodySize::None => Some(0),
+                        BodySize::Stream => None,
+                    };
+                    (res.request().match_pattern().unwrap_or(raw_path), res.status(), size)
+                }
+                Err(e) => (raw_path, e.as_response_error().status_code(), None),
+            };
+
+            let entry = AccessLogEntry {
+                client_ip,
+                method,
+                path,
+                protocol,
+                status: status.as_u16(),
+                size,
+                latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+                timestamp,
+            };
+
+            dual_log(client, LogLevel::Info, "access_log", None, entry.format(format));
+
+            result
+        })
+    }
+}
@@ -0,0 +1,223 @@
+use actix_cors::Cors;
+use actix_web::http::header::HeaderName;
+use actix_web::http::Method;
+
+/// An entry of an origin allow-list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllowedOrigin {
+    /// `*`: every origin
+    Any,
+    /// `https://app.example.com[:port]`
+    Exact(String),
+    /// `https://*.example.com[:port]`: any subdomain of `domain`, at any depth, but not
+    /// `domain` itself
+    Subdomains { scheme: String, domain: String },
+}
+
+impl AllowedOrigin {
+    /// Parse `*`, an origin, or an origin whose host starts with `*.`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim().trim_end_matches('/').to_ascii_lowercase();
+        if value == "*" {
+            return Ok(AllowedOrigin::Any);
+        }
+        let invalid = || format!("'{}' is not an origin (scheme://host[:port])", value);
+        let (scheme, host) = value.split_once("://").ok_or_else(invalid)?;
+        if !matches!(scheme, "http" | "https") || host.is_empty() || host.contains(['/', '?', '#', '@']) {
+            return Err(invalid());
+        }
+        match host.strip_prefix("*.") {
+            Some(domain) if !domain.is_empty() && !domain.contains('*') => {
+                Ok(AllowedOrigin::Subdomains { scheme: scheme.to_string(), domain: domain.to_string() })
+            }
+            Some(_) => Err(invalid()),
+            None if host.contains('*') => Err(invalid()),
+            None => Ok(AllowedOrigin::Exact(value)),
+        }
+    }
+
+    /// Whether the `Origin` header value `origin` is allowed
+    pub fn matches(&self, origin: &str) -> bool {
+        let origin = origin.to_ascii_lowercase();
+        match self {
+            AllowedOrigin::Any => true,
+            AllowedOrigin::Exact(allowed) => origin == *allowed,
+            AllowedOrigin::Subdomains { scheme, domain } => {
+                let Some(host) = origin.strip_prefix(scheme.as_str()).and_then(|rest| rest.strip_prefix("://")) else {
+                    return false;
+                };
+                host.strip_suffix(domain.as_str())
+                    .and_then(|subdomain| subdomain.strip_suffix('.'))
+                    .is_some_and(|subdomain| {
+                        !subdomain.is_empty()
+                            && subdomain.split('.').all(|label| {
+                                !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                            })
+                    })
+            }
+        }
+    }
+}
+
+/// Which cross-origin requests one group of routes accepts
+#[derive(Debug, Clone)]
+pub struct CorsPolicy {
+    origins: Vec<AllowedOrigin>,
+    methods: Vec<Method>,
+    headers: Vec<HeaderName>,
+    credentials: bool,
+    max_age: usize,
+}
+
+impl CorsPolicy {
+    /// Readable from any page: `GET` from every origin, without credentials
+    pub fn public() -> Self {
+        CorsPolicy {
+            origins: vec![AllowedOrigin::Any],
+            methods: vec![Method::GET],
+            headers: Vec::new(),
+            credentials: false,
+            max_age: 3600,
+        }
+    }
+
+    /// Default for `/api`: no cross-origin access until origins are configured
+    pub fn api() -> Self {
+        CorsPolicy {
+            origins: Vec::new(),
+            methods: vec![Method::GET, Method::POST, Method::PUT, Method::DELETE],
+            headers: vec![
+                actix_web::http::header::ACCEPT,
+                actix_web::http::header::ACCEPT_LANGUAGE,
+                actix_web::http::header::CONTENT_TYPE,
+            ],
+            credentials: false,
+            max_age: 3600,
+        }
+    }
+
+    /// Default for `/admin`: like `api`, plus the admin token header
+    pub fn admin() -> Self {
+        let mut policy = Self::api();
+        policy.methods = vec![Method::GET, Method::POST, Method::PUT];
+        policy.headers.push(HeaderName::from_static("x-admin-token"));
+        policy
+    }
+
+    /// The policy with any of `{prefix}_ALLOWED_ORIGINS`, `{prefix}_ALLOWED_METHODS`,
+    /// `{prefix}_ALLOWED_HEADERS` (comma-separated), `{prefix}_ALLOW_CREDENTIALS` and
+    /// `{prefix}_MAX_AGE` (seconds) that are set taking the place of its own settings
+    pub fn with_env(mut self, prefix: &str) -> Result<Self, String> {
+        let var = |name: &str| {
+            let name = format!("{}_{}", prefix, name);
+            std::env::var(&name).ok().filter(|v| !v.trim().is_empty()).map(|v| (name, v))
+        };
+        let list = |value: &str| value.split(',').map(str::trim).filter(|v| !v.is_empty()).map(String::from).collect::<Vec<_>>();
+
+        if let Some((name, value)) = var("ALLOWED_ORIGINS") {
+            self.origins = list(&value)
+                .iter()
+                .map(|origin| AllowedOrigin::parse(origin))
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("{}: {}", name, e))?;
+        }
+        if let Some((name, value)) = var("ALLOWED_METHODS") {
+            self.methods = list(&value)
+                .iter()
+                .map(|method| method.to_ascii_uppercase().parse::<Method>().map_err(|_| format!("{}: invalid method '{}'", name, method)))
+                .collect::<Result<_, _>>()?;
+            if self.methods.is_empty() {
+                return Err(format!("{} must list at least one method", name));
+            }
+        }
+        if let Some((name, value)) = var("ALLOWED_HEADERS") {
+            self.headers = list(&value)
+                .iter()
+                .map(|header| HeaderName::try_from(header.as_str()).map_err(|_| format!("{}: invalid header '{}'", name, header)))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some((name, value)) = var("MAX_AGE") {
+            self.max_age = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("{} must be a number of seconds, got '{}'", name, value))?;
+        }
+        let credentials = match var("ALLOW_CREDENTIALS") {
+            Some((_, value)) => value.trim().eq_ignore_ascii_case("true"),
+            None => self.credentials,
+        };
+        self.with_credentials(credentials).map_err(|e| format!("{}_ALLOW_CREDENTIALS: {}", prefix, e))
+    }
+
+    /// The policy letting allowed origins send cookies and read credentialed responses.
+    /// Refused together with `*`, which would hand every site the user's session.
+    pub fn with_credentials(mut self, credentials: bool) -> Result<Self, String> {
+        if credentials && self.origins.contains(&AllowedOrigin::Any) {
+            return Err("credentials cannot be allowed for every origin ('*')".to_string());
+        }
+        self.credentials = credentials;
+        Ok(self)
+    }
+
+    /// The allowed origins
+    pub fn origins(&self) -> &[AllowedOrigin] {
+        &self.origins
+    }
+
+    /// Whether `origin` may make cross-origin requests
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.origins.iter().any(|allowed| allowed.matches(origin))
+    }
+
+    /// Middleware enforcing the policy. Requests from other origins still reach the
+    /// handlers, but without the headers that let the browser hand the response to the page.
+    pub fn middleware(&self) -> Cors {
+        let mut cors = Cors::default()
+            .allowed_methods(self.methods.clone())
+            .max_age(self.max_age);
+        if !self.headers.is_empty() {
+            cors = cors.allowed_headers(self.headers.clone());
+        }
+        if self.origins.contains(&AllowedOrigin::Any) {
+            cors = cors.allow_any_origin().send_wildcard();
+        } else {
+            let policy = self.clone();
+            cors = cors.allowed_origin_fn(move |origin, _| origin.to_str().is_ok_and(|o| policy.allows_origin(o)));
+        }
+        if self.credentials {
+            cors = cors.supports_credentials();
+        }
+        cors
+    }
+}
+
+/// CORS policies per group of routes: `public` for `/health`, `api` for `/api` and `admin`
+/// for `/admin`. The pages and the web UI are same-origin only.
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    pub public: CorsPolicy,
+    pub api: CorsPolicy,
+    pub admin: CorsPolicy,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            public: CorsPolicy::public(),
+            api: CorsPolicy::api(),
+            admin: CorsPolicy::admin(),
+        }
+    }
+}
+
+impl CorsConfig {
+    /// Defaults with the `public` policy configured by `CORS_PUBLIC_*`, the `api` policy by
+    /// `CORS_*` and the `admin` policy by `CORS_ADMIN_*` (see [`CorsPolicy::with_env`])
+    pub fn from_env() -> Result<Self, String> {
+        Ok(CorsConfig {
+            public: CorsPolicy::public().with_env("CORS_PUBLIC")?,
+            api: CorsPolicy::api().with_env("CORS")?,
+            admin: CorsPolicy::admin().with_env("CORS_ADMIN")?,
+        })
+    }
+}
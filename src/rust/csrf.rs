@@ -0,0 +1,132 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, Method};
+use actix_web::{HttpRequest, HttpResponse};
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::cors::{AllowedOrigin, CorsPolicy};
+use crate::ErrorResponse;
+
+/// Content types an HTML form or a no-cors `fetch` can send cross-site without a CORS
+/// preflight. Anything else (JSON, for one) is left to the CORS policy.
+const SIMPLE_CONTENT_TYPES: &[&str] = &["application/x-www-form-urlencoded", "multipart/form-data", "text/plain"];
+
+/// Why a request was refused as cross-site
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CsrfRejection {
+    #[error("Cross-origin form post from {0} is not allowed")]
+    CrossOrigin(String),
+    #[error("Form posts from an opaque origin are not allowed")]
+    OpaqueOrigin,
+}
+
+/// Origin/Referer checking for state-changing requests a cross-site page could forge:
+/// POST, PUT, PATCH and DELETE with a form-like body (or none). When the browser names the
+/// page that sent the request, its origin must be the service's own or a trusted one.
+/// Clients that send neither header (curl, other services) are not browsers and pass.
+#[derive(Debug, Clone, Default)]
+pub struct CsrfProtection {
+    trusted_origins: Arc<Vec<AllowedOrigin>>,
+}
+
+impl CsrfProtection {
+    /// Trust the origins `api` (the `/api` CORS policy) allows besides the service's own, so
+    /// one list decides which sites may call the service. `*` is left out: opening the API
+    /// to every site is no reason to accept their form posts.
+    pub fn new(api: &CorsPolicy) -> Self {
+        let origins = api.origins().iter().filter(|o| **o != AllowedOrigin::Any).cloned().collect();
+        CsrfProtection { trusted_origins: Arc::new(origins) }
+    }
+
+    /// Whether `req` may go through
+    pub fn check(&self, req: &HttpRequest) -> Result<(), CsrfRejection> {
+        if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE) {
+            return Ok(());
+        }
+        let content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.split(';').next().unwrap_or_default().trim().to_ascii_lowercase());
+        if content_type.is_some_and(|t| !SIMPLE_CONTENT_TYPES.contains(&t.as_str())) {
+            return Ok(());
+        }
+
+        let header_value = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+        let source = match (header_value(header::ORIGIN), header_value(header::REFERER)) {
+            (Some("null"), _) => return Err(CsrfRejection::OpaqueOrigin),
+            (Some(origin), _) => origin.to_ascii_lowercase(),
+            (None, Some(referer)) => origin_of(referer),
+            (None, None) => return Ok(()),
+        };
+
+        let info = req.connection_info();
+        let own = format!("{}://{}", info.scheme(), info.host()).to_ascii_lowercase();
+        if source == own || self.trusted_origins.iter().any(|trusted| trusted.matches(&source)) {
+            Ok(())
+        } else {
+            Err(CsrfRejection::CrossOrigin(source))
+        }
+    }
+}
+
+/// `scheme://host[:port]` of a URL, lowercased
+fn origin_of(url: &str) -> String {
+    let end = url
+        .find("://")
+        .and_then(|scheme_end| url[scheme_end + 3..].find(['/', '?', '#']).map(|i| scheme_end + 3 + i))
+        .unwrap_or(url.len());
+    url[..end].to_ascii_lowercase()
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CsrfProtection
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type Transform = CsrfProtectionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CsrfProtectionMiddleware {
+            service: Rc::new(service),
+            protection: self.clone(),
+        }))
+    }
+}
+
+pub struct CsrfProtectionMiddleware<S> {
+    service: Rc<S>,
+    protection: CsrfProtection,
+}
+
+impl<S, B> Service<ServiceRequest> for CsrfProtectionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if let Err(rejection) = self.protection.check(req.request()) {
+            let response = HttpResponse::Forbidden().json(ErrorResponse {
+                error: "CSRF_REJECTED".to_string(),
+                message: rejection.to_string(),
+            });
+            return Box::pin(ready(Ok(req.into_response(response))));
+        }
+
+        let service = Rc::clone(&self.service);
+        Box::pin(async move { Ok(service.call(req).await?.map_into_boxed_body()) })
+    }
+}
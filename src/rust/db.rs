@@ -1,56 +1,56 @@
-#[cfg(not(test))]
-use sqlx::mysql::{MySqlPool as Pool, MySqlPoolOptions as PoolOptions};
-#[cfg(test)]
-use sqlx::sqlite::{SqlitePool as Pool, SqlitePoolOptions as PoolOptions};
-
-use sqlx::Row;
-use std::time::Duration;
+use sqlx::mysql::{MySqlConnection, MySqlPool};
+use sqlx::postgres::{PgConnection, PgPool};
+use sqlx::sqlite::{SqliteConnection, SqlitePool};
+
+use crate::metadata::{self, MetadataNode, MetadataSubtree, MetadataTreeError};
+use crate::metadata_schema::{MetadataSchema, SchemaError};
+use crate::metadata_search::{self, Combine, MatchMode, MetadataCondition, MetadataQuery, SearchError};
+use crate::migrations::{
+    Migration, MigrationReport, MYSQL_MIGRATIONS, POSTGRES_MIGRATIONS, SCHEMA_MIGRATIONS_TABLE,
+    SQLITE_MIGRATIONS,
+};
+use sqlx::pool::{PoolConnection, PoolOptions};
+use sqlx::{Connection, Row};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::field::Empty;
+use tracing::Instrument;
 use chrono::NaiveDateTime;
 use serde::{Serialize, Deserialize};
 
-// SQLite-compatible schema for testing
-// Note: Uses AUTOINCREMENT (SQLite) instead of AUTO_INCREMENT (MySQL)
-// and INTEGER PRIMARY KEY instead of INT AUTO_INCREMENT
-#[cfg(test)]
-const CREATE_SCHEMA_SQLITE: &str = "
-CREATE TABLE IF NOT EXISTS users (
-    id INTEGER PRIMARY KEY AUTOINCREMENT,
-    username VARCHAR(16) NOT NULL UNIQUE,
-    password VARCHAR(255) NOT NULL,
-    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
-);
-
-CREATE TABLE IF NOT EXISTS user_profiles (
-    user_id INTEGER PRIMARY KEY,
-    first_name VARCHAR(255),
-    last_name VARCHAR(255),
-    email VARCHAR(255),
-    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
-);
-
-CREATE TABLE IF NOT EXISTS user_metadata (
-    id INTEGER PRIMARY KEY AUTOINCREMENT,
-    user_id INTEGER NOT NULL,
-    parent_property VARCHAR(255),
-    property VARCHAR(255) NOT NULL,
-    value TEXT,
-    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
-);
-";
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
+
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct UserProfile {
     pub first_name: Option<String>,
     pub last_name: Option<String>,
     pub email: Option<String>,
+    #[serde(default)]
+    pub middle_name: Option<String>,
+    /// Preferred name, used by informal display names
+    #[serde(default)]
+    pub nickname: Option<String>,
+    /// Honorific prefix such as "Dr." or "Ms."
+    #[serde(default)]
+    pub honorific: Option<String>,
+    /// Generational or academic suffix such as "Jr." or "PhD"
+    #[serde(default)]
+    pub suffix: Option<String>,
+    /// Replaces the constructed formal display name
+    #[serde(default)]
+    pub display_name: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct UserMetadata {
     pub parent_property: Option<String>,
     pub property: String,
     pub value: Option<String>,
+    /// Order among siblings with the same `property`, from 0; only multi-valued
+    /// properties have rows past 0
+    #[serde(default)]
+    pub position: i32,
 }
 
 #[derive(Debug, Clone)]
@@ -76,200 +76,891 @@ pub struct CreateUserRequest {
 pub enum DatabaseError {
     #[error("Database connection error: {0}")]
     ConnectionError(String),
+    #[error("Timed out waiting for a database connection")]
+    PoolTimeout,
     #[error("Username already exists")]
     DuplicateUsername,
+    #[error("Email already exists")]
+    DuplicateEmail,
+    #[error("Foreign key violation: {0}")]
+    ForeignKeyViolation(String),
+    #[error("Constraint violation: {0}")]
+    ConstraintViolation(String),
+    #[error("Transaction could not be serialized; retry")]
+    SerializationFailure,
     #[error("User not found")]
     UserNotFound,
+    #[error("Metadata property '{0}' not found")]
+    MetadataNotFound(String),
+    #[error("Invalid metadata: {0}")]
+    InvalidMetadata(#[from] MetadataTreeError),
+    #[error("Invalid metadata: {0}")]
+    SchemaViolation(#[from] SchemaError),
+    #[error("Invalid search: {0}")]
+    InvalidSearch(#[from] SearchError),
     #[error("Database error: {0}")]
     QueryError(String),
+    #[error("Migration error: {0}")]
+    MigrationError(String),
+}
+
+impl DatabaseError {
+    /// Whether repeating the same request later may succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            DatabaseError::ConnectionError(_) | DatabaseError::PoolTimeout | DatabaseError::SerializationFailure
+        )
+    }
 }
 
 impl From<sqlx::Error> for DatabaseError {
     fn from(err: sqlx::Error) -> Self {
         match err {
-            sqlx::Error::RowNotFound => DatabaseError::UserNotFound,
-            sqlx::Error::Database(db_err) => {
-                if db_err.message().contains("Duplicate entry") || db_err.message().contains("UNIQUE") {
-                    DatabaseError::DuplicateUsername
-                } else {
-                    DatabaseError::QueryError(db_err.message().to_string())
-                }
+            // Callers that look up a single row decide what a missing row means
+            sqlx::Error::RowNotFound => DatabaseError::QueryError("query returned no rows".to_string()),
+            sqlx::Error::PoolTimedOut => DatabaseError::PoolTimeout,
+            sqlx::Error::PoolClosed | sqlx::Error::Io(_) | sqlx::Error::Tls(_) => {
+                DatabaseError::ConnectionError(err.to_string())
             }
+            sqlx::Error::Database(db_err) => from_driver_error(db_err.as_ref()),
             _ => DatabaseError::QueryError(err.to_string()),
         }
     }
 }
 
+/// Map a driver error by its error class and the constraint it names
+pub(crate) fn from_driver_error(err: &dyn sqlx::error::DatabaseError) -> DatabaseError {
+    use sqlx::error::ErrorKind;
+
+    if is_serialization_failure(err) {
+        return DatabaseError::SerializationFailure;
+    }
+
+    let constraint = violated_constraint(err);
+    match err.kind() {
+        ErrorKind::UniqueViolation if constraint.contains("username") => DatabaseError::DuplicateUsername,
+        ErrorKind::UniqueViolation if constraint.contains("email") => DatabaseError::DuplicateEmail,
+        ErrorKind::ForeignKeyViolation => DatabaseError::ForeignKeyViolation(constraint),
+        ErrorKind::UniqueViolation | ErrorKind::NotNullViolation | ErrorKind::CheckViolation => {
+            DatabaseError::ConstraintViolation(constraint)
+        }
+        _ => DatabaseError::QueryError(err.message().to_string()),
+    }
+}
+
+/// Constraint or index named by the error. Postgres reports it directly; MySQL
+/// ("Duplicate entry '..' for key 'users.idx_username'") and SQLite
+/// ("UNIQUE constraint failed: users.username") only mention it in the message.
+fn violated_constraint(err: &dyn sqlx::error::DatabaseError) -> String {
+    if let Some(name) = err.constraint() {
+        return name.to_string();
+    }
+
+    let message = err.message();
+    if let Some((_, key)) = message.split_once(" for key '") {
+        return key.trim_end_matches('\'').to_string();
+    }
+    if let Some((_, columns)) = message.split_once("constraint failed: ") {
+        return columns.to_string();
+    }
+    message.to_string()
+}
+
+/// Deadlocks, serialization conflicts and lock timeouts, which a retry can resolve
+fn is_serialization_failure(err: &dyn sqlx::error::DatabaseError) -> bool {
+    // SQLSTATE 40001 (serialization_failure, also MySQL deadlocks) and 40P01 (Postgres deadlock)
+    if matches!(err.code().as_deref(), Some("40001") | Some("40P01")) {
+        return true;
+    }
+    if let Some(mysql) = err.try_downcast_ref::<sqlx::mysql::MySqlDatabaseError>() {
+        // ER_LOCK_WAIT_TIMEOUT
+        return mysql.number() == 1205;
+    }
+    if err.try_downcast_ref::<sqlx::sqlite::SqliteError>().is_some() {
+        // SQLITE_BUSY / SQLITE_LOCKED, including their extended codes
+        let code = err.code().and_then(|c| c.parse::<i32>().ok()).unwrap_or(0);
+        return matches!(code & 0xff, 5 | 6);
+    }
+    false
+}
+
+/// Check the metadata rows a change leaves behind against `schema`
+pub fn check_schema(schema: &MetadataSchema, rows: &[UserMetadata]) -> Result<(), DatabaseError> {
+    Ok(schema.validate(&metadata::build_tree(rows)?)?)
+}
+
+/// Database engine, selected at runtime from the `DATABASE_URL` scheme
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    MySql,
+    Sqlite,
+    Postgres,
+}
+
+impl Backend {
+    /// Detect the backend from a connection URL (`mysql://`, `sqlite:`, `postgres://`)
+    pub fn from_url(url: &str) -> Result<Self, DatabaseError> {
+        let scheme = url.split(':').next().unwrap_or("").to_ascii_lowercase();
+        match scheme.as_str() {
+            "mysql" => Ok(Backend::MySql),
+            "sqlite" => Ok(Backend::Sqlite),
+            "postgres" | "postgresql" => Ok(Backend::Postgres),
+            _ => Err(DatabaseError::ConnectionError(format!(
+                "Unsupported DATABASE_URL scheme '{}' (expected mysql, sqlite or postgres)",
+                scheme
+            ))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Backend::MySql => "mysql",
+            Backend::Sqlite => "sqlite",
+            Backend::Postgres => "postgres",
+        }
+    }
+
+    /// Embedded migrations written in this backend's dialect
+    pub fn migrations(&self) -> &'static [Migration] {
+        match self {
+            Backend::MySql => MYSQL_MIGRATIONS,
+            Backend::Sqlite => SQLITE_MIGRATIONS,
+            Backend::Postgres => POSTGRES_MIGRATIONS,
+        }
+    }
+
+    /// Statements taking and releasing the session lock held while migrations run. SQLite
+    /// has none; its migrations run in transactions on a file one service instance owns.
+    fn migration_lock(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Backend::MySql => Some((
+                "SELECT GET_LOCK('rust_user_service.migrations', -1)",
+                "SELECT RELEASE_LOCK('rust_user_service.migrations')",
+            )),
+            // Arbitrary key reserved for this service's migrations
+            Backend::Postgres => Some(("SELECT pg_advisory_lock(727350)", "SELECT pg_advisory_unlock(727350)")),
+            Backend::Sqlite => None,
+        }
+    }
+
+    /// Query locking one user's row until the transaction ends, for read-check-write
+    /// changes to the user's data. SQLite locks the whole database on write instead.
+    fn lock_user_sql(&self) -> &'static str {
+        match self {
+            Backend::MySql | Backend::Postgres => "SELECT id FROM users WHERE id = ? FOR UPDATE",
+            Backend::Sqlite => "SELECT id FROM users WHERE id = ?",
+        }
+    }
+
+    /// Adapt a query written with `?` placeholders to this backend's bind syntax
+    fn sql<'q>(&self, query: &'q str) -> Cow<'q, str> {
+        match self {
+            Backend::Postgres => {
+                let mut out = String::with_capacity(query.len() + 8);
+                let mut n = 0;
+                for c in query.chars() {
+                    if c == '?' {
+                        n += 1;
+                        out.push('$');
+                        out.push_str(&n.to_string());
+                    } else {
+                        out.push(c);
+                    }
+                }
+                Cow::Owned(out)
+            }
+            Backend::MySql | Backend::Sqlite => Cow::Borrowed(query),
+        }
+    }
+
+    /// SQL comparing `m.value` with one bound parameter for `condition`, and the value to
+    /// bind. MySQL stores values with a binary collation (migration 0004) so that, as on
+    /// the other backends, only `ignore_case` conditions ignore case. SQLite's `LIKE`
+    /// ignores ASCII case, so prefixes use `GLOB` there.
+    fn metadata_value_match(&self, condition: &MetadataCondition) -> (&'static str, String) {
+        let value = &condition.value;
+        match (condition.mode, condition.ignore_case, self) {
+            (MatchMode::Exact, false, _) => ("m.value = ?", value.clone()),
+            (MatchMode::Exact, true, _) => ("LOWER(m.value) = LOWER(?)", value.clone()),
+            (MatchMode::Prefix, false, Backend::Sqlite) => ("m.value GLOB ?", format!("{}*", metadata_search::escape_glob(value))),
+            (MatchMode::Prefix, true, Backend::Sqlite) => {
+                ("LOWER(m.value) GLOB LOWER(?)", format!("{}*", metadata_search::escape_glob(value)))
+            }
+            (MatchMode::Prefix, false, _) => ("m.value LIKE ? ESCAPE '!'", format!("{}%", metadata_search::escape_like(value))),
+            (MatchMode::Prefix, true, _) => {
+                ("LOWER(m.value) LIKE LOWER(?) ESCAPE '!'", format!("{}%", metadata_search::escape_like(value)))
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+enum DbPool {
+    MySql(MySqlPool),
+    Sqlite(SqlitePool),
+    Postgres(PgPool),
+}
+
+/// Run the same body against whichever concrete pool backs the database.
+/// The body is compiled once per backend, so the sqlx calls inside it are
+/// checked against each driver's types.
+macro_rules! with_pool {
+    ($db:expr, $pool:ident => $body:expr) => {
+        match &$db.pool {
+            DbPool::MySql($pool) => $body,
+            DbPool::Sqlite($pool) => $body,
+            DbPool::Postgres($pool) => $body,
+        }
+    };
+}
+
+/// Driver-specific insert of the core user row, returning the generated id
+trait InsertUser {
+    async fn insert_user(&mut self, username: &str, password: &str) -> Result<i64, sqlx::Error>;
+}
+
+impl InsertUser for MySqlConnection {
+    async fn insert_user(&mut self, username: &str, password: &str) -> Result<i64, sqlx::Error> {
+        let result = sqlx::query("INSERT INTO users (username, password) VALUES (?, ?)")
+            .bind(username)
+            .bind(password)
+            .execute(self)
+            .await?;
+        Ok(result.last_insert_id() as i64)
+    }
+}
+
+impl InsertUser for SqliteConnection {
+    async fn insert_user(&mut self, username: &str, password: &str) -> Result<i64, sqlx::Error> {
+        let result = sqlx::query("INSERT INTO users (username, password) VALUES (?, ?)")
+            .bind(username)
+            .bind(password)
+            .execute(self)
+            .await?;
+        Ok(result.last_insert_rowid())
+    }
+}
+
+impl InsertUser for PgConnection {
+    async fn insert_user(&mut self, username: &str, password: &str) -> Result<i64, sqlx::Error> {
+        // Postgres has no last-insert-id; the generated key comes back from the INSERT itself
+        let id: i32 = sqlx::query_scalar("INSERT INTO users (username, password) VALUES ($1, $2) RETURNING id")
+            .bind(username)
+            .bind(password)
+            .fetch_one(self)
+            .await?;
+        Ok(id as i64)
+    }
+}
+
+/// Bind parameters per batched user lookup, well under every backend's limit
+/// (SQLite's is the lowest at 32766)
+const MAX_IDS_PER_QUERY: usize = 500;
+
+/// A user's metadata rows with their IDs. The join yields one row with a NULL ID for a
+/// user without metadata, and no rows for a missing user.
+const SELECT_USER_METADATA: &str = "SELECT m.id, m.parent_property, m.property, m.value, m.position
+     FROM users u
+     LEFT JOIN user_metadata m ON u.id = m.user_id
+     WHERE u.id = ?
+     ORDER BY m.id";
+
+type UserMetadataRow = (Option<i32>, Option<String>, Option<String>, Option<String>, Option<i32>);
+
+/// Split `SELECT_USER_METADATA` results into row IDs and metadata
+fn user_metadata_rows(rows: Vec<UserMetadataRow>) -> Result<(Vec<i32>, Vec<UserMetadata>), DatabaseError> {
+    if rows.is_empty() {
+        return Err(DatabaseError::UserNotFound);
+    }
+    Ok(rows
+        .into_iter()
+        .filter_map(|(id, parent_property, property, value, position)| {
+            let position = position.unwrap_or_default();
+            Some((id?, UserMetadata { parent_property, property: property?, value, position }))
+        })
+        .unzip())
+}
+
+/// Pool settings shared by every backend
+fn pool_options<DB: sqlx::Database>() -> PoolOptions<DB> {
+    PoolOptions::new()
+        .max_connections(10)
+        .min_connections(1)
+        .acquire_timeout(Duration::from_secs(30))
+}
+
+/// Check out a pooled connection, recording how long the wait took
+#[tracing::instrument(name = "db.acquire", skip_all)]
+async fn acquire<DB: sqlx::Database>(pool: &sqlx::Pool<DB>) -> Result<PoolConnection<DB>, DatabaseError> {
+    let started = Instant::now();
+    let conn = pool.acquire().await;
+    crate::metrics::metrics()
+        .db_pool_acquire_wait_seconds
+        .observe(started.elapsed().as_secs_f64());
+    conn.map_err(DatabaseError::from)
+}
+
 #[derive(Clone)]
 pub struct Database {
-    pool: Pool,
+    pool: DbPool,
+    backend: Backend,
 }
 
 impl Database {
     /// Initialize database connection pool with environment variables
+    ///
+    /// `DATABASE_URL` selects the backend; without it a MySQL URL is built from
+    /// the `DATABASE_HOST`/`PORT`/`USER`/`PASSWORD`/`NAME` variables.
     pub async fn new() -> Result<Self, DatabaseError> {
-        let database_url = std::env::var("DATABASE_URL")
-            .or_else(|_| {
-                let host = std::env::var("DATABASE_HOST").unwrap_or_else(|_| "localhost".to_string());
-                let port = std::env::var("DATABASE_PORT").unwrap_or_else(|_| "3306".to_string());
-                let user = std::env::var("DATABASE_USER").unwrap_or_else(|_| "root".to_string());
-                let password = std::env::var("DATABASE_PASSWORD").unwrap_or_else(|_| "".to_string());
-                let database = std::env::var("DATABASE_NAME").unwrap_or_else(|_| "webapp_db".to_string());
-
-                Ok(format!(
-                    "mysql://{}:{}@{}:{}/{}?charset=utf8mb4",
-                    user, password, host, port, database
-                ))
-            })
-            .map_err(|e: sqlx::Error| DatabaseError::ConnectionError(e.to_string()))?;
-
-        let pool = PoolOptions::new()
-            .max_connections(10)
-            .min_connections(1)
-            .acquire_timeout(Duration::from_secs(30))
-            .connect(&database_url)
-            .await
-            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+        let database_url = std::env::var("DATABASE_URL").unwrap_or_else(|_| {
+            let host = std::env::var("DATABASE_HOST").unwrap_or_else(|_| "localhost".to_string());
+            let port = std::env::var("DATABASE_PORT").unwrap_or_else(|_| "3306".to_string());
+            let user = std::env::var("DATABASE_USER").unwrap_or_else(|_| "root".to_string());
+            let password = std::env::var("DATABASE_PASSWORD").unwrap_or_else(|_| "".to_string());
+            let database = std::env::var("DATABASE_NAME").unwrap_or_else(|_| "webapp_db".to_string());
+
+            format!(
+                "mysql://{}:{}@{}:{}/{}?charset=utf8mb4",
+                user, password, host, port, database
+            )
+        });
 
-        Ok(Database { pool })
+        Database::connect(&database_url).await
+    }
+
+    /// Connect to the database at `url`, choosing the driver from its scheme
+    pub async fn connect(url: &str) -> Result<Self, DatabaseError> {
+        let backend = Backend::from_url(url)?;
+
+        let pool = match backend {
+            Backend::MySql => DbPool::MySql(
+                pool_options::<sqlx::MySql>()
+                    .connect(url)
+                    .await
+                    .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?,
+            ),
+            Backend::Sqlite => DbPool::Sqlite(
+                pool_options::<sqlx::Sqlite>()
+                    .connect(url)
+                    .await
+                    .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?,
+            ),
+            Backend::Postgres => DbPool::Postgres(
+                pool_options::<sqlx::Postgres>()
+                    .connect(url)
+                    .await
+                    .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?,
+            ),
+        };
+
+        Ok(Database { pool, backend })
     }
 
     /// Create a test database with in-memory SQLite
     /// Used exclusively for integration tests - does not affect production
+    ///
+    /// Set `TEST_DATABASE_URL=postgres://...` to run the suite against PostgreSQL
+    /// instead; each call then gets its own freshly created schema.
     #[cfg(test)]
     pub async fn new_test() -> Result<Self, DatabaseError> {
-        // Create in-memory SQLite database
-        let pool = PoolOptions::new()
+        let db = match std::env::var("TEST_DATABASE_URL") {
+            Ok(url) => Database::new_test_postgres(&url).await?,
+            Err(_) => {
+                // Each sqlx in-memory SQLite pool gets its own private database
+                let pool = sqlx::sqlite::SqlitePoolOptions::new()
+                    .max_connections(5)
+                    .connect("sqlite::memory:")
+                    .await
+                    .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+
+                Database {
+                    pool: DbPool::Sqlite(pool),
+                    backend: Backend::Sqlite,
+                }
+            }
+        };
+        db.run_migrations().await?;
+
+        Ok(db)
+    }
+
+    /// Wrap an existing SQLite pool, so tests can control its size and timeouts
+    #[cfg(test)]
+    pub fn from_sqlite_pool(pool: sqlx::SqlitePool) -> Self {
+        Database {
+            pool: DbPool::Sqlite(pool),
+            backend: Backend::Sqlite,
+        }
+    }
+
+    #[cfg(test)]
+    async fn new_test_postgres(url: &str) -> Result<Self, DatabaseError> {
+        use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static SCHEMAS: AtomicUsize = AtomicUsize::new(0);
+
+        if Backend::from_url(url)? != Backend::Postgres {
+            return Err(DatabaseError::ConnectionError(
+                "TEST_DATABASE_URL must be a postgres:// URL".to_string(),
+            ));
+        }
+
+        let schema = format!("test_{}_{}", std::process::id(), SCHEMAS.fetch_add(1, Ordering::Relaxed));
+
+        let mut conn = PgConnection::connect(url).await?;
+        sqlx::raw_sql(&format!("DROP SCHEMA IF EXISTS {0} CASCADE; CREATE SCHEMA {0}", schema))
+            .execute(&mut conn)
+            .await?;
+        conn.close().await?;
+
+        let options = url
+            .parse::<PgConnectOptions>()
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?
+            .options([("search_path", schema.as_str())]);
+        let pool = PgPoolOptions::new()
             .max_connections(5)
-            .connect("sqlite::memory:")
+            .connect_with(options)
             .await
             .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
 
-        // Initialize schema (running multiple statements in one query is supported by sqlx for sqlite)
-        sqlx::query(CREATE_SCHEMA_SQLITE)
-            .execute(&pool)
-            .await
-            .map_err(|e| DatabaseError::QueryError(format!("Failed to create schema: {}", e)))?;
+        Ok(Database {
+            pool: DbPool::Postgres(pool),
+            backend: Backend::Postgres,
+        })
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Apply pending embedded migrations, verifying checksums of those already applied
+    #[tracing::instrument(name = "db.run_migrations", skip_all, fields(backend = self.backend.as_str()))]
+    pub async fn run_migrations(&self) -> Result<MigrationReport, DatabaseError> {
+        self.apply_migrations(self.backend.migrations()).await
+    }
+
+    /// Apply the given migration set (ordered by version). The run holds the backend's
+    /// migration lock, so instances starting together apply each migration once.
+    pub async fn apply_migrations(&self, migrations: &[Migration]) -> Result<MigrationReport, DatabaseError> {
+        let lock = self.backend.migration_lock();
+        with_pool!(self, pool => {
+            let mut conn = acquire(pool).await?;
+            if let Some((lock, _)) = lock {
+                sqlx::raw_sql(lock).execute(&mut *conn).await?;
+            }
+
+            let result = async {
+                sqlx::raw_sql(SCHEMA_MIGRATIONS_TABLE).execute(&mut *conn).await?;
+
+                let applied: Vec<(i64, String)> =
+                    sqlx::query_as("SELECT version, checksum FROM schema_migrations ORDER BY version")
+                        .fetch_all(&mut *conn)
+                        .await?;
+
+                let mut report = MigrationReport::default();
+
+                for (version, checksum) in &applied {
+                    match migrations.iter().find(|m| m.version == *version) {
+                        Some(m) if m.checksum() != *checksum => {
+                            return Err(DatabaseError::MigrationError(format!(
+                                "checksum mismatch for migration {} ({}): it was modified after being applied",
+                                m.version, m.name
+                            )));
+                        }
+                        Some(_) => report.already_applied.push(*version),
+                        None => {
+                            return Err(DatabaseError::MigrationError(format!(
+                                "database has migration {} which this binary does not know about",
+                                version
+                            )));
+                        }
+                    }
+                }
+
+                for migration in migrations.iter().filter(|m| !report.already_applied.contains(&m.version)) {
+                    let mut tx = conn.begin().await?;
+
+                    sqlx::raw_sql(migration.sql)
+                        .execute(&mut *tx)
+                        .await
+                        .map_err(|e| {
+                            DatabaseError::MigrationError(format!(
+                                "migration {} ({}) failed: {}",
+                                migration.version, migration.name, e
+                            ))
+                        })?;
+
+                    sqlx::query(&self.backend.sql("INSERT INTO schema_migrations (version, name, checksum) VALUES (?, ?, ?)"))
+                        .bind(migration.version)
+                        .bind(migration.name)
+                        .bind(migration.checksum())
+                        .execute(&mut *tx)
+                        .await?;
+
+                    tx.commit().await?;
+                    report.applied.push(migration.version);
+                }
 
-        Ok(Database { pool })
+                Ok(report)
+            }
+            .await;
+
+            // The lock belongs to the session; close it rather than pool it if unlocking fails
+            if let Some((_, unlock)) = lock {
+                if let Err(e) = sqlx::raw_sql(unlock).execute(&mut *conn).await {
+                    let _ = conn.close().await;
+                    return Err(e.into());
+                }
+            }
+            result
+        })
+    }
+
+    /// Number of open connections and how many of them are idle
+    pub fn pool_stats(&self) -> (u32, usize) {
+        with_pool!(self, pool => (pool.size(), pool.num_idle()))
     }
 
     /// Create a new user record in the database using multiple tables and transactions
+    #[tracing::instrument(
+        name = "db.create_user",
+        skip_all,
+        fields(metadata_rows = user.metadata.len(), user_id = Empty)
+    )]
     pub async fn create_user(&self, user: &CreateUserRequest) -> Result<i32, DatabaseError> {
-        let mut tx = self.pool.begin().await.map_err(|e| DatabaseError::QueryError(e.to_string()))?;
-
-        // 1. Insert into core 'users' table
-        let result = sqlx::query(
-            "INSERT INTO users (username, password) VALUES (?, ?)"
-        )
-        .bind(&user.username)
-        .bind(&user.password)
-        .execute(&mut *tx)
-        .await?;
-
-        // Get the new user ID
-        #[cfg(not(test))]
-        let user_id = result.last_insert_id() as i64;
-        #[cfg(test)]
-        let user_id = result.last_insert_rowid();
-        let user_id = user_id as i32;
-
-        // 2. Insert into 'user_profiles' if profile data exists
-        if let Some(ref profile) = user.profile {
-            sqlx::query(
-                "INSERT INTO user_profiles (user_id, first_name, last_name, email) VALUES (?, ?, ?, ?)"
-            )
-            .bind(user_id)
-            .bind(&profile.first_name)
-            .bind(&profile.last_name)
-            .bind(&profile.email)
-            .execute(&mut *tx)
-            .await?;
-        }
+        metadata::validate(&user.metadata)?;
+
+        with_pool!(self, pool => {
+            let mut conn = acquire(pool).await?;
+            let mut tx = conn.begin().await?;
+
+            // 1. Insert into core 'users' table
+            let user_id = tx
+                .insert_user(&user.username, &user.password)
+                .instrument(tracing::info_span!("db.insert_user"))
+                .await? as i32;
+            tracing::Span::current().record("user_id", user_id);
+
+            // 2. Insert into 'user_profiles' if profile data exists
+            if let Some(ref profile) = user.profile {
+                sqlx::query(
+                    &self.backend.sql(
+                        "INSERT INTO user_profiles
+                             (user_id, first_name, middle_name, last_name, nickname, honorific, suffix, display_name, email)
+                         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                    )
+                )
+                .bind(user_id)
+                .bind(&profile.first_name)
+                .bind(&profile.middle_name)
+                .bind(&profile.last_name)
+                .bind(&profile.nickname)
+                .bind(&profile.honorific)
+                .bind(&profile.suffix)
+                .bind(&profile.display_name)
+                .bind(&profile.email)
+                .execute(&mut *tx)
+                .instrument(tracing::info_span!("db.insert_profile", user_id))
+                .await?;
+            }
 
-        // 3. Insert into 'user_metadata' for hobbies, titles, and extra metadata
-        for meta in &user.metadata {
-            sqlx::query(
-                "INSERT INTO user_metadata (user_id, parent_property, property, value) VALUES (?, ?, ?, ?)"
-            )
-            .bind(user_id)
-            .bind(&meta.parent_property)
-            .bind(&meta.property)
-            .bind(&meta.value)
-            .execute(&mut *tx)
-            .await?;
-        }
+            // 3. Insert into 'user_metadata' for hobbies, titles, and extra metadata
+            let metadata_span = tracing::info_span!("db.insert_metadata", user_id, rows = user.metadata.len());
+            for meta in &user.metadata {
+                sqlx::query(
+                    &self.backend.sql("INSERT INTO user_metadata (user_id, parent_property, property, value, position) VALUES (?, ?, ?, ?, ?)")
+                )
+                .bind(user_id)
+                .bind(&meta.parent_property)
+                .bind(&meta.property)
+                .bind(&meta.value)
+                .bind(meta.position)
+                .execute(&mut *tx)
+                .instrument(metadata_span.clone())
+                .await?;
+            }
 
-        tx.commit().await.map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+            tx.commit().instrument(tracing::info_span!("db.commit")).await?;
 
-        Ok(user_id)
+            Ok(user_id)
+        })
     }
 
     /// Find user by username (optimized for authentication)
+    #[tracing::instrument(name = "db.authenticate_user", skip_all)]
     pub async fn authenticate_user(&self, username: &str) -> Result<(i32, String), DatabaseError> {
-        let row: (i32, String) = sqlx::query_as(
-            "SELECT id, password FROM users WHERE username = ?"
-        )
-        .bind(username)
-        .fetch_one(&self.pool)
-        .await?;
+        with_pool!(self, pool => {
+            let mut conn = acquire(pool).await?;
+            let row: Option<(i32, String)> = sqlx::query_as(
+                &self.backend.sql("SELECT id, password FROM users WHERE username = ?")
+            )
+            .bind(username)
+            .fetch_optional(&mut *conn)
+            .await?;
 
-        Ok(row)
+            row.ok_or(DatabaseError::UserNotFound)
+        })
     }
 
     /// Find user by ID (aggregates profile and metadata)
+    #[tracing::instrument(name = "db.find_user_by_id", skip_all, fields(user_id = id))]
     pub async fn find_user_by_id(&self, id: i32) -> Result<User, DatabaseError> {
-        // 1. Fetch core info and profile
-        let user_row = sqlx::query(
-            "SELECT u.id, u.username, u.password, u.created_at, u.updated_at,
-                    p.first_name AS prof_first_name,
-                    p.last_name AS prof_last_name,
-                    p.email AS prof_email
-             FROM users u
-             LEFT JOIN user_profiles p ON u.id = p.user_id
-             WHERE u.id = ?"
-        )
-        .bind(id)
-        .fetch_one(&self.pool)
-        .await?;
+        self.load_users(&[id]).await?.pop().ok_or(DatabaseError::UserNotFound)
+    }
 
-        // 2. Fetch metadata
-        let metadata_rows = sqlx::query(
-            "SELECT parent_property, property, value FROM user_metadata WHERE user_id = ?"
-        )
-        .bind(id)
-        .fetch_all(&self.pool)
-        .await?;
-
-        let metadata = metadata_rows.into_iter().map(|row| {
-            UserMetadata {
-                parent_property: row.get(0),
-                property: row.get(1),
-                value: row.get(2),
+    /// Find several users with their profiles and metadata. Users come back in the order
+    /// of `ids`, once each; IDs that don't exist are skipped.
+    #[tracing::instrument(name = "db.find_users_by_ids", skip_all, fields(users = ids.len()))]
+    pub async fn find_users_by_ids(&self, ids: &[i32]) -> Result<Vec<User>, DatabaseError> {
+        self.load_users(ids).await
+    }
+
+    /// Users whose metadata satisfies `query`, in ID order. Each condition is an `EXISTS`
+    /// over `user_metadata`; the matching IDs are then loaded as a batch.
+    #[tracing::instrument(
+        name = "db.find_users_by_metadata",
+        skip_all,
+        fields(conditions = query.conditions.len(), users = Empty)
+    )]
+    pub async fn find_users_by_metadata(&self, query: &MetadataQuery) -> Result<Vec<User>, DatabaseError> {
+        query.validate()?;
+
+        let mut clauses = Vec::with_capacity(query.conditions.len());
+        let mut binds = Vec::new();
+        for condition in &query.conditions {
+            let (parent, property) = condition.target()?;
+            let (value_match, pattern) = self.backend.metadata_value_match(condition);
+            let parent_match = if parent.is_some() { "m.parent_property = ?" } else { "m.parent_property IS NULL" };
+            clauses.push(format!(
+                "EXISTS (SELECT 1 FROM user_metadata m WHERE m.user_id = u.id AND m.property = ? AND {} AND {})",
+                parent_match, value_match
+            ));
+            binds.push(property.to_string());
+            if let Some(parent) = parent {
+                binds.push(parent.to_string());
             }
-        }).collect();
-
-        // Map optional profile fields
-        let profile = if user_row.try_get::<String, _>("prof_first_name").is_ok() ||
-                         user_row.try_get::<String, _>("prof_last_name").is_ok() ||
-                         user_row.try_get::<String, _>("prof_email").is_ok() {
-            Some(UserProfile {
-                first_name: user_row.try_get("prof_first_name").ok(),
-                last_name: user_row.try_get("prof_last_name").ok(),
-                email: user_row.try_get("prof_email").ok(),
-            })
-        } else {
-            None
+            binds.push(pattern);
+        }
+        let separator = match query.combine {
+            Combine::All => " AND ",
+            Combine::Any => " OR ",
         };
+        let sql = format!(
+            "SELECT u.id FROM users u WHERE {} ORDER BY u.id LIMIT ? OFFSET ?",
+            clauses.join(separator)
+        );
+
+        let ids: Vec<i32> = with_pool!(self, pool => {
+            let mut conn = acquire(pool).await?;
+            let sql = self.backend.sql(&sql);
+            let mut select = sqlx::query_scalar(&sql);
+            for value in &binds {
+                select = select.bind(value);
+            }
+            select
+                .bind(i64::from(query.limit))
+                .bind(i64::from(query.offset))
+                .fetch_all(&mut *conn)
+                .await?
+        });
+        tracing::Span::current().record("users", ids.len());
+
+        self.find_users_by_ids(&ids).await
+    }
 
-        Ok(User {
-            id: user_row.get("id"),
-            username: user_row.get("username"),
-            password: user_row.get("password"),
-            created_at: user_row.get("created_at"),
-            updated_at: user_row.get("updated_at"),
-            profile,
-            metadata,
+    /// The user's metadata subtree rooted at `property`
+    #[tracing::instrument(name = "db.find_metadata_subtree", skip_all, fields(user_id = user_id, property = %property))]
+    pub async fn find_metadata_subtree(&self, user_id: i32, property: &str) -> Result<MetadataSubtree, DatabaseError> {
+        with_pool!(self, pool => {
+            let mut conn = acquire(pool).await?;
+            let rows: Vec<UserMetadataRow> = sqlx::query_as(&self.backend.sql(SELECT_USER_METADATA))
+                .bind(user_id)
+                .fetch_all(&mut *conn)
+                .await?;
+            let (_, rows) = user_metadata_rows(rows)?;
+
+            metadata::subtree(&rows, property)?.ok_or_else(|| DatabaseError::MetadataNotFound(property.to_string()))
         })
     }
+
+    /// Replace the user's metadata subtree rooted at `node.property` with `node`, or add
+    /// `node` as a top-level property when there is none. The resulting tree must satisfy
+    /// `schema`; returns its rows.
+    #[tracing::instrument(name = "db.replace_metadata_subtree", skip_all, fields(user_id = user_id, property = %node.property))]
+    pub async fn replace_metadata_subtree(
+        &self,
+        user_id: i32,
+        node: &MetadataNode,
+        schema: &MetadataSchema,
+    ) -> Result<Vec<UserMetadata>, DatabaseError> {
+        self.rewrite_metadata(user_id, schema, |rows| Ok(metadata::replace_subtree(rows, node)?))
+            .await
+    }
+
+    /// Delete the user's metadata subtree rooted at `property`, as long as the remaining
+    /// tree satisfies `schema`
+    #[tracing::instrument(name = "db.delete_metadata_subtree", skip_all, fields(user_id = user_id, property = %property))]
+    pub async fn delete_metadata_subtree(&self, user_id: i32, property: &str, schema: &MetadataSchema) -> Result<(), DatabaseError> {
+        self.rewrite_metadata(user_id, schema, |rows| {
+            let removed = metadata::subtree_rows(rows, property)?
+                .ok_or_else(|| DatabaseError::MetadataNotFound(property.to_string()))?;
+            Ok((removed, Vec::new()))
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Replace all of the user's metadata with `nodes`, which must satisfy `schema`
+    #[tracing::instrument(name = "db.replace_metadata", skip_all, fields(user_id = user_id))]
+    pub async fn replace_metadata(&self, user_id: i32, nodes: &[MetadataNode], schema: &MetadataSchema) -> Result<(), DatabaseError> {
+        let inserted = metadata::flatten_tree(nodes)?;
+        self.rewrite_metadata(user_id, schema, |rows| Ok(((0..rows.len()).collect(), inserted)))
+            .await
+            .map(|_| ())
+    }
+
+    /// Read the user's metadata rows, let `plan` pick the row indices to delete and the
+    /// rows to insert, check the result against `schema` and apply it, all in one
+    /// transaction. Returns the rows after the change.
+    ///
+    /// The user's row is locked first, so concurrent rewrites of the same user's metadata
+    /// each see the other's result. SQLite has no row locks; there the second writer fails
+    /// with a busy error instead of committing a tree that was checked against stale rows.
+    async fn rewrite_metadata<F>(&self, user_id: i32, schema: &MetadataSchema, plan: F) -> Result<Vec<UserMetadata>, DatabaseError>
+    where
+        F: FnOnce(&[UserMetadata]) -> Result<(Vec<usize>, Vec<UserMetadata>), DatabaseError>,
+    {
+        with_pool!(self, pool => {
+            let mut conn = acquire(pool).await?;
+            let mut tx = conn.begin().await?;
+
+            sqlx::query(&self.backend.sql(self.backend.lock_user_sql()))
+                .bind(user_id)
+                .fetch_optional(&mut *tx)
+                .await?
+                .ok_or(DatabaseError::UserNotFound)?;
+
+            let rows: Vec<UserMetadataRow> = sqlx::query_as(&self.backend.sql(SELECT_USER_METADATA))
+                .bind(user_id)
+                .fetch_all(&mut *tx)
+                .await?;
+            let (ids, rows) = user_metadata_rows(rows)?;
+            let (removed, inserted) = plan(&rows)?;
+            let result = metadata::apply(&rows, &removed, &inserted);
+            check_schema(schema, &result)?;
+
+            // Deletes go first so a re-inserted property doesn't trip the unique index
+            if !removed.is_empty() {
+                let sql = format!(
+                    "DELETE FROM user_metadata WHERE id IN ({})",
+                    vec!["?"; removed.len()].join(", ")
+                );
+                let sql = self.backend.sql(&sql);
+                let mut query = sqlx::query(&sql);
+                for index in removed {
+                    query = query.bind(ids[index]);
+                }
+                query.execute(&mut *tx).await?;
+            }
+
+            for meta in &inserted {
+                sqlx::query(
+                    &self.backend.sql("INSERT INTO user_metadata (user_id, parent_property, property, value, position) VALUES (?, ?, ?, ?, ?)")
+                )
+                .bind(user_id)
+                .bind(&meta.parent_property)
+                .bind(&meta.property)
+                .bind(&meta.value)
+                .bind(meta.position)
+                .execute(&mut *tx)
+                .await?;
+            }
+
+            tx.commit().instrument(tracing::info_span!("db.commit")).await?;
+
+            Ok(result)
+        })
+    }
+
+    /// Users, profiles and metadata in one joined query per batch of IDs. A user appears
+    /// on one row per metadata entry (or a single row without any). `prof_user_id` tells a
+    /// missing profile row apart from one whose columns are all NULL.
+    async fn load_users(&self, ids: &[i32]) -> Result<Vec<User>, DatabaseError> {
+        let mut found: HashMap<i32, User> = HashMap::with_capacity(ids.len());
+
+        with_pool!(self, pool => {
+            let mut conn = acquire(pool).await?;
+
+            for batch in ids.chunks(MAX_IDS_PER_QUERY) {
+                let sql = format!(
+                    "SELECT u.id, u.username, u.password, u.created_at, u.updated_at,
+                            p.user_id AS prof_user_id,
+                            p.first_name AS prof_first_name,
+                            p.last_name AS prof_last_name,
+                            p.email AS prof_email,
+                            p.middle_name AS prof_middle_name,
+                            p.nickname AS prof_nickname,
+                            p.honorific AS prof_honorific,
+                            p.suffix AS prof_suffix,
+                            p.display_name AS prof_display_name,
+                            m.parent_property AS meta_parent_property,
+                            m.property AS meta_property,
+                            m.value AS meta_value,
+                            m.position AS meta_position
+                     FROM users u
+                     LEFT JOIN user_profiles p ON u.id = p.user_id
+                     LEFT JOIN user_metadata m ON u.id = m.user_id
+                     WHERE u.id IN ({})
+                     ORDER BY u.id, m.id",
+                    vec!["?"; batch.len()].join(", ")
+                );
+                let sql = self.backend.sql(&sql);
+                let mut query = sqlx::query(&sql);
+                for id in batch {
+                    query = query.bind(*id);
+                }
+
+                let span = tracing::info_span!("db.select_users", users = batch.len(), rows = Empty);
+                let rows = query.fetch_all(&mut *conn).instrument(span.clone()).await?;
+                span.record("rows", rows.len());
+
+                for row in rows {
+                    let user = found.entry(row.get("id")).or_insert_with(|| User {
+                        id: row.get("id"),
+                        username: row.get("username"),
+                        password: row.get("password"),
+                        created_at: row.get("created_at"),
+                        updated_at: row.get("updated_at"),
+                        profile: row.get::<Option<i32>, _>("prof_user_id").map(|_| UserProfile {
+                            first_name: row.get("prof_first_name"),
+                            last_name: row.get("prof_last_name"),
+                            email: row.get("prof_email"),
+                            middle_name: row.get("prof_middle_name"),
+                            nickname: row.get("prof_nickname"),
+                            honorific: row.get("prof_honorific"),
+                            suffix: row.get("prof_suffix"),
+                            display_name: row.get("prof_display_name"),
+                        }),
+                        metadata: Vec::new(),
+                    });
+
+                    // `property` is NOT NULL, so a NULL here means the user has no metadata
+                    if let Some(property) = row.get::<Option<String>, _>("meta_property") {
+                        user.metadata.push(UserMetadata {
+                            parent_property: row.get("meta_parent_property"),
+                            property,
+                            value: row.get("meta_value"),
+                            position: row.get("meta_position"),
+                        });
+                    }
+                }
+            }
+
+            Ok::<_, DatabaseError>(())
+        })?;
+
+        Ok(ids.iter().filter_map(|id| found.remove(id)).collect())
+    }
 }
@@ -0,0 +1,314 @@
+use fluent_bundle::{FluentArgs, FluentValue};
+use minijinja::value::Kwargs;
+use minijinja::{context, AutoEscape, Environment, ErrorKind, State, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use unic_langid::LanguageIdentifier;
+
+use crate::db::User;
+use crate::metadata::{self, MetadataNode};
+use crate::i18n::{self, DEFAULT_LOCALE};
+use crate::user_info_formatter::{all_names, construct_name, escape, EscapeContext, NameOrder, NameStyle};
+
+/// Template used when a request doesn't pick one
+pub const DEFAULT_TEMPLATE: &str = "default";
+
+/// Extension of template files in `GREETING_TEMPLATES_DIR`; the rest of the file name is
+/// the template name
+const TEMPLATE_EXTENSION: &str = "j2";
+
+/// Titles or hobbies listed in a greeting before the rest are counted, unless
+/// `GREETING_LIST_LIMIT` says otherwise
+pub const DEFAULT_LIST_LIMIT: usize = 3;
+
+const BUILTIN_DEFAULT: &str = include_str!("../templates/greetings/default.j2");
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum GreetingTemplateError {
+    #[error("Cannot read greeting templates from {path}: {message}")]
+    Io { path: String, message: String },
+    #[error("Greeting template name '{0}' must be letters, digits, '-' or '_'")]
+    InvalidName(String),
+    #[error("Greeting template '{name}' is invalid: {message}")]
+    Invalid { name: String, message: String },
+    #[error("Unknown greeting template '{0}'")]
+    Unknown(String),
+    #[error("Greeting template '{name}' failed to render: {message}")]
+    Render { name: String, message: String },
+    #[error("GREETING_LIST_LIMIT must be a positive integer, got '{0}'")]
+    InvalidListLimit(String),
+}
+
+/// Greeting templates (minijinja syntax), optionally loaded from a directory. The
+/// built-in `default` template is always present unless the directory overrides it.
+pub struct GreetingTemplates {
+    dir: Option<PathBuf>,
+    env: RwLock<Arc<Environment<'static>>>,
+    list_limit: usize,
+}
+
+impl Default for GreetingTemplates {
+    /// Only the built-in `default` template
+    fn default() -> Self {
+        GreetingTemplates {
+            dir: None,
+            env: RwLock::new(Arc::new(builtin_environment())),
+            list_limit: DEFAULT_LIST_LIMIT,
+        }
+    }
+}
+
+impl GreetingTemplates {
+    /// Load templates from `GREETING_TEMPLATES_DIR`; only the built-in template is used
+    /// when it is unset. `GREETING_LIST_LIMIT` caps the titles and hobbies listed.
+    pub fn from_env() -> Result<Self, GreetingTemplateError> {
+        let templates = match std::env::var("GREETING_TEMPLATES_DIR") {
+            Ok(dir) if !dir.is_empty() => Self::load(dir)?,
+            _ => Self::default(),
+        };
+        match std::env::var("GREETING_LIST_LIMIT") {
+            Ok(limit) if !limit.is_empty() => match limit.parse() {
+                Ok(parsed) if parsed > 0 => Ok(templates.with_list_limit(parsed)),
+                _ => Err(GreetingTemplateError::InvalidListLimit(limit)),
+            },
+            _ => Ok(templates),
+        }
+    }
+
+    /// Load and validate every `*.j2` file in `dir`
+    pub fn load(dir: impl Into<PathBuf>) -> Result<Self, GreetingTemplateError> {
+        let dir = dir.into();
+        let env = load_environment(&dir)?;
+        Ok(GreetingTemplates {
+            dir: Some(dir),
+            env: RwLock::new(Arc::new(env)),
+            list_limit: DEFAULT_LIST_LIMIT,
+        })
+    }
+
+    /// List at most `limit` titles or hobbies in `title` and `hobby`; the rest are counted
+    pub fn with_list_limit(mut self, limit: usize) -> Self {
+        self.list_limit = limit.max(1);
+        self
+    }
+
+    /// Re-read the template directory. The new set replaces the old one only if every
+    /// template is valid; on error the templates in use are kept.
+    pub fn reload(&self) -> Result<Vec<String>, GreetingTemplateError> {
+        let env = match &self.dir {
+            Some(dir) => load_environment(dir)?,
+            None => builtin_environment(),
+        };
+        *self.env.write().unwrap() = Arc::new(env);
+        Ok(self.names())
+    }
+
+    /// Names of the available templates, sorted
+    pub fn names(&self) -> Vec<String> {
+        let env = self.env.read().unwrap().clone();
+        let mut names: Vec<String> = env.templates().map(|(name, _)| name.to_string()).collect();
+        names.sort();
+        names
+    }
+
+    /// Render the greeting template `name` for `user` in `locale`, with `name_style` for
+    /// the `name` variable
+    pub fn render(
+        &self,
+        name: &str,
+        user: &User,
+        locale: &LanguageIdentifier,
+        name_style: NameStyle,
+    ) -> Result<String, GreetingTemplateError> {
+        let env = self.env.read().unwrap().clone();
+        render(&env, name, greeting_context(user, locale, name_style, self.list_limit))
+    }
+}
+
+/// Values a greeting template can use:
+/// - `locale`: the negotiated locale, which `t()` translates into
+/// - `name`: display name from `construct_name` in `name_style` and the locale's name order
+/// - `names`: the display name in every style (`names.informal`, `names.sorted`, ...)
+/// - `username`, `first_name`, `middle_name`, `last_name`, `nickname`, `honorific`, `suffix`,
+///   `display_name`, `email`
+/// - `title`, `hobby`: every value of that metadata property in position order, joined as a
+///   list in the locale ("chess, hiking and jazz"); past `list_limit` values the rest are counted
+/// - `titles`, `hobbies`: the same values as lists
+/// - `metadata`: every metadata value keyed by its dotted path (`metadata["address.city"]`)
+///
+/// Missing values are `none`.
+pub fn greeting_context(user: &User, locale: &LanguageIdentifier, name_style: NameStyle, list_limit: usize) -> Value {
+    let profile = user.profile.clone().unwrap_or_default();
+    let order = NameOrder::for_locale(locale);
+
+    let titles = metadata::values(&user.metadata, "title");
+    let hobbies = metadata::values(&user.metadata, "hobby");
+    let list = |values: &[String]| {
+        (!values.is_empty()).then(|| i18n::catalog().format_list(locale, values, list_limit))
+    };
+
+    let mut values = BTreeMap::new();
+    collect_paths(&metadata::build_tree_lossy(&user.metadata), None, &mut values);
+    let names: BTreeMap<_, _> = all_names(user.profile.as_ref(), &user.username, order).into_iter().collect();
+
+    context! {
+        locale => locale.to_string(),
+        name => construct_name(user.profile.as_ref(), &user.username, name_style, order),
+        names,
+        username => user.username,
+        first_name => profile.first_name,
+        middle_name => profile.middle_name,
+        last_name => profile.last_name,
+        nickname => profile.nickname,
+        honorific => profile.honorific,
+        suffix => profile.suffix,
+        display_name => profile.display_name,
+        email => profile.email,
+        title => list(&titles),
+        hobby => list(&hobbies),
+        titles,
+        hobbies,
+        metadata => values,
+    }
+}
+
+fn collect_paths(nodes: &[MetadataNode], parent: Option<&str>, values: &mut BTreeMap<String, String>) {
+    for node in nodes {
+        let path = match parent {
+            Some(parent) => format!("{}.{}", parent, node.property),
+            None => node.property.clone(),
+        };
+        if let Some(value) = &node.value {
+            values.insert(path.clone(), value.clone());
+        }
+        collect_paths(&node.children, Some(&path), values);
+    }
+}
+
+fn render(env: &Environment<'static>, name: &str, ctx: Value) -> Result<String, GreetingTemplateError> {
+    let template = env
+        .get_template(name)
+        .map_err(|_| GreetingTemplateError::Unknown(name.to_string()))?;
+    template.render(ctx).map_err(|e| GreetingTemplateError::Render {
+        name: name.to_string(),
+        message: e.to_string(),
+    })
+}
+
+/// `t(id, **args)`: message `id` from the catalog of the context's `locale`
+fn translate(state: &State, id: &str, kwargs: Kwargs) -> Result<String, minijinja::Error> {
+    let locale = state
+        .lookup("locale")
+        .and_then(|locale| locale.as_str().and_then(|l| l.parse().ok()))
+        .unwrap_or_else(|| DEFAULT_LOCALE.parse().expect("default locale is valid"));
+
+    let mut args = FluentArgs::new();
+    for key in kwargs.args() {
+        let value: Value = kwargs.get(key)?;
+        if !value.is_none() {
+            args.set(key.to_string(), FluentValue::from(value.to_string()));
+        }
+    }
+
+    i18n::catalog().format(&locale, id, &args).ok_or_else(|| {
+        minijinja::Error::new(
+            ErrorKind::InvalidOperation,
+            format!("message '{}' is missing or lacks an argument", id),
+        )
+    })
+}
+
+/// `value | escape_for("html")`: `value` escaped for a `text`, `html`, `attr` or `json` context
+fn escape_for(value: Value, context: &str) -> Result<String, minijinja::Error> {
+    let context: EscapeContext = context
+        .parse()
+        .map_err(|message: String| minijinja::Error::new(ErrorKind::InvalidOperation, message))?;
+    if value.is_none() || value.is_undefined() {
+        return Ok(String::new());
+    }
+    Ok(escape(&value.to_string(), context))
+}
+
+fn builtin_environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    // Greetings are plain text whatever the template is called; `escape_for` escapes explicitly
+    env.set_auto_escape_callback(|_| AutoEscape::None);
+    env.add_function("t", translate);
+    env.add_filter("escape_for", escape_for);
+    env.add_template(DEFAULT_TEMPLATE, BUILTIN_DEFAULT)
+        .expect("built-in greeting template is valid");
+    env
+}
+
+/// The built-in templates overlaid with the directory's, each checked by compiling it
+/// and rendering it in every locale for a user with every field set and for one with none
+fn load_environment(dir: &Path) -> Result<Environment<'static>, GreetingTemplateError> {
+    let io_error = |e: std::io::Error| GreetingTemplateError::Io {
+        path: dir.display().to_string(),
+        message: e.to_string(),
+    };
+
+    let mut env = builtin_environment();
+    for entry in std::fs::read_dir(dir).map_err(io_error)? {
+        let path = entry.map_err(io_error)?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(TEMPLATE_EXTENSION) || !path.is_file() {
+            continue;
+        }
+        let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(GreetingTemplateError::InvalidName(name));
+        }
+        let source = std::fs::read_to_string(&path).map_err(io_error)?;
+        env.add_template_owned(name.clone(), source)
+            .map_err(|e| GreetingTemplateError::Invalid { name, message: e.to_string() })?;
+    }
+
+    // Unknown filters and functions only fail at render time
+    let names: Vec<String> = env.templates().map(|(name, _)| name.to_string()).collect();
+    for name in &names {
+        for locale in i18n::catalog().locales() {
+            for user in [sample_user(true), sample_user(false)] {
+                let ctx = greeting_context(&user, locale, NameStyle::default(), DEFAULT_LIST_LIMIT);
+                render(&env, name, ctx).map_err(|e| match e {
+                    GreetingTemplateError::Render { name, message } => GreetingTemplateError::Invalid { name, message },
+                    other => other,
+                })?;
+            }
+        }
+    }
+
+    Ok(env)
+}
+
+fn sample_user(complete: bool) -> User {
+    let now = chrono::Utc::now().naive_utc();
+    let text = |s: &str| complete.then(|| s.to_string());
+    User {
+        id: 1,
+        username: "sample".to_string(),
+        password: String::new(),
+        created_at: now,
+        updated_at: now,
+        profile: complete.then(|| crate::db::UserProfile {
+            first_name: text("Ada"),
+            last_name: text("Lovelace"),
+            email: text("ada@example.com"),
+            middle_name: text("Augusta"),
+            nickname: text("Ada"),
+            honorific: text("Countess"),
+            suffix: text("FRS"),
+            display_name: text("Ada Lovelace"),
+        }),
+        metadata: [("title", 0), ("hobby", 0), ("hobby", 1)]
+            .into_iter()
+            .filter(|_| complete)
+            .map(|(property, position)| crate::db::UserMetadata {
+                parent_property: None,
+                property: property.to_string(),
+                value: Some("sample".to_string()),
+                position,
+            })
+            .collect(),
+    }
+}
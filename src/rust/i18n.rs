@@ -0,0 +1,126 @@
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource};
+use fluent_langneg::{negotiate_languages, NegotiationStrategy};
+use std::sync::OnceLock;
+use unic_langid::LanguageIdentifier;
+
+use crate::db::User;
+
+/// Locale used when nothing the client or user asks for is available
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// Message catalogs compiled into the binary, one Fluent file per locale
+pub const CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en/greeting.ftl")),
+    ("de", include_str!("../locales/de/greeting.ftl")),
+    ("ja", include_str!("../locales/ja/greeting.ftl")),
+];
+
+/// Fluent bundles for every shipped locale
+pub struct Catalog {
+    bundles: Vec<(LanguageIdentifier, FluentBundle<FluentResource>)>,
+}
+
+/// The shipped catalogs, parsed on first use
+pub fn catalog() -> &'static Catalog {
+    static CATALOG: OnceLock<Catalog> = OnceLock::new();
+    CATALOG.get_or_init(|| {
+        let bundles = CATALOGS
+            .iter()
+            .map(|(locale, source)| {
+                let locale: LanguageIdentifier = locale.parse().expect("catalog locale is valid");
+                let resource = FluentResource::try_new(source.to_string()).expect("catalog parses");
+                let mut bundle = FluentBundle::new_concurrent(vec![locale.clone()]);
+                // Isolation marks around arguments would end up in plain-text greetings
+                bundle.set_use_isolating(false);
+                bundle.add_resource(resource).expect("catalog has no duplicate messages");
+                (locale, bundle)
+            })
+            .collect();
+        Catalog { bundles }
+    })
+}
+
+impl Catalog {
+    /// Locales with a catalog, in the order they were added
+    pub fn locales(&self) -> Vec<&LanguageIdentifier> {
+        self.bundles.iter().map(|(locale, _)| locale).collect()
+    }
+
+    /// Best available locale for `requested`, most preferred first
+    pub fn negotiate(&self, requested: &[LanguageIdentifier]) -> LanguageIdentifier {
+        let available: Vec<LanguageIdentifier> = self.locales().into_iter().cloned().collect();
+        let default: LanguageIdentifier = DEFAULT_LOCALE.parse().expect("default locale is valid");
+        negotiate_languages(requested, &available, Some(&default), NegotiationStrategy::Filtering)
+            .first()
+            .map(|locale| (*locale).clone())
+            .unwrap_or(default)
+    }
+
+    /// Format message `id` in `locale`, falling back to the default locale when that
+    /// catalog lacks it. `None` when no catalog has the message or an argument is missing.
+    pub fn format(&self, locale: &LanguageIdentifier, id: &str, args: &FluentArgs) -> Option<String> {
+        let default: LanguageIdentifier = DEFAULT_LOCALE.parse().ok()?;
+        let formatted = [locale, &default].into_iter().find_map(|locale| {
+            let bundle = self.bundle(locale)?;
+            let pattern = bundle.get_message(id)?.value()?;
+            let mut errors = Vec::new();
+            let text = bundle.format_pattern(pattern, Some(args), &mut errors);
+            Some(errors.is_empty().then(|| text.into_owned()))
+        });
+        formatted.flatten()
+    }
+
+    /// `items` joined as a list in `locale` ("chess, hiking and jazz"). Items past `limit`
+    /// are counted instead of listed ("chess, hiking and 2 more").
+    pub fn format_list(&self, locale: &LanguageIdentifier, items: &[String], limit: usize) -> String {
+        let mut shown: Vec<String> = items.iter().take(limit).cloned().collect();
+        if items.len() > limit {
+            let mut args = FluentArgs::new();
+            args.set("count", items.len() - limit);
+            shown.push(self.format(locale, "list-more", &args).unwrap_or_else(|| (items.len() - limit).to_string()));
+        }
+
+        let join = |id: &str, first: &str, key: &str, second: &str| {
+            let mut args = FluentArgs::new();
+            args.set("first", first.to_string());
+            args.set(key.to_string(), second.to_string());
+            self.format(locale, id, &args).unwrap_or_else(|| format!("{}, {}", first, second))
+        };
+        match shown.as_slice() {
+            [] => String::new(),
+            [only] => only.clone(),
+            [first, second] => join("list-two", first, "second", second),
+            [init @ .., before_last, last] => {
+                // Join the last two, then prepend the others from the right
+                let tail = join("list-end", before_last, "last", last);
+                init.iter().rev().fold(tail, |rest, item| join("list-middle", item, "rest", &rest))
+            }
+        }
+    }
+
+    fn bundle(&self, locale: &LanguageIdentifier) -> Option<&FluentBundle<FluentResource>> {
+        self.bundles.iter().find(|(l, _)| l == locale).map(|(_, bundle)| bundle)
+    }
+}
+
+/// Locale to greet `user` in: their `locale` metadata property when the catalog has it,
+/// otherwise the best match for the `Accept-Language` header
+pub fn negotiate_locale(user: &User, accept_language: Option<&str>) -> LanguageIdentifier {
+    let catalog = catalog();
+    let preferred = user
+        .metadata
+        .iter()
+        .find(|m| m.parent_property.is_none() && m.property == "locale")
+        .and_then(|m| m.value.as_deref())
+        .and_then(|value| value.parse::<LanguageIdentifier>().ok());
+    if let Some(preferred) = preferred {
+        let matched = catalog.negotiate(std::slice::from_ref(&preferred));
+        if matched.language == preferred.language {
+            return matched;
+        }
+    }
+
+    let requested = accept_language.map(fluent_langneg::accepted_languages::parse).unwrap_or_default();
+    catalog.negotiate(&requested)
+}
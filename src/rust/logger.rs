@@ -1,4 +1,11 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{OnceLock, RwLock};
+
 #[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", try_from = "String")]
 pub enum LogLevel {
     Debug,
     Info,
@@ -15,6 +22,159 @@ impl LogLevel {
             LogLevel::Error => "error",
         }
     }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "debug" => Some(LogLevel::Debug),
+            "info" => Some(LogLevel::Info),
+            "warn" | "warning" => Some(LogLevel::Warn),
+            "error" => Some(LogLevel::Error),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<String> for LogLevel {
+    type Error = String;
+
+    /// Accepts the same spellings as the environment variables
+    fn try_from(value: String) -> Result<Self, String> {
+        LogLevel::parse(&value)
+            .ok_or_else(|| format!("unknown log level '{}', expected debug, info, warn or error", value))
+    }
+}
+
+/// Filtering applied to events forwarded to `LOGGER_URL`, independent of `RUST_LOG`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteLogConfig {
+    /// Minimum level forwarded when no per-app override matches
+    pub min_level: LogLevel,
+    /// Per-app (function name) minimum levels
+    #[serde(default)]
+    pub app_levels: HashMap<String, LogLevel>,
+    /// Fraction of info events forwarded (0.0 - 1.0)
+    #[serde(default = "default_sample_rate")]
+    pub info_sample_rate: f64,
+}
+
+fn default_sample_rate() -> f64 {
+    1.0
+}
+
+impl Default for RemoteLogConfig {
+    fn default() -> Self {
+        RemoteLogConfig {
+            min_level: LogLevel::Debug,
+            app_levels: HashMap::new(),
+            info_sample_rate: default_sample_rate(),
+        }
+    }
+}
+
+impl RemoteLogConfig {
+    /// Load from `LOGGER_REMOTE_LEVEL`, `LOGGER_REMOTE_APP_LEVELS` (e.g. `login_user=warn,main=info`)
+    /// and `LOGGER_INFO_SAMPLE_RATE`; defaults forward everything. An unknown level, a malformed
+    /// `app=level` pair or a sample rate that is not a number between 0.0 and 1.0 is an error.
+    pub fn from_env() -> Result<Self, String> {
+        let mut config = RemoteLogConfig::default();
+
+        if let Ok(value) = std::env::var("LOGGER_REMOTE_LEVEL") {
+            config.min_level = LogLevel::try_from(value).map_err(|e| format!("LOGGER_REMOTE_LEVEL: {}", e))?;
+        }
+
+        if let Ok(value) = std::env::var("LOGGER_REMOTE_APP_LEVELS") {
+            config.app_levels = parse_app_levels(&value).map_err(|e| format!("LOGGER_REMOTE_APP_LEVELS: {}", e))?;
+        }
+
+        if let Ok(value) = std::env::var("LOGGER_INFO_SAMPLE_RATE") {
+            config.info_sample_rate = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("LOGGER_INFO_SAMPLE_RATE must be a number, got '{}'", value))?;
+        }
+
+        config.validate().map_err(|e| format!("LOGGER_INFO_SAMPLE_RATE: {}", e))?;
+        Ok(config)
+    }
+
+    /// Reject configurations that cannot be applied
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.info_sample_rate) {
+            return Err("info_sample_rate must be between 0.0 and 1.0".to_string());
+        }
+        if self.app_levels.keys().any(|app| app.trim().is_empty()) {
+            return Err("app_levels keys must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    /// Effective minimum level for the given app
+    pub fn level_for(&self, app: &str) -> LogLevel {
+        self.app_levels.get(app).copied().unwrap_or(self.min_level)
+    }
+}
+
+/// Parse `app=level` pairs separated by commas. Blank entries are skipped; a pair without
+/// `=`, with an empty app name or with an unknown level is an error.
+pub fn parse_app_levels(value: &str) -> Result<HashMap<String, LogLevel>, String> {
+    value
+        .split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            let (app, level) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected app=level, got '{}'", pair.trim()))?;
+            let app = app.trim();
+            if app.is_empty() {
+                return Err(format!("missing app name in '{}'", pair.trim()));
+            }
+            let level = LogLevel::parse(level)
+                .ok_or_else(|| format!("unknown log level '{}' for {}", level.trim(), app))?;
+            Ok((app.to_string(), level))
+        })
+        .collect()
+}
+
+static REMOTE_CONFIG: OnceLock<RwLock<RemoteLogConfig>> = OnceLock::new();
+static INFO_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+/// The configuration, initialized from the environment on first use. An invalid environment
+/// falls back to the defaults here; `main` refuses to start with one.
+fn remote_config_lock() -> &'static RwLock<RemoteLogConfig> {
+    REMOTE_CONFIG.get_or_init(|| RwLock::new(RemoteLogConfig::from_env().unwrap_or_default()))
+}
+
+/// Snapshot of the current remote log configuration
+pub fn remote_config() -> RemoteLogConfig {
+    remote_config_lock().read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Replace the remote log configuration at runtime
+pub fn set_remote_config(config: RemoteLogConfig) -> Result<(), String> {
+    config.validate()?;
+    *remote_config_lock().write().unwrap_or_else(|e| e.into_inner()) = config;
+    Ok(())
+}
+
+/// Decide whether an event is forwarded to the remote logger
+pub fn should_forward(level: LogLevel, app: &str) -> bool {
+    let config = remote_config_lock().read().unwrap_or_else(|e| e.into_inner());
+
+    if level < config.level_for(app) {
+        return false;
+    }
+
+    if level == LogLevel::Info {
+        return sample_info(INFO_EVENTS.fetch_add(1, Ordering::Relaxed), config.info_sample_rate);
+    }
+
+    true
+}
+
+/// Whether the `n`th info event (counting from 0) passes the sampler. Deterministic: an
+/// event is forwarded whenever the running count crosses the next whole multiple of 1 / rate.
+pub fn sample_info(n: u64, rate: f64) -> bool {
+    ((n + 1) as f64 * rate).floor() > (n as f64 * rate).floor()
 }
 
 pub fn dual_log(
@@ -24,6 +184,11 @@ pub fn dual_log(
     user: Option<String>,
     message: String,
 ) {
+    // 0. Scrub sensitive data before it reaches any output
+    let redactor = crate::redaction::redactor();
+    let message = redactor.redact(&message);
+    let user = user.map(|u| redactor.redact(&u));
+
     // 1. Local logging with function name prefix
     let formatted = format!("[{}] {}", app, message);
     match level {
@@ -35,6 +200,10 @@ pub fn dual_log(
 
     // 2. Remote logging (fire and forget)
     if let Ok(logger_url) = std::env::var("LOGGER_URL") {
+        if !should_forward(level, app) {
+            return;
+        }
+
         let payload = serde_json::json!({
             "timestamp": chrono::Utc::now().to_rfc3339(),
             "level": level.as_str(),
@@ -43,13 +212,20 @@ pub fn dual_log(
             "app": app,
         });
 
+        let metrics = crate::metrics::metrics();
+        metrics.remote_log_in_flight.inc();
+
         tokio::spawn(async move {
-            let _ = client
+            let result = client
                 .post(format!("{}/logs", logger_url))
                 .json(&payload)
                 .send()
                 .await;
-            // Ignore result - fire and forget
+            // Fire and forget - failures are only counted
+            if !matches!(result, Ok(ref resp) if resp.status().is_success()) {
+                metrics.remote_log_dropped_total.inc();
+            }
+            metrics.remote_log_in_flight.dec();
         });
     }
 }
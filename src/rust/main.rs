@@ -1,14 +1,52 @@
+mod access_log;
+mod cors;
+mod csrf;
 mod db;
 mod user_info_formatter;
+mod greeting_templates;
+mod i18n;
 mod logger;
+mod metadata;
+mod metadata_schema;
+mod metadata_search;
+mod metrics;
+mod migrations;
+mod pages;
+mod repository;
+mod telemetry;
+mod redaction;
+mod sanitize;
+mod session;
+mod web_ui;
 
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
-use actix_cors::Cors;
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
 use serde::{Deserialize, Serialize};
-use crate::user_info_formatter::format_user_greeting;
+use std::future::Future;
+use std::pin::Pin;
+use subtle::ConstantTimeEq;
+use crate::access_log::{AccessLog, AccessLogFormat, TrustedProxies};
+use crate::cors::CorsConfig;
+use crate::csrf::CsrfProtection;
+use crate::metadata::MetadataNode;
+use crate::metadata_schema::{MetadataSchema, TypedMetadataNode};
+use crate::metadata_search::MetadataQuery;
+use crate::metrics::{metrics, RequestMetrics};
+use crate::pages::{LoginForm, LogoutForm, Pages, SignupForm};
+use crate::repository::UserRepository;
+use crate::sanitize::UnsafeTextError;
+use crate::session::SessionKey;
+use crate::telemetry::{RequestTracing, Telemetry};
+use crate::web_ui::WebUi;
+use crate::greeting_templates::{GreetingTemplateError, GreetingTemplates, DEFAULT_TEMPLATE};
+use crate::user_info_formatter::{
+    construct_name, escape, profile_fields, render_html, render_markdown, render_vcard, EscapeContext, FormatError,
+    NameOrder, NameStyle, UserInfoFormat,
+};
+use unic_langid::LanguageIdentifier;
 
 // Re-export database types
-use db::{Database, CreateUserRequest, User, DatabaseError, UserProfile, UserMetadata};
+use db::{Database, CreateUserRequest, User, DatabaseError, UserProfile};
 
 // ============ Request/Response Structs ============
 
@@ -17,11 +55,187 @@ pub struct CreateUserPayload {
     pub username: String,
     pub password: String,
     pub first_name: Option<String>,
+    pub middle_name: Option<String>,
     pub last_name: Option<String>,
+    pub nickname: Option<String>,
+    pub honorific: Option<String>,
+    pub suffix: Option<String>,
+    pub display_name: Option<String>,
     pub email: Option<String>,
-    pub title: Option<String>,
-    pub hobby: Option<String>,
-    pub extra_metadata: Option<Vec<UserMetadata>>,
+    /// One title, or several in a JSON array or repeated form keys
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub title: Vec<String>,
+    /// One hobby, or several in a JSON array or repeated form keys
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub hobby: Vec<String>,
+    /// Nested metadata; only JSON request bodies can carry it
+    pub extra_metadata: Option<Vec<MetadataNode>>,
+}
+
+impl CreateUserPayload {
+    /// The payload with its text fields normalized; control characters and bidi
+    /// controls are rejected
+    pub fn cleaned(mut self) -> Result<Self, UnsafeTextError> {
+        self.username = sanitize::clean_field("username", &self.username)?;
+        self.first_name = sanitize::clean_optional("first_name", self.first_name)?;
+        self.middle_name = sanitize::clean_optional("middle_name", self.middle_name)?;
+        self.last_name = sanitize::clean_optional("last_name", self.last_name)?;
+        self.nickname = sanitize::clean_optional("nickname", self.nickname)?;
+        self.honorific = sanitize::clean_optional("honorific", self.honorific)?;
+        self.suffix = sanitize::clean_optional("suffix", self.suffix)?;
+        self.display_name = sanitize::clean_optional("display_name", self.display_name)?;
+        self.email = sanitize::clean_optional("email", self.email)?;
+        self.title = self.title.iter().map(|v| sanitize::clean_field("title", v)).collect::<Result<_, _>>()?;
+        self.hobby = self.hobby.iter().map(|v| sanitize::clean_field("hobby", v)).collect::<Result<_, _>>()?;
+        if let Some(extra) = &mut self.extra_metadata {
+            sanitize::clean_metadata(extra, None)?;
+        }
+        Ok(self)
+    }
+
+    /// Check a `cleaned` payload's lengths and metadata against `schema` and build the
+    /// repository request
+    pub fn to_request(&self, schema: &MetadataSchema) -> Result<CreateUserRequest, InvalidUser> {
+        if self.username.is_empty() || self.username.len() > 16 {
+            return Err(InvalidUser::field("username", "Username is required and must be max 16 characters"));
+        }
+
+        if self.password.is_empty() || self.password.len() > 255 {
+            return Err(InvalidUser::field("password", "Password is required and must be max 255 characters"));
+        }
+
+        // Validate optional fields length
+        let optional_fields = [
+            ("first_name", &self.first_name),
+            ("middle_name", &self.middle_name),
+            ("last_name", &self.last_name),
+            ("nickname", &self.nickname),
+            ("honorific", &self.honorific),
+            ("suffix", &self.suffix),
+            ("display_name", &self.display_name),
+            ("email", &self.email),
+        ];
+        let list_fields = self
+            .title
+            .iter()
+            .map(|title| ("title", title))
+            .chain(self.hobby.iter().map(|hobby| ("hobby", hobby)));
+        let values = optional_fields
+            .into_iter()
+            .filter_map(|(field, value)| value.as_ref().map(|value| (field, value)))
+            .chain(list_fields);
+        for (field, value) in values {
+            if value.len() > 255 {
+                return Err(InvalidUser::field(field, format!("{} must be max 255 characters", field)));
+            }
+        }
+
+        let shortcut = |property: &str, value: &String| MetadataNode {
+            property: property.to_string(),
+            value: Some(value.clone()),
+            children: Vec::new(),
+        };
+        let mut nodes: Vec<MetadataNode> = self.title.iter().map(|title| shortcut("title", title)).collect();
+        nodes.extend(self.hobby.iter().map(|hobby| shortcut("hobby", hobby)));
+        if let Some(ref extra) = self.extra_metadata {
+            nodes.extend(extra.iter().cloned());
+        }
+
+        let metadata = metadata::flatten_tree(&nodes).map_err(InvalidUser::metadata)?;
+        schema.validate(&nodes).map_err(InvalidUser::metadata)?;
+
+        Ok(CreateUserRequest {
+            username: self.username.clone(),
+            password: self.password.clone(),
+            profile: Some(UserProfile {
+                first_name: self.first_name.clone(),
+                last_name: self.last_name.clone(),
+                email: self.email.clone(),
+                middle_name: self.middle_name.clone(),
+                nickname: self.nickname.clone(),
+                honorific: self.honorific.clone(),
+                suffix: self.suffix.clone(),
+                display_name: self.display_name.clone(),
+            }),
+            metadata,
+        })
+    }
+}
+
+/// Why a new user was rejected; `field` names the offending field when there is one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidUser {
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl InvalidUser {
+    fn field(field: &str, message: impl Into<String>) -> Self {
+        InvalidUser { field: Some(field.to_string()), message: message.into() }
+    }
+
+    fn metadata(e: impl std::fmt::Display) -> Self {
+        InvalidUser { field: None, message: e.to_string() }
+    }
+
+    /// 400 VALIDATION_ERROR with the message
+    fn response(&self) -> HttpResponse {
+        HttpResponse::BadRequest().json(ErrorResponse {
+            error: "VALIDATION_ERROR".to_string(),
+            message: self.message.clone(),
+        })
+    }
+}
+
+impl From<UnsafeTextError> for InvalidUser {
+    fn from(e: UnsafeTextError) -> Self {
+        let field = match &e {
+            UnsafeTextError::Control { field, .. } | UnsafeTextError::Bidi { field, .. } => field.clone(),
+        };
+        InvalidUser { field: Some(field), message: e.to_string() }
+    }
+}
+
+/// Accept a single string or an array of strings
+fn one_or_many<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+/// Urlencoded `CreateUserPayload`. A repeated key (`hobby=chess&hobby=golf`) becomes a
+/// list, which `title` and `hobby` accept like a JSON array.
+pub struct CreateUserForm(pub CreateUserPayload);
+
+impl actix_web::FromRequest for CreateUserForm {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let pairs = web::Form::<Vec<(String, String)>>::from_request(req, payload);
+        Box::pin(async move {
+            let mut fields = serde_json::Map::new();
+            for (key, value) in pairs.await?.into_inner() {
+                match fields.get_mut(&key) {
+                    None => {
+                        fields.insert(key, value.into());
+                    }
+                    Some(serde_json::Value::Array(values)) => values.push(value.into()),
+                    Some(first) => *first = serde_json::Value::Array(vec![first.take(), value.into()]),
+                }
+            }
+            serde_json::from_value(fields.into())
+                .map(CreateUserForm)
+                .map_err(|e| actix_web::error::UrlencodedError::Parse(serde::de::Error::custom(e)).into())
+        })
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -44,42 +258,67 @@ pub struct LoginResponse {
 pub struct UserInfoResponse {
     pub id: i32,
     pub username: String,
+    /// Display name in the configured `NameStyle`
+    pub name: String,
     pub first_name: Option<String>,
+    pub middle_name: Option<String>,
     pub last_name: Option<String>,
+    pub nickname: Option<String>,
+    pub honorific: Option<String>,
+    pub suffix: Option<String>,
+    pub display_name: Option<String>,
     pub email: Option<String>,
+    /// The first of `titles`
     pub title: Option<String>,
+    /// The first of `hobbies`
     pub hobby: Option<String>,
-    pub metadata: Vec<UserMetadata>,
+    /// Every `title` value in position order
+    pub titles: Vec<String>,
+    /// Every `hobby` value in position order
+    pub hobbies: Vec<String>,
+    pub metadata: Vec<TypedMetadataNode>,
 }
 
-impl From<User> for UserInfoResponse {
-    fn from(user: User) -> Self {
-        let (first_name, last_name, email) = user.profile.as_ref().map(|p| (p.first_name.clone(), p.last_name.clone(), p.email.clone())).unwrap_or((None, None, None));
-
-        let mut title = None;
-        let mut hobby = None;
+impl UserInfoResponse {
+    /// Response for `user`, with metadata values typed by `schema` and the display name
+    /// built in `style` and `order`
+    pub fn from_user(user: User, schema: &MetadataSchema, style: NameStyle, order: NameOrder) -> Self {
+        let name = construct_name(user.profile.as_ref(), &user.username, style, order);
+        let profile = user.profile.clone().unwrap_or_default();
 
-        for m in &user.metadata {
-            if m.property == "title" {
-                title = m.value.clone();
-            } else if m.property == "hobby" {
-                hobby = m.value.clone();
-            }
-        }
+        let titles = metadata::values(&user.metadata, "title");
+        let hobbies = metadata::values(&user.metadata, "hobby");
 
         UserInfoResponse {
             id: user.id,
             username: user.username,
-            first_name,
-            last_name,
-            email,
-            title,
-            hobby,
-            metadata: user.metadata,
+            name,
+            first_name: profile.first_name,
+            middle_name: profile.middle_name,
+            last_name: profile.last_name,
+            nickname: profile.nickname,
+            honorific: profile.honorific,
+            suffix: profile.suffix,
+            display_name: profile.display_name,
+            email: profile.email,
+            title: titles.first().cloned(),
+            hobby: hobbies.first().cloned(),
+            titles,
+            hobbies,
+            metadata: schema.typed_tree(&metadata::build_tree_lossy(&user.metadata)),
         }
     }
 }
 
+/// Body of `PUT /api/users/{user_id}/metadata/{property}`; the property comes from the path
+#[derive(Debug, Deserialize)]
+pub struct MetadataSubtreePayload {
+    #[serde(default, deserialize_with = "metadata::scalar_as_text")]
+    pub value: Option<String>,
+    #[serde(default)]
+    pub children: Vec<MetadataNode>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
     pub error: String,
@@ -89,142 +328,140 @@ pub struct ErrorResponse {
 // ============ Application State ============
 
 struct AppState {
-    db: Database,
+    users: std::sync::Arc<dyn UserRepository>,
     http_client: reqwest::Client,
+    metadata_schema: MetadataSchema,
+    greeting_templates: GreetingTemplates,
+    name_style: NameStyle,
+    web_ui: WebUi,
+    pages: Pages,
+    sessions: SessionKey,
 }
 
-// ============ Endpoint Handlers ============
-
-/// POST /api/users - Create a new user
-async fn create_user(
-    state: web::Data<AppState>,
-    payload: web::Form<CreateUserPayload>,
-) -> impl Responder {
-    log_info!(state.http_client, "create_user", payload.username, "Creating new user");
+/// 400 response for metadata that is not a valid tree or breaks the schema
+fn invalid_metadata_response(message: impl std::fmt::Display) -> HttpResponse {
+    HttpResponse::BadRequest().json(ErrorResponse {
+        error: "VALIDATION_ERROR".to_string(),
+        message: message.to_string(),
+    })
+}
 
-    // Validate required fields
-    if payload.username.is_empty() || payload.username.len() > 16 {
-        return HttpResponse::BadRequest().json(ErrorResponse {
-            error: "VALIDATION_ERROR".to_string(),
-            message: "Username is required and must be max 16 characters".to_string(),
-        });
+/// Status, error code and message for store failures that mean the same on every
+/// endpoint; `fallback` is the message for unexpected errors
+fn database_error_parts(e: &DatabaseError, fallback: &'static str) -> (StatusCode, &'static str, &'static str) {
+    match e {
+        DatabaseError::ConnectionError(_) => (StatusCode::SERVICE_UNAVAILABLE, "DATABASE_UNAVAILABLE", "Database connection failed"),
+        DatabaseError::PoolTimeout => (StatusCode::SERVICE_UNAVAILABLE, "DATABASE_BUSY", "Database is busy, please retry"),
+        DatabaseError::SerializationFailure => (StatusCode::SERVICE_UNAVAILABLE, "TRANSACTION_CONFLICT", "Concurrent update conflict, please retry"),
+        DatabaseError::DuplicateUsername => (StatusCode::CONFLICT, "DUPLICATE_USERNAME", "Username already exists"),
+        DatabaseError::DuplicateEmail => (StatusCode::CONFLICT, "DUPLICATE_EMAIL", "Email address is already registered"),
+        DatabaseError::ForeignKeyViolation(_) | DatabaseError::ConstraintViolation(_) => {
+            (StatusCode::CONFLICT, "CONSTRAINT_VIOLATION", "Request conflicts with existing data")
+        }
+        DatabaseError::UserNotFound => (StatusCode::NOT_FOUND, "USER_NOT_FOUND", "User not found"),
+        DatabaseError::MetadataNotFound(_) => (StatusCode::NOT_FOUND, "METADATA_NOT_FOUND", "Metadata property not found"),
+        DatabaseError::InvalidMetadata(_) | DatabaseError::SchemaViolation(_) => {
+            (StatusCode::BAD_REQUEST, "VALIDATION_ERROR", "Invalid metadata")
+        }
+        DatabaseError::InvalidSearch(_) => (StatusCode::BAD_REQUEST, "VALIDATION_ERROR", "Invalid search"),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR", fallback),
     }
+}
 
-    if payload.password.is_empty() || payload.password.len() > 255 {
-        return HttpResponse::BadRequest().json(ErrorResponse {
-            error: "VALIDATION_ERROR".to_string(),
-            message: "Password is required and must be max 255 characters".to_string(),
-        });
+/// Start a response for a store failure, asking clients to back off when a retry may succeed
+fn database_error_builder(e: &DatabaseError, status: StatusCode) -> actix_web::HttpResponseBuilder {
+    let mut builder = HttpResponse::build(status);
+    if e.is_retryable() {
+        builder.insert_header((header::RETRY_AFTER, "1"));
     }
+    builder
+}
 
-    // Validate optional fields length
-    if let Some(ref first_name) = payload.first_name {
-        if first_name.len() > 255 {
-            return HttpResponse::BadRequest().json(ErrorResponse {
-                error: "VALIDATION_ERROR".to_string(),
-                message: "first_name must be max 255 characters".to_string(),
-            });
-        }
-    }
+/// JSON error response for a store failure
+fn database_error_response(e: &DatabaseError, fallback: &'static str) -> HttpResponse {
+    let (status, error, message) = database_error_parts(e, fallback);
+    database_error_builder(e, status).json(ErrorResponse {
+        error: error.to_string(),
+        message: message.to_string(),
+    })
+}
 
-    if let Some(ref last_name) = payload.last_name {
-        if last_name.len() > 255 {
-            return HttpResponse::BadRequest().json(ErrorResponse {
-                error: "VALIDATION_ERROR".to_string(),
-                message: "last_name must be max 255 characters".to_string(),
-            });
-        }
-    }
+/// Parse a `{user_id}` path segment, which must be a positive integer
+fn parse_user_id(raw: &str) -> Result<i32, HttpResponse> {
+    let message = match raw.parse::<i32>() {
+        Ok(user_id) if user_id > 0 => return Ok(user_id),
+        Ok(_) => "user_id must be a positive integer",
+        Err(_) => "user_id must be a valid integer",
+    };
+    Err(HttpResponse::BadRequest().json(ErrorResponse {
+        error: "VALIDATION_ERROR".to_string(),
+        message: message.to_string(),
+    }))
+}
 
-    if let Some(ref email) = payload.email {
-        if email.len() > 255 {
-            return HttpResponse::BadRequest().json(ErrorResponse {
-                error: "VALIDATION_ERROR".to_string(),
-                message: "email must be max 255 characters".to_string(),
-            });
-        }
-    }
+// ============ Endpoint Handlers ============
 
-    if let Some(ref title) = payload.title {
-        if title.len() > 255 {
-            return HttpResponse::BadRequest().json(ErrorResponse {
-                error: "VALIDATION_ERROR".to_string(),
-                message: "title must be max 255 characters".to_string(),
-            });
-        }
-    }
+/// POST /api/users - Create a new user from a form or a JSON body
+#[tracing::instrument(name = "create_user", skip_all)]
+async fn create_user(
+    state: web::Data<AppState>,
+    payload: web::Either<web::Json<CreateUserPayload>, CreateUserForm>,
+) -> impl Responder {
+    let payload = match payload {
+        web::Either::Left(json) => json.into_inner(),
+        web::Either::Right(CreateUserForm(form)) => form,
+    };
+    let payload = match payload.cleaned() {
+        Ok(payload) => payload,
+        Err(e) => return InvalidUser::from(e).response(),
+    };
 
-    if let Some(ref hobby) = payload.hobby {
-        if hobby.len() > 255 {
-            return HttpResponse::BadRequest().json(ErrorResponse {
-                error: "VALIDATION_ERROR".to_string(),
-                message: "hobby must be max 255 characters".to_string(),
-            });
-        }
-    }
+    log_info!(state.http_client, "create_user", payload.username, "Creating new user");
 
-    let mut metadata = Vec::new();
-    if let Some(ref title) = payload.title {
-        metadata.push(UserMetadata {
-            parent_property: None,
-            property: "title".to_string(),
-            value: Some(title.clone()),
-        });
-    }
-    if let Some(ref hobby) = payload.hobby {
-        metadata.push(UserMetadata {
-            parent_property: None,
-            property: "hobby".to_string(),
-            value: Some(hobby.clone()),
-        });
-    }
-    if let Some(ref extra) = payload.extra_metadata {
-        metadata.extend(extra.clone());
-    }
+    let create_request = match payload.to_request(&state.metadata_schema) {
+        Ok(create_request) => create_request,
+        Err(e) => return e.response(),
+    };
 
-    let create_request = CreateUserRequest {
-        username: payload.username.clone(),
-        password: payload.password.clone(),
-        profile: Some(UserProfile {
-            first_name: payload.first_name.clone(),
-            last_name: payload.last_name.clone(),
-            email: payload.email.clone(),
+    match register(&state, &create_request).await {
+        Ok(user_id) => HttpResponse::Ok()
+            .content_type("text/plain")
+            .body(user_id.to_string()),
+        Err(DatabaseError::DuplicateUsername) => HttpResponse::Conflict().json(ErrorResponse {
+            error: "DUPLICATE_USERNAME".to_string(),
+            message: format!("Username '{}' already exists", payload.username),
         }),
-        metadata,
-    };
+        Err(e) => database_error_response(&e, "Failed to create user"),
+    }
+}
 
-    match state.db.create_user(&create_request).await {
+/// Store a validated new user, counting the outcome in metrics and logging it
+async fn register(state: &AppState, request: &CreateUserRequest) -> Result<i32, DatabaseError> {
+    let username = &request.username;
+    let result = state.users.create_user(request).await;
+    match &result {
         Ok(user_id) => {
-            log_info!(state.http_client, "create_user", payload.username, "User created successfully with ID: {}", user_id);
-            HttpResponse::Ok()
-                .content_type("text/plain")
-                .body(user_id.to_string())
+            metrics().users_created_total.with_label_values(&["success"]).inc();
+            log_info!(state.http_client, "create_user", username, "User created successfully with ID: {}", user_id);
         }
         Err(DatabaseError::DuplicateUsername) => {
-            log_info!(state.http_client, "create_user", payload.username, "Username already exists");
-            HttpResponse::Conflict().json(ErrorResponse {
-                error: "DUPLICATE_USERNAME".to_string(),
-                message: format!("Username '{}' already exists", payload.username),
-            })
+            metrics().users_created_total.with_label_values(&["duplicate"]).inc();
+            log_info!(state.http_client, "create_user", username, "Username already exists");
         }
-        Err(DatabaseError::ConnectionError(_)) => {
-            log_error!(state.http_client, "create_user", payload.username, "Database connection error");
-            HttpResponse::ServiceUnavailable().json(ErrorResponse {
-                error: "DATABASE_UNAVAILABLE".to_string(),
-                message: "Database connection failed".to_string(),
-            })
+        Err(DatabaseError::DuplicateEmail) => {
+            metrics().users_created_total.with_label_values(&["duplicate"]).inc();
+            log_info!(state.http_client, "create_user", username, "Email already registered");
         }
         Err(e) => {
-            log_error!(state.http_client, "create_user", payload.username, "Error creating user: {:?}", e);
-            HttpResponse::InternalServerError().json(ErrorResponse {
-                error: "INTERNAL_ERROR".to_string(),
-                message: "Failed to create user".to_string(),
-            })
+            metrics().users_created_total.with_label_values(&["error"]).inc();
+            log_error!(state.http_client, "create_user", username, "Error creating user: {}", e);
         }
     }
+    result
 }
 
 /// POST /api/login - Login with username and password
+#[tracing::instrument(name = "login", skip_all)]
 async fn login(
     state: web::Data<AppState>,
     payload: web::Form<LoginPayload>,
@@ -246,66 +483,97 @@ async fn login(
         });
     }
 
-    match state.db.authenticate_user(&payload.username).await {
+    match authenticate(&state, &payload.username, &payload.password).await {
+        Ok(user_id) => HttpResponse::Ok()
+            .content_type("text/plain")
+            .body(user_id.to_string()),
+        Err(LoginFailure::InvalidCredentials) => HttpResponse::Unauthorized().json(ErrorResponse {
+            error: "INVALID_CREDENTIALS".to_string(),
+            message: "Invalid username or password".to_string(),
+        }),
+        Err(LoginFailure::Database(e)) => database_error_response(&e, "Login failed"),
+    }
+}
+
+/// Why a login attempt failed
+#[derive(Debug)]
+pub enum LoginFailure {
+    /// Unknown username or wrong password; the two are not told apart
+    InvalidCredentials,
+    Database(DatabaseError),
+}
+
+/// Check `password` for `username`, counting the attempt in metrics and logging it
+async fn authenticate(state: &AppState, username: &str, password: &str) -> Result<i32, LoginFailure> {
+    match state.users.authenticate_user(username).await {
         Ok((user_id, stored_password)) => {
             // Compare passwords (plain-text comparison as per design)
-            if stored_password == payload.password {
-                log_info!(state.http_client, "login_user", payload.username, "Successful login");
-                HttpResponse::Ok()
-                    .content_type("text/plain")
-                    .body(user_id.to_string())
+            if stored_password == password {
+                metrics().login_attempts_total.with_label_values(&["success"]).inc();
+                log_info!(state.http_client, "login_user", username, "Successful login");
+                Ok(user_id)
             } else {
-                log_info!(state.http_client, "login_user", payload.username, "Invalid password");
-                HttpResponse::Unauthorized().json(ErrorResponse {
-                    error: "INVALID_CREDENTIALS".to_string(),
-                    message: "Invalid username or password".to_string(),
-                })
+                metrics().login_attempts_total.with_label_values(&["invalid_password"]).inc();
+                log_info!(state.http_client, "login_user", username, "Invalid password");
+                Err(LoginFailure::InvalidCredentials)
             }
         }
         Err(DatabaseError::UserNotFound) => {
-            log_info!(state.http_client, "login_user", payload.username, "User not found during login");
-            HttpResponse::Unauthorized().json(ErrorResponse {
-                error: "INVALID_CREDENTIALS".to_string(),
-                message: "Invalid username or password".to_string(),
-            })
-        }
-        Err(DatabaseError::ConnectionError(_)) => {
-            log_error!(state.http_client, "login_user", payload.username, "Database connection error");
-            HttpResponse::ServiceUnavailable().json(ErrorResponse {
-                error: "DATABASE_UNAVAILABLE".to_string(),
-                message: "Database connection failed".to_string(),
-            })
+            metrics().login_attempts_total.with_label_values(&["unknown_user"]).inc();
+            log_info!(state.http_client, "login_user", username, "User not found during login");
+            Err(LoginFailure::InvalidCredentials)
         }
         Err(e) => {
-            log_error!(state.http_client, "login_user", payload.username, "Error during login: {:?}", e);
-            HttpResponse::InternalServerError().json(ErrorResponse {
-                error: "INTERNAL_ERROR".to_string(),
-                message: "Login failed".to_string(),
-            })
+            metrics().login_attempts_total.with_label_values(&["error"]).inc();
+            log_error!(state.http_client, "login_user", username, "Error during login: {}", e);
+            Err(LoginFailure::Database(e))
         }
     }
 }
 
+/// Query string of `GET /api/users/{user_id}`
+#[derive(Debug, Deserialize)]
+pub struct UserInfoQuery {
+    /// Greeting template to render (default: `default`)
+    pub template: Option<String>,
+    /// `text`, `json`, `html`, `markdown` or `vcard`; overrides `Accept`
+    pub format: Option<String>,
+}
+
 /// GET /api/users/{user_id} - Get user information
+#[tracing::instrument(name = "get_user_info", skip_all, fields(user_id = path.as_str()))]
 async fn get_user_info(
+    req: HttpRequest,
     state: web::Data<AppState>,
     path: web::Path<String>,
+    query: web::Query<UserInfoQuery>,
 ) -> impl Responder {
     let user_id_str = path.into_inner();
+    let header_value = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+
+    let format = match UserInfoFormat::select(query.format.as_deref(), header_value(header::ACCEPT)) {
+        Ok(format) => format,
+        Err(e) => {
+            let mut builder = match e {
+                FormatError::Unknown(_) => HttpResponse::BadRequest(),
+                FormatError::NotAcceptable => HttpResponse::NotAcceptable(),
+            };
+            return builder.content_type("text/plain; charset=utf-8").body(e.to_string());
+        }
+    };
 
     // Validate user_id format and parse
     match user_id_str.parse::<i32>() {
         Ok(user_id) if user_id > 0 => {
             log_info!(state.http_client, "get_user_info", user_id, "Fetching user info");
 
-            match state.db.find_user_by_id(user_id).await {
+            match state.users.find_user_by_id(user_id).await {
                 Ok(user) => {
                     let username = user.username.clone();
                     log_info!(state.http_client, "get_user_info", username, "User info retrieved for ID: {}", user_id);
-                    let greeting = format_user_greeting(&user);
-                    HttpResponse::Ok()
-                        .content_type("text/plain; charset=utf-8")
-                        .body(greeting)
+                    let template = query.template.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+                    let locale = i18n::negotiate_locale(&user, header_value(header::ACCEPT_LANGUAGE));
+                    user_info_response(&state, user, format, template, &locale)
                 }
                 Err(DatabaseError::UserNotFound) => {
                     log_info!(state.http_client, "get_user_info", user_id, "User not found");
@@ -313,17 +581,12 @@ async fn get_user_info(
                         .content_type("text/plain; charset=utf-8")
                         .body(format!("User with ID {} not found", user_id))
                 }
-                Err(DatabaseError::ConnectionError(_)) => {
-                    log_error!(state.http_client, "get_user_info", "", "Database connection error");
-                    HttpResponse::ServiceUnavailable()
-                        .content_type("text/plain; charset=utf-8")
-                        .body("Database connection failed")
-                }
                 Err(e) => {
-                    log_error!(state.http_client, "get_user_info", user_id, "Error fetching user: {:?}", e);
-                    HttpResponse::InternalServerError()
+                    log_error!(state.http_client, "get_user_info", user_id, "Error fetching user: {}", e);
+                    let (status, _, message) = database_error_parts(&e, "Failed to fetch user");
+                    database_error_builder(&e, status)
                         .content_type("text/plain; charset=utf-8")
-                        .body("Failed to fetch user")
+                        .body(message)
                 }
             }
         }
@@ -344,6 +607,597 @@ async fn get_user_info(
     }
 }
 
+/// `user` in `format`; the greeting is rendered from `template` in `locale` for formats
+/// that include it
+fn user_info_response(
+    state: &AppState,
+    user: User,
+    format: UserInfoFormat,
+    template: &str,
+    locale: &LanguageIdentifier,
+) -> HttpResponse {
+    let greeting = if format.has_greeting() {
+        match state.greeting_templates.render(template, &user, locale, state.name_style) {
+            Ok(greeting) => greeting,
+            Err(e @ GreetingTemplateError::Unknown(_)) => {
+                return HttpResponse::BadRequest()
+                    .content_type("text/plain; charset=utf-8")
+                    .body(e.to_string());
+            }
+            Err(e) => {
+                log_error!(state.http_client, "get_user_info", user.id, "Error rendering greeting: {}", e);
+                return HttpResponse::InternalServerError()
+                    .content_type("text/plain; charset=utf-8")
+                    .body("Failed to render greeting");
+            }
+        }
+    } else {
+        String::new()
+    };
+
+    let info = UserInfoResponse::from_user(user, &state.metadata_schema, state.name_style, NameOrder::for_locale(locale));
+    let name = info.name.clone();
+    let body = match format {
+        UserInfoFormat::Text => escape(&greeting, EscapeContext::Text),
+        UserInfoFormat::Json => serde_json::to_string(&info).expect("user info serializes"),
+        UserInfoFormat::Html => render_html(&info, &name, &greeting, locale),
+        UserInfoFormat::Markdown => render_markdown(&info, &name, &greeting),
+        UserInfoFormat::VCard => render_vcard(&info, &name),
+    };
+
+    let mut builder = HttpResponse::Ok();
+    builder
+        .content_type(format.content_type())
+        .insert_header((header::VARY, "Accept, Accept-Language"));
+    if format.has_greeting() {
+        builder.insert_header((header::CONTENT_LANGUAGE, locale.to_string()));
+    }
+    builder.body(body)
+}
+
+/// POST /api/users/search - Users whose metadata matches all or any of the conditions
+#[tracing::instrument(name = "search_users", skip_all, fields(conditions = payload.conditions.len()))]
+async fn search_users(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    payload: web::Json<MetadataQuery>,
+) -> impl Responder {
+    let query = payload.into_inner();
+    if let Err(e) = query.validate() {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            error: "VALIDATION_ERROR".to_string(),
+            message: e.to_string(),
+        });
+    }
+
+    match state.users.find_users_by_metadata(&query).await {
+        Ok(users) => {
+            log_info!(state.http_client, "search_users", "", "Metadata search matched {} users", users.len());
+            // Each name is ordered for the locale `get_user_info` would pick for that user
+            let accept_language = req.headers().get(header::ACCEPT_LANGUAGE).and_then(|v| v.to_str().ok());
+            let users: Vec<UserInfoResponse> = users
+                .into_iter()
+                .map(|user| {
+                    let order = NameOrder::for_locale(&i18n::negotiate_locale(&user, accept_language));
+                    UserInfoResponse::from_user(user, &state.metadata_schema, state.name_style, order)
+                })
+                .collect();
+            HttpResponse::Ok()
+                .insert_header((header::VARY, "Accept-Language"))
+                .json(users)
+        }
+        Err(e) => {
+            log_error!(state.http_client, "search_users", "", "Error searching users: {}", e);
+            database_error_response(&e, "Failed to search users")
+        }
+    }
+}
+
+/// GET /api/users/{user_id}/metadata - The user's metadata as a tree
+#[tracing::instrument(name = "get_user_metadata", skip_all, fields(user_id = path.as_str()))]
+async fn get_user_metadata(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let user_id = match parse_user_id(&path) {
+        Ok(user_id) => user_id,
+        Err(resp) => return resp,
+    };
+
+    match state.users.find_user_by_id(user_id).await {
+        Ok(user) => HttpResponse::Ok().json(state.metadata_schema.typed_tree(&metadata::build_tree_lossy(&user.metadata))),
+        Err(e) => {
+            log_error!(state.http_client, "get_user_metadata", user_id, "Error fetching metadata: {}", e);
+            database_error_response(&e, "Failed to fetch metadata")
+        }
+    }
+}
+
+/// GET /api/users/{user_id}/metadata/{property} - One property with its children
+#[tracing::instrument(name = "get_metadata_subtree", skip_all, fields(user_id = path.0.as_str(), property = %path.1))]
+async fn get_metadata_subtree(
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let (user_id, property) = path.into_inner();
+    let user_id = match parse_user_id(&user_id) {
+        Ok(user_id) => user_id,
+        Err(resp) => return resp,
+    };
+
+    match state.users.find_metadata_subtree(user_id, &property).await {
+        Ok(subtree) => HttpResponse::Ok().json(state.metadata_schema.typed_subtree(&subtree)),
+        Err(DatabaseError::InvalidMetadata(e)) => invalid_metadata_response(e),
+        Err(e) => {
+            log_error!(state.http_client, "get_metadata_subtree", user_id, "Error fetching metadata: {}", e);
+            database_error_response(&e, "Failed to fetch metadata")
+        }
+    }
+}
+
+/// PUT /api/users/{user_id}/metadata/{property} - Replace one property and its children
+#[tracing::instrument(name = "put_metadata_subtree", skip_all, fields(user_id = path.0.as_str(), property = %path.1))]
+async fn put_metadata_subtree(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+    payload: web::Json<MetadataSubtreePayload>,
+) -> impl Responder {
+    let (user_id, property) = path.into_inner();
+    let user_id = match parse_user_id(&user_id) {
+        Ok(user_id) => user_id,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = check_user_access(&state, &req, user_id) {
+        return resp;
+    }
+    let payload = payload.into_inner();
+    let mut node = MetadataNode {
+        property,
+        value: payload.value,
+        children: payload.children,
+    };
+    if let Err(e) = sanitize::clean_metadata(std::slice::from_mut(&mut node), None) {
+        return invalid_metadata_response(e);
+    }
+
+    // The store checks the schema against the whole tree as it will be after the
+    // replacement, in the same transaction as the write
+    match state.users.replace_metadata_subtree(user_id, &node, &state.metadata_schema).await {
+        Ok(rows) => {
+            log_info!(state.http_client, "put_metadata_subtree", user_id, "Replaced metadata '{}'", node.property);
+            match metadata::subtree(&rows, &node.property) {
+                Ok(Some(subtree)) => HttpResponse::Ok().json(state.metadata_schema.typed_subtree(&subtree)),
+                _ => HttpResponse::Ok().json(node),
+            }
+        }
+        Err(DatabaseError::InvalidMetadata(e)) => invalid_metadata_response(e),
+        Err(DatabaseError::SchemaViolation(e)) => invalid_metadata_response(e),
+        Err(e @ DatabaseError::UserNotFound) => database_error_response(&e, "Failed to update metadata"),
+        Err(e) => {
+            log_error!(state.http_client, "put_metadata_subtree", user_id, "Error replacing metadata: {}", e);
+            database_error_response(&e, "Failed to update metadata")
+        }
+    }
+}
+
+/// DELETE /api/users/{user_id}/metadata/{property} - Remove one property and its children
+#[tracing::instrument(name = "delete_metadata_subtree", skip_all, fields(user_id = path.0.as_str(), property = %path.1))]
+async fn delete_metadata_subtree(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let (user_id, property) = path.into_inner();
+    let user_id = match parse_user_id(&user_id) {
+        Ok(user_id) => user_id,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = check_user_access(&state, &req, user_id) {
+        return resp;
+    }
+
+    // A property the schema requires cannot be removed; the store checks that in the
+    // same transaction as the delete
+    match state.users.delete_metadata_subtree(user_id, &property, &state.metadata_schema).await {
+        Ok(()) => {
+            log_info!(state.http_client, "delete_metadata_subtree", user_id, "Deleted metadata '{}'", property);
+            HttpResponse::NoContent().finish()
+        }
+        Err(DatabaseError::InvalidMetadata(e)) => invalid_metadata_response(e),
+        Err(DatabaseError::SchemaViolation(e)) => invalid_metadata_response(e),
+        Err(e @ (DatabaseError::UserNotFound | DatabaseError::MetadataNotFound(_))) => {
+            database_error_response(&e, "Failed to delete metadata")
+        }
+        Err(e) => {
+            log_error!(state.http_client, "delete_metadata_subtree", user_id, "Error deleting metadata: {}", e);
+            database_error_response(&e, "Failed to delete metadata")
+        }
+    }
+}
+
+/// PUT /api/users/{user_id}/metadata - Replace the user's whole metadata tree
+#[tracing::instrument(name = "put_user_metadata", skip_all, fields(user_id = path.as_str()))]
+async fn put_user_metadata(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    payload: web::Json<Vec<MetadataNode>>,
+) -> impl Responder {
+    let user_id = match parse_user_id(&path) {
+        Ok(user_id) => user_id,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = check_user_access(&state, &req, user_id) {
+        return resp;
+    }
+    let mut nodes = payload.into_inner();
+
+    if let Err(e) = sanitize::clean_metadata(&mut nodes, None) {
+        return invalid_metadata_response(e);
+    }
+    if let Err(e) = metadata::flatten_tree(&nodes) {
+        return invalid_metadata_response(e);
+    }
+    if let Err(e) = state.metadata_schema.validate(&nodes) {
+        return invalid_metadata_response(e);
+    }
+
+    match state.users.replace_metadata(user_id, &nodes, &state.metadata_schema).await {
+        Ok(()) => {
+            log_info!(state.http_client, "put_user_metadata", user_id, "Replaced metadata ({} top-level properties)", nodes.len());
+            HttpResponse::Ok().json(state.metadata_schema.typed_tree(&nodes))
+        }
+        Err(DatabaseError::InvalidMetadata(e)) => invalid_metadata_response(e),
+        Err(e) => {
+            log_error!(state.http_client, "put_user_metadata", user_id, "Error replacing metadata: {}", e);
+            database_error_response(&e, "Failed to update metadata")
+        }
+    }
+}
+
+/// Allow changes to `user_id`'s data from that user's session or, when the request carries
+/// an `X-Admin-Token` header, with the admin token
+fn check_user_access(state: &AppState, req: &HttpRequest, user_id: i32) -> Result<(), HttpResponse> {
+    if req.headers().contains_key("X-Admin-Token") {
+        return check_admin_token(req);
+    }
+
+    match state.sessions.user_id(req) {
+        Some(signed_in) if signed_in == user_id => Ok(()),
+        Some(_) => Err(HttpResponse::Forbidden().json(ErrorResponse {
+            error: "FORBIDDEN".to_string(),
+            message: "Only the user or an admin can change this user's data".to_string(),
+        })),
+        None => Err(HttpResponse::Unauthorized().json(ErrorResponse {
+            error: "AUTHENTICATION_REQUIRED".to_string(),
+            message: "Sign in or provide an admin token".to_string(),
+        })),
+    }
+}
+
+/// Verify the `X-Admin-Token` header against `ADMIN_TOKEN`; admin endpoints are
+/// disabled when `ADMIN_TOKEN` is not configured
+fn check_admin_token(req: &HttpRequest) -> Result<(), HttpResponse> {
+    let expected = match std::env::var("ADMIN_TOKEN") {
+        Ok(token) if !token.is_empty() => token,
+        _ => {
+            return Err(HttpResponse::Forbidden().json(ErrorResponse {
+                error: "ADMIN_DISABLED".to_string(),
+                message: "Admin endpoints are disabled".to_string(),
+            }))
+        }
+    };
+
+    let provided = req
+        .headers()
+        .get("X-Admin-Token")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+
+    // Constant time, so response timing doesn't reveal how much of the token matched
+    if !bool::from(provided.as_bytes().ct_eq(expected.as_bytes())) {
+        return Err(HttpResponse::Unauthorized().json(ErrorResponse {
+            error: "INVALID_ADMIN_TOKEN".to_string(),
+            message: "Missing or invalid admin token".to_string(),
+        }));
+    }
+
+    Ok(())
+}
+
+/// GET /admin/log-levels - Current remote log filtering
+async fn get_log_levels(req: HttpRequest) -> impl Responder {
+    if let Err(resp) = check_admin_token(&req) {
+        return resp;
+    }
+    HttpResponse::Ok().json(logger::remote_config())
+}
+
+/// PUT /admin/log-levels - Replace remote log filtering without a restart
+async fn update_log_levels(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    payload: web::Json<serde_json::Value>,
+) -> impl Responder {
+    if let Err(resp) = check_admin_token(&req) {
+        return resp;
+    }
+
+    // Deserialized here so an unknown level is reported like the other validation errors
+    let config: logger::RemoteLogConfig = match serde_json::from_value(payload.into_inner()) {
+        Ok(config) => config,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                error: "VALIDATION_ERROR".to_string(),
+                message: e.to_string(),
+            })
+        }
+    };
+    match logger::set_remote_config(config.clone()) {
+        Ok(()) => {
+            log_info!(state.http_client, "update_log_levels", "SYSTEM", "Remote log levels updated: {:?}", config);
+            HttpResponse::Ok().json(config)
+        }
+        Err(message) => HttpResponse::BadRequest().json(ErrorResponse {
+            error: "VALIDATION_ERROR".to_string(),
+            message,
+        }),
+    }
+}
+
+/// Body of `POST /admin/greeting-templates/reload`
+#[derive(Debug, Serialize)]
+pub struct GreetingTemplatesResponse {
+    pub templates: Vec<String>,
+}
+
+/// POST /admin/greeting-templates/reload - Re-read `GREETING_TEMPLATES_DIR` without a restart
+async fn reload_greeting_templates(req: HttpRequest, state: web::Data<AppState>) -> impl Responder {
+    if let Err(resp) = check_admin_token(&req) {
+        return resp;
+    }
+
+    match state.greeting_templates.reload() {
+        Ok(templates) => {
+            log_info!(state.http_client, "reload_greeting_templates", "SYSTEM", "Greeting templates reloaded: {:?}", templates);
+            HttpResponse::Ok().json(GreetingTemplatesResponse { templates })
+        }
+        Err(e) => {
+            log_error!(state.http_client, "reload_greeting_templates", "SYSTEM", "Greeting templates not reloaded: {}", e);
+            HttpResponse::BadRequest().json(ErrorResponse {
+                error: "INVALID_TEMPLATES".to_string(),
+                message: e.to_string(),
+            })
+        }
+    }
+}
+
+/// GET /metrics - Prometheus text exposition (requires the admin token)
+async fn metrics_endpoint(req: HttpRequest, state: web::Data<AppState>) -> impl Responder {
+    if let Err(resp) = check_admin_token(&req) {
+        return resp;
+    }
+
+    let m = metrics();
+    if let Some((size, idle)) = state.users.pool_stats() {
+        m.db_pool_connections.set(size as i64);
+        m.db_pool_idle_connections.set(idle as i64);
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(m.render())
+}
+
+/// Shown when a form comes back without the CSRF token its page was rendered with
+const FORM_EXPIRED: &str = "This form has expired, please submit it again";
+
+/// `page` rendered with `ctx` onto `builder`, which sets the status and any extra headers.
+/// The page's forms get the request's CSRF token, or a new one with its cookie.
+fn page_response(
+    state: &AppState,
+    req: &HttpRequest,
+    mut builder: actix_web::HttpResponseBuilder,
+    page: &str,
+    ctx: minijinja::Value,
+) -> HttpResponse {
+    let (csrf_token, cookie) = state.sessions.csrf_token(req);
+    if let Some(cookie) = cookie {
+        builder.cookie(cookie);
+    }
+    match state.pages.render(page, minijinja::context! { csrf_token, ..ctx }) {
+        Ok(html) => builder
+            .content_type("text/html; charset=utf-8")
+            .insert_header((header::CACHE_CONTROL, "no-store"))
+            .body(html),
+        Err(e) => {
+            log_error!(state.http_client, "page", "SYSTEM", "Error rendering {}: {}", page, e);
+            HttpResponse::InternalServerError()
+                .content_type("text/plain; charset=utf-8")
+                .body("Failed to render page")
+        }
+    }
+}
+
+/// GET /login - Login page; signed-in users go straight to their profile
+async fn login_page(req: HttpRequest, state: web::Data<AppState>) -> impl Responder {
+    if state.sessions.user_id(&req).is_some() {
+        return pages::redirect("/profile").finish();
+    }
+    page_response(&state, &req, HttpResponse::Ok(), "login.html", minijinja::context! {})
+}
+
+/// POST /login - Sign in from the login page and continue to the profile
+#[tracing::instrument(name = "login_form", skip_all)]
+async fn login_form(req: HttpRequest, state: web::Data<AppState>, form: web::Form<LoginForm>) -> impl Responder {
+    if !state.sessions.verify_csrf(&req, &form.csrf_token) {
+        let ctx = minijinja::context! { username => form.username, error => FORM_EXPIRED };
+        return page_response(&state, &req, HttpResponse::Forbidden(), "login.html", ctx);
+    }
+
+    log_info!(state.http_client, "login_user", form.username, "Login attempt");
+
+    let mut errors = std::collections::BTreeMap::new();
+    if form.username.is_empty() {
+        errors.insert("username", "Username is required");
+    }
+    if form.password.is_empty() {
+        errors.insert("password", "Password is required");
+    }
+    if !errors.is_empty() {
+        let ctx = minijinja::context! { username => form.username, errors };
+        return page_response(&state, &req, HttpResponse::BadRequest(), "login.html", ctx);
+    }
+
+    match authenticate(&state, &form.username, &form.password).await {
+        Ok(user_id) => {
+            let [session, csrf] = state.sessions.sign_in(user_id);
+            pages::redirect("/profile").cookie(session).cookie(csrf).finish()
+        }
+        Err(LoginFailure::InvalidCredentials) => {
+            let ctx = minijinja::context! { username => form.username, error => "Invalid username or password" };
+            page_response(&state, &req, HttpResponse::Unauthorized(), "login.html", ctx)
+        }
+        Err(LoginFailure::Database(e)) => {
+            let (status, _, message) = database_error_parts(&e, "Login failed");
+            let ctx = minijinja::context! { username => form.username, error => message };
+            page_response(&state, &req, database_error_builder(&e, status), "login.html", ctx)
+        }
+    }
+}
+
+/// GET /signup - Form for creating a user
+async fn signup_page(req: HttpRequest, state: web::Data<AppState>) -> impl Responder {
+    let ctx = minijinja::context! { form => SignupForm::default() };
+    page_response(&state, &req, HttpResponse::Ok(), "signup.html", ctx)
+}
+
+/// POST /signup - Create a user from the signup page and sign them in
+#[tracing::instrument(name = "signup_form", skip_all)]
+async fn signup_form(req: HttpRequest, state: web::Data<AppState>, form: web::Form<SignupForm>) -> impl Responder {
+    if !state.sessions.verify_csrf(&req, &form.csrf_token) {
+        let ctx = minijinja::context! { form => &*form, error => FORM_EXPIRED };
+        return page_response(&state, &req, HttpResponse::Forbidden(), "signup.html", ctx);
+    }
+
+    let payload = match form.to_payload().cleaned() {
+        Ok(payload) => payload,
+        Err(e) => return signup_rejected(&state, &req, HttpResponse::BadRequest(), &form, e.into()),
+    };
+
+    log_info!(state.http_client, "create_user", payload.username, "Creating new user");
+
+    let create_request = match payload.to_request(&state.metadata_schema) {
+        Ok(create_request) => create_request,
+        Err(e) => return signup_rejected(&state, &req, HttpResponse::BadRequest(), &form, e),
+    };
+
+    match register(&state, &create_request).await {
+        Ok(user_id) => {
+            let [session, csrf] = state.sessions.sign_in(user_id);
+            pages::redirect("/profile").cookie(session).cookie(csrf).finish()
+        }
+        Err(DatabaseError::DuplicateUsername) => {
+            let e = InvalidUser::field("username", format!("Username '{}' already exists", payload.username));
+            signup_rejected(&state, &req, HttpResponse::Conflict(), &form, e)
+        }
+        Err(DatabaseError::DuplicateEmail) => {
+            let e = InvalidUser::field("email", "Email address is already registered");
+            signup_rejected(&state, &req, HttpResponse::Conflict(), &form, e)
+        }
+        Err(e) => {
+            let (status, _, message) = database_error_parts(&e, "Failed to create user");
+            let ctx = minijinja::context! { form => &*form, error => message };
+            page_response(&state, &req, database_error_builder(&e, status), "signup.html", ctx)
+        }
+    }
+}
+
+/// The signup page again with `form` filled in and the rejection shown next to its field
+fn signup_rejected(
+    state: &AppState,
+    req: &HttpRequest,
+    builder: actix_web::HttpResponseBuilder,
+    form: &SignupForm,
+    rejection: InvalidUser,
+) -> HttpResponse {
+    let ctx = match rejection.field {
+        Some(field) => minijinja::context! { form, errors => std::collections::BTreeMap::from([(field, rejection.message)]) },
+        None => minijinja::context! { form, error => rejection.message },
+    };
+    page_response(state, req, builder, "signup.html", ctx)
+}
+
+/// GET /profile - The signed-in user's greeting and profile
+#[tracing::instrument(name = "profile_page", skip_all)]
+async fn profile_page(req: HttpRequest, state: web::Data<AppState>) -> impl Responder {
+    let Some(user_id) = state.sessions.user_id(&req) else {
+        return pages::redirect("/login").finish();
+    };
+
+    match state.users.find_user_by_id(user_id).await {
+        Ok(user) => {
+            let accept_language = req.headers().get(header::ACCEPT_LANGUAGE).and_then(|v| v.to_str().ok());
+            let locale = i18n::negotiate_locale(&user, accept_language);
+            let greeting = match state.greeting_templates.render(DEFAULT_TEMPLATE, &user, &locale, state.name_style) {
+                Ok(greeting) => greeting,
+                Err(e) => {
+                    log_error!(state.http_client, "profile_page", user_id, "Error rendering greeting: {}", e);
+                    return HttpResponse::InternalServerError()
+                        .content_type("text/plain; charset=utf-8")
+                        .body("Failed to render greeting");
+                }
+            };
+
+            let info = UserInfoResponse::from_user(user, &state.metadata_schema, state.name_style, NameOrder::for_locale(&locale));
+            let ctx = minijinja::context! {
+                lang => locale.to_string(),
+                name => info.name,
+                greeting,
+                fields => profile_fields(&info),
+            };
+            let mut builder = HttpResponse::Ok();
+            builder
+                .insert_header((header::VARY, "Accept-Language, Cookie"))
+                .insert_header((header::CONTENT_LANGUAGE, locale.to_string()));
+            page_response(&state, &req, builder, "profile.html", ctx)
+        }
+        Err(DatabaseError::UserNotFound) => {
+            // The account is gone; drop the stale session
+            log_info!(state.http_client, "profile_page", user_id, "User not found");
+            pages::redirect("/login").cookie(state.sessions.removal_cookie()).finish()
+        }
+        Err(e) => {
+            log_error!(state.http_client, "profile_page", user_id, "Error fetching user: {}", e);
+            let (status, _, message) = database_error_parts(&e, "Failed to fetch user");
+            database_error_builder(&e, status)
+                .content_type("text/plain; charset=utf-8")
+                .body(message)
+        }
+    }
+}
+
+/// POST /logout - End the session and go back to the login page
+async fn logout(req: HttpRequest, state: web::Data<AppState>, form: web::Form<LogoutForm>) -> impl Responder {
+    if !state.sessions.verify_csrf(&req, &form.csrf_token) {
+        return HttpResponse::Forbidden()
+            .content_type("text/plain; charset=utf-8")
+            .body(FORM_EXPIRED);
+    }
+    pages::redirect("/login").cookie(state.sessions.removal_cookie()).finish()
+}
+
+/// GET / - The web UI's login page
+async fn web_ui_index(state: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    state.web_ui.respond(&req, web_ui::INDEX)
+}
+
+/// GET /{file} - A web UI page or stylesheet
+async fn web_ui_file(state: web::Data<AppState>, req: HttpRequest, file: web::Path<String>) -> impl Responder {
+    state.web_ui.respond(&req, &file)
+}
+
 /// Health check endpoint
 async fn health_check() -> impl Responder {
     HttpResponse::Ok().json(serde_json::json!({"status": "ok"}))
@@ -362,46 +1216,208 @@ async fn main() -> std::io::Result<()> {
     // Create HTTP client for dual logging
     let http_client = reqwest::Client::new();
 
+    if let Err(e) = logger::RemoteLogConfig::from_env().and_then(logger::set_remote_config) {
+        log_error!(http_client, "main", "SYSTEM", "Invalid remote log configuration: {}", e);
+        panic!("Cannot start server: invalid remote log configuration");
+    }
+
+    // Initialize tracing export (no-op unless OTEL_EXPORTER_OTLP_ENDPOINT is set)
+    let telemetry = match Telemetry::init() {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            log_error!(http_client, "main", "SYSTEM", "Failed to initialize tracing: {}", e);
+            panic!("Cannot start server: tracing initialization failed");
+        }
+    };
+    if telemetry.is_enabled() {
+        log_info!(http_client, "main", "SYSTEM", "Exporting traces via OTLP");
+    }
+
     // Initialize database connection pool
     let db = match Database::new().await {
-        Ok(db) => db,
+        Ok(db) => {
+            log_info!(http_client, "main", "SYSTEM", "Connected to {} database", db.backend().as_str());
+            db
+        }
         Err(e) => {
             log_error!(http_client, "main", "SYSTEM", "Failed to initialize database: {:?}", e);
             panic!("Cannot start server: database initialization failed");
         }
     };
 
+    // `rust_user_service migrate` applies pending migrations and exits
+    if std::env::args().nth(1).as_deref() == Some("migrate") {
+        return match db.run_migrations().await {
+            Ok(report) => {
+                log_info!(http_client, "migrate", "SYSTEM", "Applied migrations: {:?}, already applied: {:?}", report.applied, report.already_applied);
+                Ok(())
+            }
+            Err(e) => {
+                log_error!(http_client, "migrate", "SYSTEM", "Migration failed: {}", e);
+                Err(std::io::Error::other(e.to_string()))
+            }
+        };
+    }
+
+    if migrations::migrate_on_start() {
+        match db.run_migrations().await {
+            Ok(report) => {
+                log_info!(http_client, "main", "SYSTEM", "Applied migrations on start: {:?}", report.applied);
+            }
+            Err(e) => {
+                log_error!(http_client, "main", "SYSTEM", "Failed to apply migrations: {}", e);
+                panic!("Cannot start server: database migration failed");
+            }
+        }
+    }
+
+    let metadata_schema = match MetadataSchema::from_env() {
+        Ok(schema) => schema,
+        Err(e) => {
+            log_error!(http_client, "main", "SYSTEM", "Failed to load metadata schema: {}", e);
+            panic!("Cannot start server: invalid metadata schema");
+        }
+    };
+
+    let greeting_templates = match GreetingTemplates::from_env() {
+        Ok(templates) => templates,
+        Err(e) => {
+            log_error!(http_client, "main", "SYSTEM", "Failed to load greeting templates: {}", e);
+            panic!("Cannot start server: invalid greeting templates");
+        }
+    };
+
+    let web_ui = match WebUi::from_env() {
+        Ok(web_ui) => web_ui,
+        Err(e) => {
+            log_error!(http_client, "main", "SYSTEM", "Failed to load web UI: {}", e);
+            panic!("Cannot start server: invalid WEB_UI_DIR");
+        }
+    };
+
+    let sessions = match SessionKey::from_env() {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            log_error!(http_client, "main", "SYSTEM", "Failed to configure sessions: {}", e);
+            panic!("Cannot start server: invalid SESSION_SECRET");
+        }
+    };
+
     let state = web::Data::new(AppState {
-        db,
+        users: std::sync::Arc::new(db),
         http_client,
+        metadata_schema,
+        greeting_templates,
+        name_style: NameStyle::from_env(),
+        web_ui,
+        pages: Pages::default(),
+        sessions,
     });
 
     let server_host = std::env::var("SERVER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
     let server_port = std::env::var("SERVER_PORT").unwrap_or_else(|_| "8080".to_string());
     let bind_addr = format!("{}:{}", server_host, server_port);
 
+    let access_log_format = AccessLogFormat::from_env();
+    let trusted_proxies = match TrustedProxies::from_env() {
+        Ok(proxies) => proxies,
+        Err(e) => {
+            log_error!(state.http_client, "main", "SYSTEM", "Invalid access log configuration: {}", e);
+            panic!("Cannot start server: invalid ACCESS_LOG_TRUSTED_PROXIES");
+        }
+    };
+    let cors = match CorsConfig::from_env() {
+        Ok(cors) => cors,
+        Err(e) => {
+            log_error!(state.http_client, "main", "SYSTEM", "Invalid CORS configuration: {}", e);
+            panic!("Cannot start server: invalid CORS configuration");
+        }
+    };
+    let csrf_protection = CsrfProtection::new(&cors.api);
+
     log_info!(state.http_client, "main", "SYSTEM", "Starting HTTP server on {}", bind_addr);
 
     HttpServer::new(move || {
-        let cors = Cors::permissive();
-
         App::new()
-            .wrap(cors)
+            .wrap(csrf_protection.clone())
+            .wrap(RequestMetrics)
+            .wrap(AccessLog::new(state.http_client.clone(), access_log_format).with_trusted_proxies(trusted_proxies.clone()))
+            .wrap(RequestTracing)
             .app_data(state.clone())
-            .route("/health", web::get().to(health_check))
-            .route("/api/create-user", web::post().to(create_user))
-            .route("/api/login", web::post().to(login))
-            .route("/api/users/{user_id}", web::get().to(get_user_info))
+            .service(
+                web::resource("/health")
+                    .wrap(cors.public.middleware())
+                    .route(web::get().to(health_check)),
+            )
+            .route("/metrics", web::get().to(metrics_endpoint))
+            .service(
+                web::scope("/api")
+                    .wrap(cors.api.middleware())
+                    .route("/create-user", web::post().to(create_user))
+                    .route("/login", web::post().to(login))
+                    .route("/users/search", web::post().to(search_users))
+                    .route("/users/{user_id}", web::get().to(get_user_info))
+                    .route("/users/{user_id}/metadata", web::get().to(get_user_metadata))
+                    .route("/users/{user_id}/metadata", web::put().to(put_user_metadata))
+                    .route("/users/{user_id}/metadata/{property}", web::get().to(get_metadata_subtree))
+                    .route("/users/{user_id}/metadata/{property}", web::put().to(put_metadata_subtree))
+                    .route("/users/{user_id}/metadata/{property}", web::delete().to(delete_metadata_subtree)),
+            )
+            .service(
+                web::scope("/admin")
+                    .wrap(cors.admin.middleware())
+                    .route("/log-levels", web::get().to(get_log_levels))
+                    .route("/log-levels", web::put().to(update_log_levels))
+                    .route("/greeting-templates/reload", web::post().to(reload_greeting_templates)),
+            )
+            .route("/login", web::get().to(login_page))
+            .route("/login", web::post().to(login_form))
+            .route("/signup", web::get().to(signup_page))
+            .route("/signup", web::post().to(signup_form))
+            .route("/profile", web::get().to(profile_page))
+            .route("/logout", web::post().to(logout))
+            // Last, so the API and admin routes take precedence
+            .route("/", web::get().to(web_ui_index))
+            .route("/{file}", web::get().to(web_ui_file))
     })
     .bind(&bind_addr)?
     .run()
-    .await
+    .await?;
+
+    telemetry.shutdown();
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    // Global mutex to serialize tests that use environment variables
+    // This is necessary because std::env::set_var is not thread-safe and
+    // our implementation relies on std::env::var("LOGGER_URL")
+    static TEST_MUTEX: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    // Lints tripped by the original test idioms: `vec!` literals, borrowed `set_form`
+    // arrays and the TEST_MUTEX guard held for the whole test
+    #[allow(clippy::useless_vec)]
     mod main_test;
     mod user_info_formatter_test;
+    #[allow(clippy::needless_borrows_for_generic_args, clippy::await_holding_lock)]
     mod handler_tests;
+    mod redaction_test;
+    mod logger_test;
+    mod access_log_test;
+    mod migrations_test;
+    mod db_test;
+    mod repository_test;
+    mod metadata_test;
+    mod metadata_schema_test;
+    mod metadata_search_test;
+    mod greeting_templates_test;
+    mod i18n_test;
+    mod web_ui_test;
+    mod pages_test;
+    mod session_test;
+    mod csrf_test;
+    mod cors_test;
+    mod sanitize_test;
 }
 
@@ -0,0 +1,364 @@
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::db::UserMetadata;
+
+/// Deepest nesting accepted for a metadata tree; top-level properties are depth 1
+pub const MAX_METADATA_DEPTH: usize = 8;
+
+/// Most properties, counting every level, one user's metadata tree may hold
+pub const MAX_METADATA_NODES: usize = 1000;
+
+/// A metadata property with its nested children. Stored flat in `user_metadata`,
+/// where each child's `parent_property` names its parent's `property`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetadataNode {
+    pub property: String,
+    #[serde(default, deserialize_with = "scalar_as_text")]
+    pub value: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<MetadataNode>,
+}
+
+/// A subtree together with the dotted path of its root from the top of the tree
+/// (`address.geo`). A multi-valued property has one node per value, in `position` order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataSubtree {
+    pub path: String,
+    pub nodes: Vec<MetadataNode>,
+}
+
+/// Accept numbers and booleans as metadata values; they are stored as text
+pub fn scalar_as_text<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Null => Ok(None),
+        serde_json::Value::String(s) => Ok(Some(s)),
+        serde_json::Value::Bool(b) => Ok(Some(b.to_string())),
+        serde_json::Value::Number(n) => Ok(Some(n.to_string())),
+        _ => Err(D::Error::custom("metadata values must be strings, numbers or booleans")),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum MetadataTreeError {
+    #[error("Metadata property names must be 1 to 255 characters and cannot contain '.'")]
+    InvalidProperty,
+    #[error("Metadata '{property}' refers to missing parent '{parent}'")]
+    Orphan { property: String, parent: String },
+    #[error("Metadata '{0}' appears more than once under the same parent")]
+    Duplicate(String),
+    #[error("Metadata property '{0}' appears under more than one parent, so it cannot have children or be addressed")]
+    Ambiguous(String),
+    #[error("Metadata property '{0}' has several values, so only the first can have children")]
+    ChildrenOnLaterValue(String),
+    #[error("Metadata '{0}' is its own ancestor")]
+    Cycle(String),
+    #[error("Metadata '{0}' is nested deeper than {MAX_METADATA_DEPTH} levels")]
+    TooDeep(String),
+    #[error("Metadata may hold at most {MAX_METADATA_NODES} properties")]
+    TooMany,
+}
+
+/// Resolve each row's parent to a row index, rejecting names that are empty or too long,
+/// siblings sharing a name, parents that are missing or ambiguous, cycles, and trees
+/// deeper than the limit
+fn resolve_parents(rows: &[UserMetadata]) -> Result<Vec<Option<usize>>, MetadataTreeError> {
+    let by_name = rows_by_name(rows);
+    // Mirrors the unique (user_id, parent_property, property, position) index
+    let mut seen = HashSet::with_capacity(rows.len());
+    // First value of each parent already looked up
+    let mut resolved: HashMap<&str, usize> = HashMap::new();
+    let mut parents = Vec::with_capacity(rows.len());
+    for row in rows {
+        // '.' separates the levels of a property path
+        if row.property.is_empty() || row.property.len() > 255 || row.property.contains('.') {
+            return Err(MetadataTreeError::InvalidProperty);
+        }
+        if !seen.insert((row.parent_property.as_deref(), row.property.as_str(), row.position)) {
+            return Err(MetadataTreeError::Duplicate(row.property.clone()));
+        }
+        let Some(parent) = row.parent_property.as_deref() else {
+            parents.push(None);
+            continue;
+        };
+        if let Some(&index) = resolved.get(parent) {
+            parents.push(Some(index));
+            continue;
+        }
+        // Children of a multi-valued property hang off its first value
+        match property_rows(rows, &by_name, parent)? {
+            Some(values) => {
+                resolved.insert(parent, values[0]);
+                parents.push(Some(values[0]));
+            }
+            None => {
+                return Err(MetadataTreeError::Orphan {
+                    property: row.property.clone(),
+                    parent: parent.to_string(),
+                })
+            }
+        }
+    }
+
+    // Walk up from each row; meeting a row from the same walk means a cycle
+    let mut walked_from = vec![usize::MAX; rows.len()];
+    let mut finished = vec![false; rows.len()];
+    for start in 0..rows.len() {
+        let mut current = Some(start);
+        while let Some(index) = current {
+            if finished[index] {
+                break;
+            }
+            if walked_from[index] == start {
+                return Err(MetadataTreeError::Cycle(rows[index].property.clone()));
+            }
+            walked_from[index] = start;
+            current = parents[index];
+        }
+        current = Some(start);
+        while let Some(index) = current.filter(|&index| !finished[index]) {
+            finished[index] = true;
+            current = parents[index];
+        }
+    }
+
+    // Without cycles every walk ends at a top-level row, so depths build up from there
+    let mut depths = vec![0; rows.len()];
+    for start in 0..rows.len() {
+        let mut walk = Vec::new();
+        let mut current = Some(start);
+        while let Some(index) = current.filter(|&index| depths[index] == 0) {
+            walk.push(index);
+            current = parents[index];
+        }
+        let mut depth = current.map_or(0, |index| depths[index]);
+        for index in walk.into_iter().rev() {
+            depth += 1;
+            depths[index] = depth;
+        }
+    }
+    if let Some(index) = (0..rows.len()).find(|&index| depths[index] > MAX_METADATA_DEPTH) {
+        return Err(MetadataTreeError::TooDeep(rows[index].property.clone()));
+    }
+
+    Ok(parents)
+}
+
+/// Row indices for each property name, in row order
+fn rows_by_name(rows: &[UserMetadata]) -> HashMap<&str, Vec<usize>> {
+    let mut by_name: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, row) in rows.iter().enumerate() {
+        by_name.entry(row.property.as_str()).or_default().push(index);
+    }
+    by_name
+}
+
+/// Indices of the rows named `property` in `position` order, or `None` when there are
+/// none. Siblings sharing the name are the values of one multi-valued property; rows with
+/// the name under different parents can't be told apart.
+fn property_rows(
+    rows: &[UserMetadata],
+    by_name: &HashMap<&str, Vec<usize>>,
+    property: &str,
+) -> Result<Option<Vec<usize>>, MetadataTreeError> {
+    let Some(matches) = by_name.get(property) else {
+        return Ok(None);
+    };
+    let first = &rows[matches[0]];
+    if matches.iter().any(|&index| rows[index].parent_property != first.parent_property) {
+        return Err(MetadataTreeError::Ambiguous(property.to_string()));
+    }
+    let mut matches = matches.clone();
+    matches.sort_by_key(|&index| rows[index].position);
+    Ok(Some(matches))
+}
+
+/// Check that flat rows form a valid tree of at most `MAX_METADATA_NODES` properties
+pub fn validate(rows: &[UserMetadata]) -> Result<(), MetadataTreeError> {
+    if rows.len() > MAX_METADATA_NODES {
+        return Err(MetadataTreeError::TooMany);
+    }
+    resolve_parents(rows).map(|_| ())
+}
+
+/// Nest flat rows into a tree, keeping siblings in row order
+pub fn build_tree(rows: &[UserMetadata]) -> Result<Vec<MetadataNode>, MetadataTreeError> {
+    let parents = resolve_parents(rows)?;
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); rows.len()];
+    let mut roots = Vec::new();
+    for (index, parent) in parents.iter().enumerate() {
+        match parent {
+            Some(parent) => children[*parent].push(index),
+            None => roots.push(index),
+        }
+    }
+
+    fn node(rows: &[UserMetadata], children: &[Vec<usize>], index: usize) -> MetadataNode {
+        MetadataNode {
+            property: rows[index].property.clone(),
+            value: rows[index].value.clone(),
+            children: children[index].iter().map(|&child| node(rows, children, child)).collect(),
+        }
+    }
+
+    Ok(roots.into_iter().map(|root| node(rows, &children, root)).collect())
+}
+
+/// Tree for display: stored rows that no longer form a valid tree are listed flat
+pub fn build_tree_lossy(rows: &[UserMetadata]) -> Vec<MetadataNode> {
+    build_tree(rows).unwrap_or_else(|_| {
+        rows.iter()
+            .map(|row| MetadataNode {
+                property: row.property.clone(),
+                value: row.value.clone(),
+                children: Vec::new(),
+            })
+            .collect()
+    })
+}
+
+/// Flatten nodes into rows, parents before their children, attaching the top-level
+/// nodes to `parent`. Siblings sharing a name are numbered in order by `position`.
+pub fn flatten(nodes: &[MetadataNode], parent: Option<&str>) -> Vec<UserMetadata> {
+    let mut rows = Vec::new();
+    let mut counts: HashMap<&str, i32> = HashMap::new();
+    for node in nodes {
+        let count = counts.entry(node.property.as_str()).or_default();
+        let position = *count;
+        *count += 1;
+        rows.push(UserMetadata {
+            parent_property: parent.map(str::to_string),
+            property: node.property.clone(),
+            value: node.value.clone(),
+            position,
+        });
+        rows.extend(flatten(&node.children, Some(&node.property)));
+    }
+    rows
+}
+
+/// Number of nodes in a tree, descendants included
+pub fn node_count(nodes: &[MetadataNode]) -> usize {
+    nodes.iter().map(|node| 1 + node_count(&node.children)).sum()
+}
+
+/// Flatten a tree from a request into top-level rows and check them. Trees over
+/// `MAX_METADATA_NODES` are rejected before any other work. Children under any but the
+/// first value of a multi-valued property are rejected, since the rows would attach them
+/// to the first value.
+pub fn flatten_tree(nodes: &[MetadataNode]) -> Result<Vec<UserMetadata>, MetadataTreeError> {
+    fn check_level(nodes: &[MetadataNode]) -> Result<(), MetadataTreeError> {
+        let mut seen = HashSet::with_capacity(nodes.len());
+        for node in nodes {
+            if !seen.insert(node.property.as_str()) && !node.children.is_empty() {
+                return Err(MetadataTreeError::ChildrenOnLaterValue(node.property.clone()));
+            }
+            check_level(&node.children)?;
+        }
+        Ok(())
+    }
+
+    if node_count(nodes) > MAX_METADATA_NODES {
+        return Err(MetadataTreeError::TooMany);
+    }
+    check_level(nodes)?;
+    let rows = flatten(nodes, None);
+    validate(&rows)?;
+    Ok(rows)
+}
+
+/// Values of the top-level `property` rows in `position` order
+pub fn values(rows: &[UserMetadata], property: &str) -> Vec<String> {
+    let mut matching: Vec<&UserMetadata> = rows
+        .iter()
+        .filter(|row| row.parent_property.is_none() && row.property == property)
+        .collect();
+    matching.sort_by_key(|row| row.position);
+    matching.into_iter().filter_map(|row| row.value.clone()).collect()
+}
+
+/// Row indices of every value of `property` and all their descendants in row order, or
+/// `None` when no row has that name
+pub fn subtree_rows(rows: &[UserMetadata], property: &str) -> Result<Option<Vec<usize>>, MetadataTreeError> {
+    let parents = resolve_parents(rows)?;
+    let Some(roots) = property_rows(rows, &rows_by_name(rows), property)? else {
+        return Ok(None);
+    };
+
+    // A row is in the subtree when one of the values is among its ancestors
+    let roots: HashSet<usize> = roots.into_iter().collect();
+    let members = (0..rows.len())
+        .filter(|&index| std::iter::successors(Some(index), |&i| parents[i]).any(|i| roots.contains(&i)))
+        .collect();
+    Ok(Some(members))
+}
+
+/// The subtree rooted at `property`, or `None` when no row has that name
+pub fn subtree(rows: &[UserMetadata], property: &str) -> Result<Option<MetadataSubtree>, MetadataTreeError> {
+    let Some(members) = subtree_rows(rows, property)? else {
+        return Ok(None);
+    };
+
+    // Names along the way up are unique, since each one is some row's parent
+    let mut path = vec![property];
+    let mut parent = members
+        .iter()
+        .map(|&index| &rows[index])
+        .find(|row| row.property == property)
+        .and_then(|row| row.parent_property.as_deref());
+    while let Some(name) = parent {
+        path.push(name);
+        parent = rows.iter().find(|row| row.property == name).and_then(|row| row.parent_property.as_deref());
+    }
+    path.reverse();
+
+    // The values become top-level rows, in position order ahead of their descendants
+    let mut selected: Vec<UserMetadata> = members.iter().map(|&index| rows[index].clone()).collect();
+    for row in selected.iter_mut().filter(|row| row.property == property) {
+        row.parent_property = None;
+    }
+    selected.sort_by_key(|row| match row.parent_property {
+        Some(_) => (1, 0),
+        None => (0, row.position),
+    });
+    Ok(Some(MetadataSubtree {
+        path: path.join("."),
+        nodes: build_tree(&selected)?,
+    }))
+}
+
+/// Plan replacing the subtree rooted at `node.property` with `node`, or adding `node`
+/// at the top level when there is none. `node` replaces every value of a multi-valued
+/// property. Returns the indices of rows to delete and the
+/// rows to insert, after checking the resulting tree.
+pub fn replace_subtree(rows: &[UserMetadata], node: &MetadataNode) -> Result<(Vec<usize>, Vec<UserMetadata>), MetadataTreeError> {
+    let removed = subtree_rows(rows, &node.property)?.unwrap_or_default();
+    let parent = removed
+        .iter()
+        .map(|&index| &rows[index])
+        .find(|row| row.property == node.property)
+        .and_then(|row| row.parent_property.as_deref());
+    flatten_tree(std::slice::from_ref(node))?;
+    let inserted = flatten(std::slice::from_ref(node), parent);
+    validate(&apply(rows, &removed, &inserted))?;
+
+    Ok((removed, inserted))
+}
+
+/// All rows after removing the subtree rooted at `property`, or `None` when no row has
+/// that name
+pub fn without_subtree(rows: &[UserMetadata], property: &str) -> Result<Option<Vec<UserMetadata>>, MetadataTreeError> {
+    Ok(subtree_rows(rows, property)?.map(|removed| apply(rows, &removed, &[])))
+}
+
+/// `rows` without the `removed` indices, followed by `inserted`
+pub fn apply(rows: &[UserMetadata], removed: &[usize], inserted: &[UserMetadata]) -> Vec<UserMetadata> {
+    let removed: HashSet<&usize> = removed.iter().collect();
+    rows.iter()
+        .enumerate()
+        .filter(|(index, _)| !removed.contains(index))
+        .map(|(_, row)| row.clone())
+        .chain(inserted.iter().cloned())
+        .collect()
+}
@@ -0,0 +1,250 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::metadata::{MetadataNode, MetadataSubtree};
+
+/// Type a registered metadata property's text value must parse as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    #[default]
+    String,
+    Int,
+    Bool,
+    /// `YYYY-MM-DD`
+    Date,
+    /// One of the spec's `values`
+    Enum,
+    /// Absolute `http` or `https` URL
+    Url,
+}
+
+/// Rules for one registered property
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PropertySpec {
+    #[serde(default, rename = "type")]
+    pub value_type: ValueType,
+    /// Maximum value length in characters
+    #[serde(default)]
+    pub max_length: Option<usize>,
+    /// Must be present whenever its parent is
+    #[serde(default)]
+    pub required: bool,
+    /// May appear more than once under the same parent
+    #[serde(default)]
+    pub multi_valued: bool,
+    /// Allowed values for `enum` properties
+    #[serde(default)]
+    pub values: Vec<String>,
+}
+
+/// Registry of known metadata properties, keyed by dotted path from the top of the
+/// tree (`title`, `address.city`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataSchema {
+    /// Accept properties that are not registered, as untyped strings
+    #[serde(default = "default_allow_unknown")]
+    pub allow_unknown: bool,
+    #[serde(default)]
+    pub properties: HashMap<String, PropertySpec>,
+}
+
+fn default_allow_unknown() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SchemaError {
+    #[error("Metadata '{0}' is not a registered property")]
+    Unknown(String),
+    #[error("Metadata '{0}' is required")]
+    Missing(String),
+    #[error("Metadata '{0}' may only appear once")]
+    Repeated(String),
+    #[error("Metadata '{property}' must be {expected}")]
+    InvalidValue { property: String, expected: String },
+    #[error("Metadata '{property}' must be at most {max} characters")]
+    TooLong { property: String, max: usize },
+}
+
+/// A metadata node whose value is typed JSON according to the schema
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TypedMetadataNode {
+    pub property: String,
+    pub value: serde_json::Value,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TypedMetadataNode>,
+}
+
+/// Typed form of a subtree: the node itself, or a list of its values when the property
+/// is multi-valued
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum TypedMetadataSubtree {
+    Single(TypedMetadataNode),
+    Values(Vec<TypedMetadataNode>),
+}
+
+impl Default for MetadataSchema {
+    /// The `title` and `hobby` shortcuts of `POST /api/create-user`, each of which may
+    /// repeat; anything else is accepted
+    fn default() -> Self {
+        let text = PropertySpec {
+            max_length: Some(255),
+            multi_valued: true,
+            ..PropertySpec::default()
+        };
+        MetadataSchema {
+            allow_unknown: true,
+            properties: HashMap::from([("title".to_string(), text.clone()), ("hobby".to_string(), text)]),
+        }
+    }
+}
+
+impl MetadataSchema {
+    /// Load the registry from the JSON file named by `METADATA_SCHEMA_FILE`; the default
+    /// registry is used when it is unset
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("METADATA_SCHEMA_FILE") {
+            Ok(path) => {
+                let contents = std::fs::read_to_string(&path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+                Self::from_json(&contents).map_err(|e| format!("Invalid metadata schema in {}: {}", path, e))
+            }
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Parse a registry, rejecting `enum` properties without allowed values
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let schema: MetadataSchema = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        if let Some((path, _)) = schema
+            .properties
+            .iter()
+            .find(|(_, spec)| spec.value_type == ValueType::Enum && spec.values.is_empty())
+        {
+            return Err(format!("enum property '{}' has no values", path));
+        }
+        Ok(schema)
+    }
+
+    /// Check a complete metadata tree against the registry
+    pub fn validate(&self, nodes: &[MetadataNode]) -> Result<(), SchemaError> {
+        self.validate_level(nodes, None)
+    }
+
+    fn validate_level(&self, nodes: &[MetadataNode], parent: Option<&str>) -> Result<(), SchemaError> {
+        let mut seen = HashSet::with_capacity(nodes.len());
+        for node in nodes {
+            let path = child_path(parent, &node.property);
+            let spec = self.properties.get(&path);
+            match spec {
+                Some(spec) => {
+                    if let Some(value) = node.value.as_deref() {
+                        check_value(&path, spec, value)?;
+                    }
+                }
+                None if !self.allow_unknown => return Err(SchemaError::Unknown(path)),
+                None => {}
+            }
+            // Only registered multi-valued properties may repeat
+            if !seen.insert(node.property.as_str()) && !spec.is_some_and(|spec| spec.multi_valued) {
+                return Err(SchemaError::Repeated(path));
+            }
+            self.validate_level(&node.children, Some(&path))?;
+        }
+
+        // Required properties whose parent is this level
+        for (path, spec) in &self.properties {
+            if !spec.required {
+                continue;
+            }
+            let (spec_parent, property) = match path.rsplit_once('.') {
+                Some((spec_parent, property)) => (Some(spec_parent), property),
+                None => (None, path.as_str()),
+            };
+            if spec_parent == parent && !seen.contains(property) {
+                return Err(SchemaError::Missing(path.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Convert stored text values to JSON of each property's registered type. Values that
+    /// don't parse (written before the property was registered) stay strings.
+    pub fn typed_tree(&self, nodes: &[MetadataNode]) -> Vec<TypedMetadataNode> {
+        self.typed_level(nodes, None)
+    }
+
+    /// Typed form of a subtree, using its path to find the registered types. Registered
+    /// multi-valued properties, and any property stored more than once, are listed.
+    pub fn typed_subtree(&self, subtree: &MetadataSubtree) -> TypedMetadataSubtree {
+        let parent = subtree.path.rsplit_once('.').map(|(parent, _)| parent);
+        let mut typed = self.typed_level(&subtree.nodes, parent);
+        let multi_valued = self.properties.get(&subtree.path).is_some_and(|spec| spec.multi_valued);
+        if multi_valued || typed.len() != 1 {
+            TypedMetadataSubtree::Values(typed)
+        } else {
+            TypedMetadataSubtree::Single(typed.remove(0))
+        }
+    }
+
+    fn typed_level(&self, nodes: &[MetadataNode], parent: Option<&str>) -> Vec<TypedMetadataNode> {
+        nodes
+            .iter()
+            .map(|node| {
+                let path = child_path(parent, &node.property);
+                let value_type = self.properties.get(&path).map(|spec| spec.value_type).unwrap_or_default();
+                TypedMetadataNode {
+                    property: node.property.clone(),
+                    value: node.value.as_deref().map_or(serde_json::Value::Null, |v| typed_value(value_type, v)),
+                    children: self.typed_level(&node.children, Some(&path)),
+                }
+            })
+            .collect()
+    }
+}
+
+fn child_path(parent: Option<&str>, property: &str) -> String {
+    match parent {
+        Some(parent) => format!("{}.{}", parent, property),
+        None => property.to_string(),
+    }
+}
+
+fn check_value(path: &str, spec: &PropertySpec, value: &str) -> Result<(), SchemaError> {
+    if let Some(max) = spec.max_length {
+        if value.chars().count() > max {
+            return Err(SchemaError::TooLong { property: path.to_string(), max });
+        }
+    }
+
+    let expected = match spec.value_type {
+        ValueType::String => return Ok(()),
+        ValueType::Int if value.parse::<i64>().is_err() => "an integer".to_string(),
+        ValueType::Bool if value != "true" && value != "false" => "true or false".to_string(),
+        ValueType::Date if chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").is_err() => {
+            "a date (YYYY-MM-DD)".to_string()
+        }
+        ValueType::Enum if !spec.values.iter().any(|v| v == value) => format!("one of: {}", spec.values.join(", ")),
+        ValueType::Url if !is_web_url(value) => "an http or https URL".to_string(),
+        _ => return Ok(()),
+    };
+    Err(SchemaError::InvalidValue { property: path.to_string(), expected })
+}
+
+fn is_web_url(value: &str) -> bool {
+    reqwest::Url::parse(value).is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host())
+}
+
+fn typed_value(value_type: ValueType, value: &str) -> serde_json::Value {
+    match value_type {
+        ValueType::Int => value.parse::<i64>().map(serde_json::Value::from).unwrap_or_else(|_| value.into()),
+        ValueType::Bool => match value {
+            "true" => true.into(),
+            "false" => false.into(),
+            _ => value.into(),
+        },
+        _ => value.into(),
+    }
+}
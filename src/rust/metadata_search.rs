@@ -0,0 +1,153 @@
+use serde::Deserialize;
+
+use crate::db::UserMetadata;
+
+/// Most users one search returns
+pub const MAX_SEARCH_LIMIT: u32 = 100;
+
+/// How a condition compares stored values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    #[default]
+    Exact,
+    Prefix,
+}
+
+/// How several conditions combine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Combine {
+    /// Every condition matches (AND)
+    #[default]
+    All,
+    /// At least one condition matches (OR)
+    Any,
+}
+
+/// One property condition. `property` is `name` for a top-level property or
+/// `parent.name` for a nested one.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MetadataCondition {
+    pub property: String,
+    pub value: String,
+    #[serde(default, rename = "match")]
+    pub mode: MatchMode,
+    #[serde(default)]
+    pub ignore_case: bool,
+}
+
+/// Search for users by their metadata, returning them in ID order
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MetadataQuery {
+    pub conditions: Vec<MetadataCondition>,
+    #[serde(default)]
+    pub combine: Combine,
+    #[serde(default = "default_limit")]
+    pub limit: u32,
+    #[serde(default)]
+    pub offset: u32,
+}
+
+fn default_limit() -> u32 {
+    20
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SearchError {
+    #[error("At least one condition is required")]
+    NoConditions,
+    #[error("Search property '{0}' must be 'name' or 'parent.name'")]
+    InvalidProperty(String),
+    #[error("limit must be between 1 and {MAX_SEARCH_LIMIT}")]
+    InvalidLimit,
+}
+
+impl MetadataCondition {
+    /// `(parent_property, property)` the condition matches rows on
+    pub fn target(&self) -> Result<(Option<&str>, &str), SearchError> {
+        let invalid = || SearchError::InvalidProperty(self.property.clone());
+        let (parent, property) = match self.property.split_once('.') {
+            Some((parent, property)) => (Some(parent), property),
+            None => (None, self.property.as_str()),
+        };
+        if property.is_empty() || property.contains('.') || parent.is_some_and(str::is_empty) {
+            return Err(invalid());
+        }
+        Ok((parent, property))
+    }
+
+    /// Whether one stored row satisfies the condition
+    pub fn matches(&self, row: &UserMetadata) -> bool {
+        let Ok((parent, property)) = self.target() else {
+            return false;
+        };
+        let Some(value) = row.value.as_deref() else {
+            return false;
+        };
+        if row.property != property || row.parent_property.as_deref() != parent {
+            return false;
+        }
+
+        let (value, wanted) = if self.ignore_case {
+            (value.to_lowercase(), self.value.to_lowercase())
+        } else {
+            (value.to_string(), self.value.clone())
+        };
+        match self.mode {
+            MatchMode::Exact => value == wanted,
+            MatchMode::Prefix => value.starts_with(&wanted),
+        }
+    }
+}
+
+impl MetadataQuery {
+    /// Reject queries without conditions, with malformed properties or an out-of-range limit
+    pub fn validate(&self) -> Result<(), SearchError> {
+        if self.conditions.is_empty() {
+            return Err(SearchError::NoConditions);
+        }
+        if self.limit == 0 || self.limit > MAX_SEARCH_LIMIT {
+            return Err(SearchError::InvalidLimit);
+        }
+        self.conditions.iter().try_for_each(|c| c.target().map(|_| ()))
+    }
+
+    /// Whether a user's metadata rows satisfy the query
+    pub fn matches(&self, rows: &[UserMetadata]) -> bool {
+        let condition_holds = |c: &MetadataCondition| rows.iter().any(|row| c.matches(row));
+        match self.combine {
+            Combine::All => self.conditions.iter().all(condition_holds),
+            Combine::Any => self.conditions.iter().any(condition_holds),
+        }
+    }
+}
+
+/// Escape `%`, `_` and `!` for a `LIKE` pattern with `ESCAPE '!'`. `!` rather than `\`
+/// because MySQL's `NO_BACKSLASH_ESCAPES` mode changes what `'\\'` means.
+pub fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '!') {
+            escaped.push('!');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escape `*`, `?` and `[` for a SQLite `GLOB` pattern
+pub fn escape_glob(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '*' | '?' | '[' => {
+                escaped.push('[');
+                escaped.push(c);
+                escaped.push(']');
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,168 @@
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+/// Prometheus collectors for the service
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests_total: IntCounterVec,
+    pub http_request_duration_seconds: HistogramVec,
+    pub login_attempts_total: IntCounterVec,
+    pub users_created_total: IntCounterVec,
+    pub db_pool_connections: IntGauge,
+    pub db_pool_idle_connections: IntGauge,
+    pub db_pool_acquire_wait_seconds: Histogram,
+    pub remote_log_in_flight: IntGauge,
+    pub remote_log_dropped_total: IntCounter,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let http_requests_total = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by route and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let login_attempts_total = IntCounterVec::new(
+            Opts::new("login_attempts_total", "Login attempts by result"),
+            &["result"],
+        )
+        .unwrap();
+        let users_created_total = IntCounterVec::new(
+            Opts::new("users_created_total", "User creation attempts by result"),
+            &["result"],
+        )
+        .unwrap();
+        let db_pool_connections =
+            IntGauge::new("db_pool_connections", "Open connections in the database pool").unwrap();
+        let db_pool_idle_connections =
+            IntGauge::new("db_pool_idle_connections", "Idle connections in the database pool").unwrap();
+        let db_pool_acquire_wait_seconds = Histogram::with_opts(
+            HistogramOpts::new("db_pool_acquire_wait_seconds", "Time spent waiting for a pooled connection")
+                .buckets(vec![0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 30.0]),
+        )
+        .unwrap();
+        let remote_log_in_flight =
+            IntGauge::new("remote_log_in_flight", "Log events queued for delivery to LOGGER_URL").unwrap();
+        let remote_log_dropped_total =
+            IntCounter::new("remote_log_dropped_total", "Log events that failed delivery to LOGGER_URL").unwrap();
+
+        registry.register(Box::new(http_requests_total.clone())).unwrap();
+        registry.register(Box::new(http_request_duration_seconds.clone())).unwrap();
+        registry.register(Box::new(login_attempts_total.clone())).unwrap();
+        registry.register(Box::new(users_created_total.clone())).unwrap();
+        registry.register(Box::new(db_pool_connections.clone())).unwrap();
+        registry.register(Box::new(db_pool_idle_connections.clone())).unwrap();
+        registry.register(Box::new(db_pool_acquire_wait_seconds.clone())).unwrap();
+        registry.register(Box::new(remote_log_in_flight.clone())).unwrap();
+        registry.register(Box::new(remote_log_dropped_total.clone())).unwrap();
+
+        Metrics {
+            registry,
+            http_requests_total,
+            http_request_duration_seconds,
+            login_attempts_total,
+            users_created_total,
+            db_pool_connections,
+            db_pool_idle_connections,
+            db_pool_acquire_wait_seconds,
+            remote_log_in_flight,
+            remote_log_dropped_total,
+        }
+    }
+
+    /// Render all collectors in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("failed to encode metrics");
+        String::from_utf8(buffer).expect("metrics are not valid UTF-8")
+    }
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Process-wide metrics registry
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
+
+/// Middleware that records request counts and latency per route template
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let method = req.method().to_string();
+        // Unmatched paths share one label so scanners can't blow up cardinality. The
+        // route is resolved up front because an error carries no request.
+        let route = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let result = service.call(req).await;
+
+            let status = match &result {
+                Ok(res) => res.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+            let status = status.as_u16().to_string();
+            let labels = [method.as_str(), route.as_str(), status.as_str()];
+
+            let m = metrics();
+            m.http_requests_total.with_label_values(&labels).inc();
+            m.http_request_duration_seconds
+                .with_label_values(&labels)
+                .observe(started.elapsed().as_secs_f64());
+
+            result
+        })
+    }
+}
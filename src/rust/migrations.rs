@@ -0,0 +1,77 @@
+use sha2::{Digest, Sha256};
+
+/// A numbered schema change embedded in the binary
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+impl Migration {
+    /// SHA-256 of the migration SQL, recorded when applied to detect later edits
+    pub fn checksum(&self) -> String {
+        Sha256::digest(self.sql.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+macro_rules! migration {
+    ($version:expr, $name:expr, $dialect:expr, $file:expr) => {
+        Migration {
+            version: $version,
+            name: $name,
+            sql: include_str!(concat!("../database/migrations/", $dialect, "/", $file)),
+        }
+    };
+}
+
+pub const MYSQL_MIGRATIONS: &[Migration] = &[
+    migration!(1, "initial_schema", "mysql", "0001_initial_schema.sql"),
+    migration!(2, "unique_profile_email", "mysql", "0002_unique_profile_email.sql"),
+    migration!(3, "unique_metadata_property", "mysql", "0003_unique_metadata_property.sql"),
+    migration!(4, "metadata_search", "mysql", "0004_metadata_search.sql"),
+    migration!(5, "profile_names", "mysql", "0005_profile_names.sql"),
+];
+
+pub const SQLITE_MIGRATIONS: &[Migration] = &[
+    migration!(1, "initial_schema", "sqlite", "0001_initial_schema.sql"),
+    migration!(2, "unique_profile_email", "sqlite", "0002_unique_profile_email.sql"),
+    migration!(3, "unique_metadata_property", "sqlite", "0003_unique_metadata_property.sql"),
+    migration!(4, "metadata_search", "sqlite", "0004_metadata_search.sql"),
+    migration!(5, "profile_names", "sqlite", "0005_profile_names.sql"),
+];
+
+pub const POSTGRES_MIGRATIONS: &[Migration] = &[
+    migration!(1, "initial_schema", "postgres", "0001_initial_schema.sql"),
+    migration!(2, "unique_profile_email", "postgres", "0002_unique_profile_email.sql"),
+    migration!(3, "unique_metadata_property", "postgres", "0003_unique_metadata_property.sql"),
+    migration!(4, "metadata_search", "postgres", "0004_metadata_search.sql"),
+    migration!(5, "profile_names", "postgres", "0005_profile_names.sql"),
+];
+
+/// Bookkeeping table shared by every dialect
+pub const SCHEMA_MIGRATIONS_TABLE: &str = "
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version BIGINT PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    checksum VARCHAR(64) NOT NULL,
+    applied_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+)";
+
+/// Outcome of a migration run
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    pub applied: Vec<i64>,
+    pub already_applied: Vec<i64>,
+}
+
+/// Whether the service should apply pending migrations before serving (`DATABASE_MIGRATE_ON_START`)
+pub fn migrate_on_start() -> bool {
+    matches!(
+        std::env::var("DATABASE_MIGRATE_ON_START").as_deref(),
+        Ok("1") | Ok("true") | Ok("TRUE") | Ok("yes")
+    )
+}
@@ -0,0 +1,128 @@
+use actix_web::http::header;
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use minijinja::{Environment, Value};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::CreateUserPayload;
+
+/// Page templates compiled into the binary. Names ending in `.html` are autoescaped.
+const TEMPLATES: &[(&str, &str)] = &[
+    ("layout.html", include_str!("../templates/pages/layout.html")),
+    ("forms.html", include_str!("../templates/pages/forms.html")),
+    ("login.html", include_str!("../templates/pages/login.html")),
+    ("signup.html", include_str!("../templates/pages/signup.html")),
+    ("profile.html", include_str!("../templates/pages/profile.html")),
+];
+
+/// Server-rendered login, signup and profile pages. Their forms post straight back to
+/// the service, so they work with JavaScript disabled.
+pub struct Pages {
+    env: Environment<'static>,
+}
+
+impl Default for Pages {
+    fn default() -> Self {
+        let mut env = Environment::new();
+        for (name, source) in TEMPLATES {
+            env.add_template(name, source).expect("page template parses");
+        }
+        Pages { env }
+    }
+}
+
+impl Pages {
+    /// HTML for page `name` rendered with `ctx`
+    pub fn render(&self, name: &str, ctx: Value) -> Result<String, minijinja::Error> {
+        self.env.get_template(name)?.render(ctx)
+    }
+}
+
+/// 303 See Other to `location`, so the browser follows up a form post with a GET
+pub fn redirect(location: &str) -> HttpResponseBuilder {
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header((header::LOCATION, location));
+    builder
+}
+
+/// Body of `POST /login`; missing fields are reported on the page rather than rejected
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct LoginForm {
+    pub username: String,
+    pub password: String,
+    pub csrf_token: String,
+}
+
+/// Body of `POST /signup`. Serializes without the password to refill the form.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SignupForm {
+    pub username: String,
+    #[serde(skip_serializing)]
+    pub password: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub email: String,
+    pub title: String,
+    pub hobby: String,
+    #[serde(skip_serializing)]
+    pub csrf_token: String,
+}
+
+/// Body of `POST /logout`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct LogoutForm {
+    pub csrf_token: String,
+}
+
+// Debug output ends up in logs and panic messages, so it leaves out the password
+
+impl fmt::Debug for LoginForm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoginForm")
+            .field("username", &self.username)
+            .field("password", &"[REDACTED]")
+            .field("csrf_token", &self.csrf_token)
+            .finish()
+    }
+}
+
+impl fmt::Debug for SignupForm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SignupForm")
+            .field("username", &self.username)
+            .field("password", &"[REDACTED]")
+            .field("first_name", &self.first_name)
+            .field("last_name", &self.last_name)
+            .field("email", &self.email)
+            .field("title", &self.title)
+            .field("hobby", &self.hobby)
+            .field("csrf_token", &self.csrf_token)
+            .finish()
+    }
+}
+
+impl SignupForm {
+    /// The form as an API payload; fields left empty are not set
+    pub fn to_payload(&self) -> CreateUserPayload {
+        let optional = |value: &str| (!value.trim().is_empty()).then(|| value.to_string());
+        let list = |value: &str| optional(value).into_iter().collect();
+        CreateUserPayload {
+            username: self.username.clone(),
+            password: self.password.clone(),
+            first_name: optional(&self.first_name),
+            middle_name: None,
+            last_name: optional(&self.last_name),
+            nickname: None,
+            honorific: None,
+            suffix: None,
+            display_name: None,
+            email: optional(&self.email),
+            title: list(&self.title),
+            hobby: list(&self.hobby),
+            extra_metadata: None,
+        }
+    }
+}
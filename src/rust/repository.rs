@@ -0,0 +1,235 @@
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use crate::db::{check_schema, CreateUserRequest, Database, DatabaseError, User, UserMetadata};
+use crate::metadata::{self, MetadataNode, MetadataSubtree};
+use crate::metadata_schema::MetadataSchema;
+use crate::metadata_search::MetadataQuery;
+
+/// Storage operations the HTTP handlers need, independent of the backing store
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    /// Insert a user with its profile and metadata, returning the new ID
+    async fn create_user(&self, user: &CreateUserRequest) -> Result<i32, DatabaseError>;
+
+    /// Look up `(id, password)` by username
+    async fn authenticate_user(&self, username: &str) -> Result<(i32, String), DatabaseError>;
+
+    /// Load a user with profile and metadata
+    async fn find_user_by_id(&self, id: i32) -> Result<User, DatabaseError>;
+
+    /// Load several users at once, in the order of `ids`, skipping IDs that don't exist
+    async fn find_users_by_ids(&self, ids: &[i32]) -> Result<Vec<User>, DatabaseError>;
+
+    /// Users whose metadata satisfies `query`, in ID order
+    async fn find_users_by_metadata(&self, query: &MetadataQuery) -> Result<Vec<User>, DatabaseError>;
+
+    /// Load the user's metadata subtree rooted at `property`
+    async fn find_metadata_subtree(&self, user_id: i32, property: &str) -> Result<MetadataSubtree, DatabaseError>;
+
+    /// Replace the user's metadata subtree rooted at `node.property`, or add it at the top
+    /// level, returning the user's rows afterwards. The read, the `schema` check of the
+    /// resulting tree and the write are one atomic step.
+    async fn replace_metadata_subtree(
+        &self,
+        user_id: i32,
+        node: &MetadataNode,
+        schema: &MetadataSchema,
+    ) -> Result<Vec<UserMetadata>, DatabaseError>;
+
+    /// Delete the user's metadata subtree rooted at `property`, checking the remaining
+    /// tree against `schema` in the same atomic step
+    async fn delete_metadata_subtree(&self, user_id: i32, property: &str, schema: &MetadataSchema) -> Result<(), DatabaseError>;
+
+    /// Replace all of the user's metadata with `nodes`, which must satisfy `schema`
+    async fn replace_metadata(&self, user_id: i32, nodes: &[MetadataNode], schema: &MetadataSchema) -> Result<(), DatabaseError>;
+
+    /// Open and idle connections, for stores backed by a connection pool
+    fn pool_stats(&self) -> Option<(u32, usize)> {
+        None
+    }
+}
+
+#[async_trait]
+impl UserRepository for Database {
+    async fn create_user(&self, user: &CreateUserRequest) -> Result<i32, DatabaseError> {
+        Database::create_user(self, user).await
+    }
+
+    async fn authenticate_user(&self, username: &str) -> Result<(i32, String), DatabaseError> {
+        Database::authenticate_user(self, username).await
+    }
+
+    async fn find_user_by_id(&self, id: i32) -> Result<User, DatabaseError> {
+        Database::find_user_by_id(self, id).await
+    }
+
+    async fn find_users_by_ids(&self, ids: &[i32]) -> Result<Vec<User>, DatabaseError> {
+        Database::find_users_by_ids(self, ids).await
+    }
+
+    async fn find_users_by_metadata(&self, query: &MetadataQuery) -> Result<Vec<User>, DatabaseError> {
+        Database::find_users_by_metadata(self, query).await
+    }
+
+    async fn find_metadata_subtree(&self, user_id: i32, property: &str) -> Result<MetadataSubtree, DatabaseError> {
+        Database::find_metadata_subtree(self, user_id, property).await
+    }
+
+    async fn replace_metadata_subtree(
+        &self,
+        user_id: i32,
+        node: &MetadataNode,
+        schema: &MetadataSchema,
+    ) -> Result<Vec<UserMetadata>, DatabaseError> {
+        Database::replace_metadata_subtree(self, user_id, node, schema).await
+    }
+
+    async fn delete_metadata_subtree(&self, user_id: i32, property: &str, schema: &MetadataSchema) -> Result<(), DatabaseError> {
+        Database::delete_metadata_subtree(self, user_id, property, schema).await
+    }
+
+    async fn replace_metadata(&self, user_id: i32, nodes: &[MetadataNode], schema: &MetadataSchema) -> Result<(), DatabaseError> {
+        Database::replace_metadata(self, user_id, nodes, schema).await
+    }
+
+    fn pool_stats(&self) -> Option<(u32, usize)> {
+        Some(Database::pool_stats(self))
+    }
+}
+
+/// Process-local store with the same semantics as the SQL schema (unique usernames
+/// and profile emails, sequential IDs). Nothing is persisted.
+#[derive(Default)]
+pub struct InMemoryUserRepository {
+    users: Mutex<HashMap<i32, User>>,
+}
+
+// Only the tests construct an in-memory store for now
+#[cfg_attr(not(test), allow(dead_code))]
+impl InMemoryUserRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl UserRepository for InMemoryUserRepository {
+    async fn create_user(&self, user: &CreateUserRequest) -> Result<i32, DatabaseError> {
+        metadata::validate(&user.metadata)?;
+        let mut users = self.users.lock().unwrap();
+
+        if users.values().any(|u| u.username == user.username) {
+            return Err(DatabaseError::DuplicateUsername);
+        }
+
+        let email = user.profile.as_ref().and_then(|p| p.email.as_deref());
+        if email.is_some() && users.values().any(|u| u.profile.as_ref().and_then(|p| p.email.as_deref()) == email) {
+            return Err(DatabaseError::DuplicateEmail);
+        }
+
+        let id = users.keys().max().copied().unwrap_or(0) + 1;
+        let now = chrono::Utc::now().naive_utc();
+        users.insert(
+            id,
+            User {
+                id,
+                username: user.username.clone(),
+                password: user.password.clone(),
+                created_at: now,
+                updated_at: now,
+                profile: user.profile.clone(),
+                metadata: user.metadata.clone(),
+            },
+        );
+
+        Ok(id)
+    }
+
+    async fn authenticate_user(&self, username: &str) -> Result<(i32, String), DatabaseError> {
+        self.users
+            .lock()
+            .unwrap()
+            .values()
+            .find(|u| u.username == username)
+            .map(|u| (u.id, u.password.clone()))
+            .ok_or(DatabaseError::UserNotFound)
+    }
+
+    async fn find_user_by_id(&self, id: i32) -> Result<User, DatabaseError> {
+        self.users
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or(DatabaseError::UserNotFound)
+    }
+
+    async fn find_users_by_ids(&self, ids: &[i32]) -> Result<Vec<User>, DatabaseError> {
+        let users = self.users.lock().unwrap();
+        let mut seen = HashSet::new();
+        Ok(ids
+            .iter()
+            .filter(|id| seen.insert(**id))
+            .filter_map(|id| users.get(id).cloned())
+            .collect())
+    }
+
+    async fn find_users_by_metadata(&self, query: &MetadataQuery) -> Result<Vec<User>, DatabaseError> {
+        query.validate()?;
+        let mut ids: Vec<i32> = self
+            .users
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|u| query.matches(&u.metadata))
+            .map(|u| u.id)
+            .collect();
+        ids.sort_unstable();
+        let page: Vec<i32> = ids.into_iter().skip(query.offset as usize).take(query.limit as usize).collect();
+        self.find_users_by_ids(&page).await
+    }
+
+    async fn find_metadata_subtree(&self, user_id: i32, property: &str) -> Result<MetadataSubtree, DatabaseError> {
+        let users = self.users.lock().unwrap();
+        let user = users.get(&user_id).ok_or(DatabaseError::UserNotFound)?;
+        metadata::subtree(&user.metadata, property)?.ok_or_else(|| DatabaseError::MetadataNotFound(property.to_string()))
+    }
+
+    // The mutex is held from the read through the schema check to the write
+
+    async fn replace_metadata_subtree(
+        &self,
+        user_id: i32,
+        node: &MetadataNode,
+        schema: &MetadataSchema,
+    ) -> Result<Vec<UserMetadata>, DatabaseError> {
+        let mut users = self.users.lock().unwrap();
+        let user = users.get_mut(&user_id).ok_or(DatabaseError::UserNotFound)?;
+        let (removed, inserted) = metadata::replace_subtree(&user.metadata, node)?;
+        let rows = metadata::apply(&user.metadata, &removed, &inserted);
+        check_schema(schema, &rows)?;
+        user.metadata = rows.clone();
+        Ok(rows)
+    }
+
+    async fn delete_metadata_subtree(&self, user_id: i32, property: &str, schema: &MetadataSchema) -> Result<(), DatabaseError> {
+        let mut users = self.users.lock().unwrap();
+        let user = users.get_mut(&user_id).ok_or(DatabaseError::UserNotFound)?;
+        let rows = metadata::without_subtree(&user.metadata, property)?
+            .ok_or_else(|| DatabaseError::MetadataNotFound(property.to_string()))?;
+        check_schema(schema, &rows)?;
+        user.metadata = rows;
+        Ok(())
+    }
+
+    async fn replace_metadata(&self, user_id: i32, nodes: &[MetadataNode], schema: &MetadataSchema) -> Result<(), DatabaseError> {
+        let rows = metadata::flatten_tree(nodes)?;
+        let mut users = self.users.lock().unwrap();
+        let user = users.get_mut(&user_id).ok_or(DatabaseError::UserNotFound)?;
+        check_schema(schema, &rows)?;
+        user.metadata = rows;
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use crate::metadata::MetadataNode;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum UnsafeTextError {
+    #[error("{field} contains a control character (U+{code:04X})")]
+    Control { field: String, code: u32 },
+    #[error("{field} contains a bidirectional text control (U+{code:04X})")]
+    Bidi { field: String, code: u32 },
+}
+
+/// Bidirectional embeddings, overrides and isolates (U+202A..U+202E, U+2066..U+2069),
+/// which can make displayed text read differently from what is stored
+pub fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+/// Tabs and line breaks, including NEL and the Unicode line and paragraph separators
+pub fn is_line_break_or_tab(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\u{0B}' | '\u{0C}' | '\r' | '\u{85}' | '\u{2028}' | '\u{2029}')
+}
+
+/// Normalize a single-line field: tabs and line breaks become spaces and surrounding
+/// whitespace is trimmed. Any other control character or a bidi control is rejected.
+pub fn clean_field(field: &str, value: &str) -> Result<String, UnsafeTextError> {
+    let mut cleaned = String::with_capacity(value.len());
+    for c in value.chars() {
+        if is_line_break_or_tab(c) {
+            cleaned.push(' ');
+        } else if c.is_control() {
+            return Err(UnsafeTextError::Control { field: field.to_string(), code: c as u32 });
+        } else if is_bidi_control(c) {
+            return Err(UnsafeTextError::Bidi { field: field.to_string(), code: c as u32 });
+        } else {
+            cleaned.push(c);
+        }
+    }
+    Ok(cleaned.trim().to_string())
+}
+
+/// [`clean_field`] for an optional value
+pub fn clean_optional(field: &str, value: Option<String>) -> Result<Option<String>, UnsafeTextError> {
+    value.map(|value| clean_field(field, &value)).transpose()
+}
+
+/// Reject a name containing any control character, tabs and line breaks included, or a bidi
+/// control. Names are not normalized: that would change which property they address.
+pub fn check_name(field: &str, name: &str) -> Result<(), UnsafeTextError> {
+    for c in name.chars() {
+        if c.is_control() || is_line_break_or_tab(c) {
+            return Err(UnsafeTextError::Control { field: field.to_string(), code: c as u32 });
+        } else if is_bidi_control(c) {
+            return Err(UnsafeTextError::Bidi { field: field.to_string(), code: c as u32 });
+        }
+    }
+    Ok(())
+}
+
+/// [`check_name`] for every property name and [`clean_field`] for every value in a metadata
+/// tree; errors name the value's dotted path, or the parent of a rejected property name
+pub fn clean_metadata(nodes: &mut [MetadataNode], parent: Option<&str>) -> Result<(), UnsafeTextError> {
+    for node in nodes {
+        let name_field = match parent {
+            Some(parent) => format!("metadata property name under {}", parent),
+            None => "metadata property name".to_string(),
+        };
+        check_name(&name_field, &node.property)?;
+
+        let path = match parent {
+            Some(parent) => format!("{}.{}", parent, node.property),
+            None => node.property.clone(),
+        };
+        node.value = clean_optional(&path, node.value.take())?;
+        clean_metadata(&mut node.children, Some(&path))?;
+    }
+    Ok(())
+}
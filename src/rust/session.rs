@@ -0,0 +1,222 @@
+use actix_web::cookie::time::Duration;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::HttpRequest;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+/// Cookie holding the signed-in user of the server-rendered pages
+pub const SESSION_COOKIE: &str = "session";
+
+/// Cookie holding the CSRF token the server-rendered forms post back
+pub const CSRF_COOKIE: &str = "csrf";
+
+/// How long a sign-in lasts, in seconds
+pub const SESSION_TTL_SECS: i64 = 12 * 60 * 60;
+
+/// Shortest `SESSION_SECRET` accepted, in bytes
+const MIN_SECRET_LEN: usize = 32;
+
+/// Signs and checks session cookies and CSRF tokens. A session cookie is
+/// `{user_id}.{expires}.{signature}`, where `expires` is a Unix timestamp; a CSRF token is
+/// `{nonce}.{signature}`, signed together with the session it was issued to (the session
+/// cookie's value, or none before sign-in). Signatures are hex HMAC-SHA256 over the value and
+/// its purpose, so one kind of value can't stand in for the other.
+pub struct SessionKey {
+    key: Vec<u8>,
+    secure: bool,
+    same_site: SameSite,
+}
+
+impl Default for SessionKey {
+    /// A random key: sessions end when the process restarts
+    fn default() -> Self {
+        let mut key = vec![0u8; MIN_SECRET_LEN];
+        rand::thread_rng().fill_bytes(&mut key);
+        SessionKey { key, secure: false, same_site: SameSite::Lax }
+    }
+}
+
+impl SessionKey {
+    /// Sign with `SESSION_SECRET`, or a random key when it is unset. `SESSION_COOKIE_SECURE=true`
+    /// marks cookies `Secure` for deployments behind HTTPS; `SESSION_COOKIE_SAMESITE` is
+    /// `lax` (default), `strict` or `none`, which needs `Secure`.
+    pub fn from_env() -> Result<Self, String> {
+        let key = match std::env::var("SESSION_SECRET") {
+            Ok(secret) if !secret.is_empty() => Self::new(secret)?,
+            _ => Self::default(),
+        };
+        let secure = std::env::var("SESSION_COOKIE_SECURE").is_ok_and(|v| v.trim().eq_ignore_ascii_case("true"));
+        let same_site = match std::env::var("SESSION_COOKIE_SAMESITE") {
+            Ok(value) if !value.trim().is_empty() => parse_same_site(&value)?,
+            _ => SameSite::Lax,
+        };
+        key.with_cookie_policy(secure, same_site)
+    }
+
+    /// Sign with `secret`, which must be at least 32 bytes
+    pub fn new(secret: impl Into<Vec<u8>>) -> Result<Self, String> {
+        let key = secret.into();
+        if key.len() < MIN_SECRET_LEN {
+            return Err(format!("SESSION_SECRET must be at least {} bytes", MIN_SECRET_LEN));
+        }
+        Ok(SessionKey { key, secure: false, same_site: SameSite::Lax })
+    }
+
+    /// The key issuing session cookies with `Secure` set to `secure` and the given
+    /// `SameSite`; browsers drop `SameSite=None` cookies that are not `Secure`
+    pub fn with_cookie_policy(mut self, secure: bool, same_site: SameSite) -> Result<Self, String> {
+        if same_site == SameSite::None && !secure {
+            return Err("SESSION_COOKIE_SAMESITE=none requires SESSION_COOKIE_SECURE=true".to_string());
+        }
+        self.secure = secure;
+        self.same_site = same_site;
+        Ok(self)
+    }
+
+    /// Cookie signing in `user_id` for `SESSION_TTL_SECS`
+    pub fn cookie(&self, user_id: i32) -> Cookie<'static> {
+        let value = self.sign(user_id, chrono::Utc::now().timestamp() + SESSION_TTL_SECS);
+        self.build(SESSION_COOKIE, value, self.same_site)
+            .max_age(Duration::seconds(SESSION_TTL_SECS))
+            .finish()
+    }
+
+    /// Cookies signing in `user_id`: the session cookie and a CSRF cookie bound to it, which
+    /// replaces the token issued before sign-in
+    pub fn sign_in(&self, user_id: i32) -> [Cookie<'static>; 2] {
+        let session = self.cookie(user_id);
+        let csrf = self.csrf_cookie(self.new_csrf_token(session.value()));
+        [session, csrf]
+    }
+
+    /// Cookie value signing in `user_id` until `expires` (a Unix timestamp)
+    pub fn sign(&self, user_id: i32, expires: i64) -> String {
+        self.signed(SESSION_COOKIE, &format!("{}.{}", user_id, expires))
+    }
+
+    /// Cookie that signs the user out
+    pub fn removal_cookie(&self) -> Cookie<'static> {
+        self.build(SESSION_COOKIE, String::new(), self.same_site)
+            .max_age(Duration::ZERO)
+            .finish()
+    }
+
+    /// The signed-in user, when the request has an unexpired cookie with a valid signature
+    pub fn user_id(&self, req: &HttpRequest) -> Option<i32> {
+        let cookie = req.cookie(SESSION_COOKIE)?;
+        let (user_id, expires) = self.verified(SESSION_COOKIE, cookie.value())?.split_once('.')?;
+        if expires.parse::<i64>().ok()? <= chrono::Utc::now().timestamp() {
+            return None;
+        }
+        user_id.parse().ok()
+    }
+
+    /// CSRF token for the forms on a page: the one in the request's cookie when it was issued
+    /// to the request's session, otherwise a new one with the `SameSite=Strict` cookie that
+    /// carries it
+    pub fn csrf_token(&self, req: &HttpRequest) -> (String, Option<Cookie<'static>>) {
+        let session = self.session_id(req);
+        if let Some(cookie) = req.cookie(CSRF_COOKIE) {
+            if self.csrf_valid(&session, cookie.value()) {
+                return (cookie.value().to_string(), None);
+            }
+        }
+
+        let token = self.new_csrf_token(&session);
+        (token.clone(), Some(self.csrf_cookie(token)))
+    }
+
+    /// Whether a posted form's `submitted` token matches the request's CSRF cookie and was
+    /// issued with this key to the request's session (double-submit)
+    pub fn verify_csrf(&self, req: &HttpRequest, submitted: &str) -> bool {
+        req.cookie(CSRF_COOKIE).is_some_and(|cookie| {
+            cookie.value() == submitted && self.csrf_valid(&self.session_id(req), submitted)
+        })
+    }
+
+    /// The session a request's CSRF token must belong to: its session cookie's value when
+    /// that signs a user in, `""` otherwise
+    fn session_id(&self, req: &HttpRequest) -> String {
+        match (self.user_id(req), req.cookie(SESSION_COOKIE)) {
+            (Some(_), Some(cookie)) => cookie.value().to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// A random `{nonce}.{signature}` token for `session`
+    fn new_csrf_token(&self, session: &str) -> String {
+        let mut nonce = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let nonce = hex(&nonce);
+        let signature = hex(&self.csrf_mac(session, &nonce).finalize().into_bytes());
+        format!("{}.{}", nonce, signature)
+    }
+
+    /// Whether `token` was issued with this key to `session`
+    fn csrf_valid(&self, session: &str, token: &str) -> bool {
+        let Some((nonce, signature)) = token.rsplit_once('.') else {
+            return false;
+        };
+        unhex(signature).is_some_and(|signature| self.csrf_mac(session, nonce).verify_slice(&signature).is_ok())
+    }
+
+    /// The session and the nonce, split by a byte neither contains
+    fn csrf_mac(&self, session: &str, nonce: &str) -> Hmac<Sha256> {
+        self.mac(CSRF_COOKIE, &format!("{}\0{}", session, nonce))
+    }
+
+    fn csrf_cookie(&self, token: String) -> Cookie<'static> {
+        self.build(CSRF_COOKIE, token, SameSite::Strict).finish()
+    }
+
+    /// `{payload}.{signature}` for a value used as `purpose`
+    fn signed(&self, purpose: &str, payload: &str) -> String {
+        let signature = hex(&self.mac(purpose, payload).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    /// The payload of a `signed` value, when its signature for `purpose` is valid
+    fn verified<'a>(&self, purpose: &str, value: &'a str) -> Option<&'a str> {
+        let (payload, signature) = value.rsplit_once('.')?;
+        self.mac(purpose, payload).verify_slice(&unhex(signature)?).ok()?;
+        Some(payload)
+    }
+
+    fn mac(&self, purpose: &str, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(purpose.as_bytes());
+        mac.update(b"\0");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    fn build(&self, name: &'static str, value: String, same_site: SameSite) -> actix_web::cookie::CookieBuilder<'static> {
+        Cookie::build(name, value)
+            .path("/")
+            .http_only(true)
+            .same_site(same_site)
+            .secure(self.secure)
+    }
+}
+
+fn parse_same_site(value: &str) -> Result<SameSite, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "lax" => Ok(SameSite::Lax),
+        "strict" => Ok(SameSite::Strict),
+        "none" => Ok(SameSite::None),
+        other => Err(format!("SESSION_COOKIE_SAMESITE must be lax, strict or none, got '{}'", other)),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Bytes of a hex string; `None` for odd lengths or non-hex digits
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,160 @@
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::HeaderMap;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::{TraceContextExt as _, TracerProvider as _};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use tracing::field::Empty;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Handle to the tracer provider; flushes pending spans on shutdown
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Telemetry {
+    /// Install OTLP/HTTP span export when `OTEL_EXPORTER_OTLP_ENDPOINT` is set
+    /// (e.g. `http://localhost:4318`). Without it, spans are no-ops.
+    pub fn init() -> Result<Self, String> {
+        if std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_err() {
+            return Ok(Telemetry { provider: None });
+        }
+
+        // The exporter reads the endpoint, headers and timeout from the standard OTEL_* variables
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        let service_name =
+            std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "rust_user_service".to_string());
+
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(service_name).build())
+            .build();
+
+        install_subscriber(&provider)?;
+
+        Ok(Telemetry {
+            provider: Some(provider),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.provider.is_some()
+    }
+
+    pub fn shutdown(self) {
+        if let Some(provider) = self.provider {
+            let _ = provider.shutdown();
+        }
+    }
+}
+
+/// Build a subscriber that forwards `tracing` spans to the given provider
+pub fn subscriber(provider: &SdkTracerProvider) -> impl tracing::Subscriber + Send + Sync {
+    let tracer = provider.tracer("rust_user_service");
+    tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+fn install_subscriber(provider: &SdkTracerProvider) -> Result<(), String> {
+    tracing::subscriber::set_global_default(subscriber(provider)).map_err(|e| e.to_string())
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Middleware that opens a server span per request, continuing the caller's
+/// trace when a W3C `traceparent` header is present
+pub struct RequestTracing;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestTracing
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequestTracingMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTracingMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestTracingMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTracingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let parent = TraceContextPropagator::new().extract(&HeaderExtractor(req.headers()));
+        let method = req.method().to_string();
+
+        let span = tracing::info_span!(
+            "http_request",
+            otel.name = %method,
+            otel.kind = "server",
+            http.request.method = %method,
+            url.path = %req.path(),
+            http.route = Empty,
+            http.response.status_code = Empty,
+            otel.status_code = Empty,
+        );
+        let _ = span.set_parent(parent);
+
+        // Resolved before the call because an error carries no request
+        let route = req.match_pattern();
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let result = service.call(req).instrument(span.clone()).await;
+
+            if let Some(route) = route {
+                span.context().span().update_name(format!("{} {}", method, route));
+                span.record("http.route", route);
+            }
+            match &result {
+                Ok(res) => {
+                    span.record("http.response.status_code", res.status().as_u16());
+                }
+                Err(e) => {
+                    span.record("http.response.status_code", e.as_response_error().status_code().as_u16());
+                    span.record("otel.status_code", "ERROR");
+                }
+            }
+
+            result
+        })
+    }
+}
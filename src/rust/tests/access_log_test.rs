@@ -0,0 +1,86 @@
+use super::TEST_MUTEX;
+use crate::access_log::{AccessLogEntry, AccessLogFormat, TrustedProxies};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use chrono::TimeZone;
+
+fn sample_entry() -> AccessLogEntry {
+    AccessLogEntry {
+        client_ip: "192.168.1.10".to_string(),
+        method: "GET".to_string(),
+        path: "/api/users/{user_id}".to_string(),
+        protocol: "HTTP/1.1".to_string(),
+        status: 200,
+        size: Some(87),
+        latency_ms: 1.5,
+        timestamp: chrono::Utc.with_ymd_and_hms(2026, 2, 12, 10, 30, 0).unwrap(),
+    }
+}
+
+#[test]
+fn test_common_log_format() {
+    let line = sample_entry().format(AccessLogFormat::Common);
+    assert_eq!(
+        line,
+        "192.168.1.10 - - [12/Feb/2026:10:30:00 +0000] \"GET /api/users/{user_id} HTTP/1.1\" 200 87 1.500ms"
+    );
+}
+
+#[test]
+fn test_common_log_format_unknown_size() {
+    let mut entry = sample_entry();
+    entry.size = None;
+    let line = entry.format(AccessLogFormat::Common);
+    assert!(line.contains("\" 200 - "));
+}
+
+#[test]
+fn test_json_log_format() {
+    let line = sample_entry().format(AccessLogFormat::Json);
+    let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(value["method"], "GET");
+    assert_eq!(value["path"], "/api/users/{user_id}");
+    assert_eq!(value["status"], 200);
+    assert_eq!(value["size"], 87);
+    assert_eq!(value["client_ip"], "192.168.1.10");
+    assert_eq!(value["latency_ms"], 1.5);
+}
+
+fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    for (name, value) in pairs {
+        map.append(HeaderName::from_static(name), HeaderValue::from_static(value));
+    }
+    map
+}
+
+#[test]
+fn test_client_ip_ignores_forwarding_headers_from_untrusted_peers() {
+    let proxies = TrustedProxies::new(vec!["10.0.0.1".parse().unwrap()]);
+    let spoofed = headers(&[("x-forwarded-for", "1.2.3.4"), ("forwarded", "for=5.6.7.8")]);
+    assert_eq!(proxies.client_ip(Some("192.0.2.10:40000".parse().unwrap()), &spoofed), "192.0.2.10");
+    assert_eq!(TrustedProxies::default().client_ip(None, &spoofed), "-");
+}
+
+#[test]
+fn test_client_ip_skips_trusted_proxies_in_forwarded_for() {
+    let proxies = TrustedProxies::new(vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()]);
+    let peer = Some("10.0.0.1:40000".parse().unwrap());
+
+    // The leftmost entry is whatever the client sent; the nearest untrusted hop is the client
+    let chain = headers(&[("x-forwarded-for", "1.2.3.4, 198.51.100.7"), ("x-forwarded-for", "10.0.0.2")]);
+    assert_eq!(proxies.client_ip(peer, &chain), "198.51.100.7");
+
+    let forwarded = headers(&[("forwarded", r#"for=1.2.3.4, for="[2001:db8::7]:4711";proto=https, for=10.0.0.2"#)]);
+    assert_eq!(proxies.client_ip(peer, &forwarded), "2001:db8::7");
+
+    assert_eq!(proxies.client_ip(peer, &HeaderMap::new()), "10.0.0.1");
+}
+
+#[test]
+fn test_trusted_proxies_from_env_rejects_invalid_addresses() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    std::env::set_var("ACCESS_LOG_TRUSTED_PROXIES", "10.0.0.1, proxy.internal");
+    let result = TrustedProxies::from_env();
+    std::env::remove_var("ACCESS_LOG_TRUSTED_PROXIES");
+    assert!(result.unwrap_err().contains("proxy.internal"));
+}
@@ -0,0 +1,115 @@
+use super::TEST_MUTEX;
+use crate::cors::{AllowedOrigin, CorsConfig, CorsPolicy};
+
+/// Set `vars` (prefixed by `prefix`) and build the `api` policy from them. Each test uses
+/// its own prefix, so tests running in parallel don't see each other's variables.
+fn api_policy(prefix: &str, vars: &[(&str, &str)]) -> Result<CorsPolicy, String> {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    for (name, value) in vars {
+        std::env::set_var(format!("{}_{}", prefix, name), value);
+    }
+    CorsPolicy::api().with_env(prefix)
+}
+
+#[test]
+fn test_origin_patterns_parse() {
+    assert_eq!(AllowedOrigin::parse("*"), Ok(AllowedOrigin::Any));
+    assert_eq!(
+        AllowedOrigin::parse(" https://App.Example.com/ "),
+        Ok(AllowedOrigin::Exact("https://app.example.com".to_string()))
+    );
+    assert_eq!(
+        AllowedOrigin::parse("https://*.example.com:8443"),
+        Ok(AllowedOrigin::Subdomains { scheme: "https".to_string(), domain: "example.com:8443".to_string() })
+    );
+
+    for invalid in ["example.com", "ftp://example.com", "https://", "https://example.com/app", "https://a.*.example.com", "https://*."] {
+        assert!(AllowedOrigin::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn test_wildcard_origins_match_subdomains_only() {
+    let pattern = AllowedOrigin::parse("https://*.example.com").unwrap();
+    assert!(pattern.matches("https://app.example.com"));
+    assert!(pattern.matches("https://EU.App.example.com"));
+
+    for other in [
+        "https://example.com",
+        "http://app.example.com",
+        "https://app.example.com:8443",
+        "https://evilexample.com",
+        "https://app.example.com.evil.test",
+        "https://evil.test/.example.com",
+        "https://.example.com",
+    ] {
+        assert!(!pattern.matches(other), "{}", other);
+    }
+
+    let exact = AllowedOrigin::parse("https://app.example.com").unwrap();
+    assert!(exact.matches("https://APP.example.com"));
+    assert!(!exact.matches("https://app.example.com:443"));
+}
+
+#[test]
+fn test_default_policies() {
+    let config = CorsConfig::default();
+    assert!(config.public.allows_origin("https://anywhere.test"));
+    assert!(!config.api.allows_origin("https://anywhere.test"));
+    assert!(!config.admin.allows_origin("https://anywhere.test"));
+}
+
+#[test]
+fn test_policy_from_env() {
+    let policy = api_policy(
+        "TEST_CORS_ENV",
+        &[
+            ("ALLOWED_ORIGINS", "https://app.example.com, https://*.partner.test"),
+            ("ALLOWED_METHODS", "get,post"),
+            ("ALLOWED_HEADERS", "Content-Type, X-Request-Id"),
+            ("ALLOW_CREDENTIALS", "true"),
+            ("MAX_AGE", "600"),
+        ],
+    )
+    .unwrap();
+    assert!(policy.allows_origin("https://app.example.com"));
+    assert!(policy.allows_origin("https://shop.partner.test"));
+    assert!(!policy.allows_origin("https://other.example.com"));
+}
+
+#[test]
+fn test_public_policy_from_env() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    std::env::set_var("CORS_PUBLIC_ALLOWED_ORIGINS", "https://status.example.com");
+    std::env::set_var("CORS_PUBLIC_MAX_AGE", "60");
+    let config = CorsConfig::from_env();
+    std::env::remove_var("CORS_PUBLIC_ALLOWED_ORIGINS");
+    std::env::remove_var("CORS_PUBLIC_MAX_AGE");
+
+    let public = config.unwrap().public;
+    assert!(public.allows_origin("https://status.example.com"));
+    assert!(!public.allows_origin("https://anywhere.test"));
+
+    // Unset, it stays open to every origin
+    assert!(CorsConfig::from_env().unwrap().public.allows_origin("https://anywhere.test"));
+}
+
+#[test]
+fn test_invalid_policies_are_rejected() {
+    let error = api_policy("TEST_CORS_ORIGIN", &[("ALLOWED_ORIGINS", "app.example.com")]).unwrap_err();
+    assert!(error.starts_with("TEST_CORS_ORIGIN_ALLOWED_ORIGINS:"), "{}", error);
+
+    let error = api_policy("TEST_CORS_METHOD", &[("ALLOWED_METHODS", "GET, NOT A METHOD")]).unwrap_err();
+    assert!(error.contains("invalid method"), "{}", error);
+
+    let error = api_policy("TEST_CORS_HEADER", &[("ALLOWED_HEADERS", "Bad Header")]).unwrap_err();
+    assert!(error.contains("invalid header"), "{}", error);
+
+    let error = api_policy("TEST_CORS_AGE", &[("MAX_AGE", "soon")]).unwrap_err();
+    assert!(error.contains("TEST_CORS_AGE_MAX_AGE"), "{}", error);
+
+    // Credentials for every origin would let any site act as the signed-in user
+    let error = api_policy("TEST_CORS_CREDS", &[("ALLOWED_ORIGINS", "*"), ("ALLOW_CREDENTIALS", "true")]).unwrap_err();
+    assert!(error.contains("every origin"), "{}", error);
+    assert!(CorsPolicy::public().with_credentials(true).is_err());
+}
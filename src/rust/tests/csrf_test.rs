@@ -0,0 +1,106 @@
+use actix_web::http::header;
+use actix_web::test::TestRequest;
+
+use super::TEST_MUTEX;
+use crate::cors::CorsPolicy;
+use crate::csrf::{CsrfProtection, CsrfRejection};
+
+fn form_post() -> TestRequest {
+    TestRequest::post()
+        .insert_header(("host", "users.example.com"))
+        .insert_header((header::CONTENT_TYPE, "application/x-www-form-urlencoded"))
+}
+
+#[test]
+fn test_same_origin_form_posts_pass() {
+    let csrf = CsrfProtection::default();
+    let req = form_post().insert_header((header::ORIGIN, "http://users.example.com")).to_http_request();
+    assert_eq!(csrf.check(&req), Ok(()));
+
+    let req = form_post().insert_header((header::REFERER, "http://USERS.example.com/login?next=1")).to_http_request();
+    assert_eq!(csrf.check(&req), Ok(()));
+}
+
+#[test]
+fn test_cross_origin_form_posts_are_rejected() {
+    let csrf = CsrfProtection::default();
+    let req = form_post().insert_header((header::ORIGIN, "https://evil.example")).to_http_request();
+    assert_eq!(csrf.check(&req), Err(CsrfRejection::CrossOrigin("https://evil.example".to_string())));
+
+    // The Origin header wins over a same-origin Referer
+    let req = form_post()
+        .insert_header((header::ORIGIN, "https://evil.example"))
+        .insert_header((header::REFERER, "http://users.example.com/"))
+        .to_http_request();
+    assert!(csrf.check(&req).is_err());
+
+    // Same host on another scheme or port is another origin
+    for referer in ["https://users.example.com/", "http://users.example.com:8443/", "http://users.example.com.evil.example/"] {
+        let req = form_post().insert_header((header::REFERER, referer)).to_http_request();
+        assert!(csrf.check(&req).is_err(), "{}", referer);
+    }
+
+    let req = form_post().insert_header((header::ORIGIN, "null")).to_http_request();
+    assert_eq!(csrf.check(&req), Err(CsrfRejection::OpaqueOrigin));
+}
+
+#[test]
+fn test_every_simple_content_type_is_checked() {
+    let csrf = CsrfProtection::default();
+    for content_type in ["multipart/form-data; boundary=x", "text/plain;charset=UTF-8"] {
+        let req = TestRequest::post()
+            .insert_header((header::CONTENT_TYPE, content_type))
+            .insert_header((header::ORIGIN, "https://evil.example"))
+            .to_http_request();
+        assert!(csrf.check(&req).is_err(), "{}", content_type);
+    }
+
+    let req = TestRequest::delete().insert_header((header::ORIGIN, "https://evil.example")).to_http_request();
+    assert!(csrf.check(&req).is_err(), "a bodyless DELETE is checked too");
+}
+
+#[test]
+fn test_preflighted_and_safe_requests_are_not_checked() {
+    let csrf = CsrfProtection::default();
+    let req = TestRequest::post()
+        .insert_header((header::CONTENT_TYPE, "application/json"))
+        .insert_header((header::ORIGIN, "https://evil.example"))
+        .to_http_request();
+    assert_eq!(csrf.check(&req), Ok(()));
+
+    let req = TestRequest::get().insert_header((header::ORIGIN, "https://evil.example")).to_http_request();
+    assert_eq!(csrf.check(&req), Ok(()));
+
+    // Not a browser: nothing to forge
+    assert_eq!(csrf.check(&form_post().to_http_request()), Ok(()));
+}
+
+/// `CorsPolicy::api()` allowing `origins`, read from variables under `prefix`
+fn api_policy(prefix: &str, origins: &str) -> CorsPolicy {
+    let _guard = TEST_MUTEX.lock().unwrap();
+    std::env::set_var(format!("{}_ALLOWED_ORIGINS", prefix), origins);
+    let policy = CorsPolicy::api().with_env(prefix);
+    std::env::remove_var(format!("{}_ALLOWED_ORIGINS", prefix));
+    policy.unwrap()
+}
+
+#[test]
+fn test_origins_the_api_allows_are_trusted() {
+    let csrf = CsrfProtection::new(&api_policy("TEST_CSRF", "https://App.example.com/, https://*.partner.test"));
+    for origin in ["https://app.example.com", "https://eu.partner.test"] {
+        let req = form_post().insert_header((header::ORIGIN, origin)).to_http_request();
+        assert_eq!(csrf.check(&req), Ok(()), "{}", origin);
+    }
+
+    let req = form_post().insert_header((header::ORIGIN, "https://other.example.com")).to_http_request();
+    assert!(csrf.check(&req).is_err());
+    let req = form_post().insert_header((header::REFERER, "https://partner.test/form")).to_http_request();
+    assert!(csrf.check(&req).is_err());
+}
+
+#[test]
+fn test_an_api_open_to_every_origin_trusts_none() {
+    let csrf = CsrfProtection::new(&api_policy("TEST_CSRF_ANY", "*"));
+    let req = form_post().insert_header((header::ORIGIN, "https://evil.example")).to_http_request();
+    assert!(csrf.check(&req).is_err());
+}
@@ -0,0 +1,292 @@
+use crate::db::{from_driver_error, Backend, CreateUserRequest, Database, DatabaseError, UserMetadata, UserProfile};
+use sqlx::error::ErrorKind;
+use std::borrow::Cow;
+use crate::metadata::{self, MetadataNode};
+use crate::migrations::{Migration, MYSQL_MIGRATIONS, POSTGRES_MIGRATIONS, SQLITE_MIGRATIONS};
+
+#[test]
+fn test_backend_from_url_scheme() {
+    assert_eq!(Backend::from_url("mysql://root@localhost/webapp_db").unwrap(), Backend::MySql);
+    assert_eq!(Backend::from_url("sqlite::memory:").unwrap(), Backend::Sqlite);
+    assert_eq!(Backend::from_url("sqlite://data/users.db?mode=rwc").unwrap(), Backend::Sqlite);
+    assert_eq!(Backend::from_url("postgres://localhost/webapp_db").unwrap(), Backend::Postgres);
+    assert_eq!(Backend::from_url("postgresql://localhost/webapp_db").unwrap(), Backend::Postgres);
+    assert_eq!(Backend::from_url("MySQL://localhost/webapp_db").unwrap(), Backend::MySql);
+}
+
+#[test]
+fn test_backend_from_url_rejects_unknown_scheme() {
+    for url in ["mssql://localhost/db", "localhost:3306", ""] {
+        assert!(matches!(Backend::from_url(url), Err(DatabaseError::ConnectionError(_))), "{}", url);
+    }
+}
+
+fn checksums(migrations: &[Migration]) -> Vec<(i64, String)> {
+    migrations.iter().map(|m| (m.version, m.checksum())).collect()
+}
+
+#[test]
+fn test_backend_migrations_match_dialect() {
+    assert_eq!(checksums(Backend::MySql.migrations()), checksums(MYSQL_MIGRATIONS));
+    assert_eq!(checksums(Backend::Sqlite.migrations()), checksums(SQLITE_MIGRATIONS));
+    assert_eq!(checksums(Backend::Postgres.migrations()), checksums(POSTGRES_MIGRATIONS));
+}
+
+#[actix_web::test]
+async fn test_connect_to_sqlite_file_round_trip() {
+    let path = std::env::temp_dir().join(format!("rust_user_service_{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let url = format!("sqlite://{}?mode=rwc", path.display());
+
+    let db = Database::connect(&url).await.expect("Failed to open SQLite file");
+    assert_eq!(db.backend(), Backend::Sqlite);
+    db.run_migrations().await.unwrap();
+
+    let user_id = db
+        .create_user(&CreateUserRequest {
+            username: "filedb".to_string(),
+            password: "hashed".to_string(),
+            profile: None,
+            metadata: vec![],
+        })
+        .await
+        .unwrap();
+
+    // A second connection sees the committed row and the recorded migrations
+    let reopened = Database::connect(&url).await.unwrap();
+    assert!(reopened.run_migrations().await.unwrap().applied.is_empty());
+    assert_eq!(reopened.find_user_by_id(user_id).await.unwrap().username, "filedb");
+
+    let _ = std::fs::remove_file(&path);
+}
+
+fn user_request(username: &str, profile: Option<UserProfile>, metadata: usize) -> CreateUserRequest {
+    CreateUserRequest {
+        username: username.to_string(),
+        password: "hashed".to_string(),
+        profile,
+        metadata: (0..metadata)
+            .map(|i| UserMetadata {
+                parent_property: None,
+                property: format!("prop{}", i),
+                value: Some(i.to_string()),
+                position: 0,
+            })
+            .collect(),
+    }
+}
+
+#[actix_web::test]
+async fn test_profile_row_with_null_columns_is_not_missing() {
+    let db = Database::new_test().await.expect("Failed to create test database");
+
+    let empty_profile = UserProfile::default();
+    let with_profile = db.create_user(&user_request("nullprofile", Some(empty_profile), 0)).await.unwrap();
+    let without_profile = db.create_user(&user_request("noprofile", None, 0)).await.unwrap();
+
+    let profile = db.find_user_by_id(with_profile).await.unwrap().profile.expect("profile row exists");
+    assert!(profile.first_name.is_none() && profile.last_name.is_none() && profile.email.is_none());
+    assert!(db.find_user_by_id(without_profile).await.unwrap().profile.is_none());
+}
+
+#[actix_web::test]
+async fn test_repeated_metadata_keeps_positions() {
+    let db = Database::new_test().await.expect("Failed to create test database");
+
+    let mut request = user_request("positions", None, 0);
+    request.metadata = metadata::flatten(
+        &[
+            MetadataNode { property: "hobby".to_string(), value: Some("chess".to_string()), children: vec![] },
+            MetadataNode { property: "hobby".to_string(), value: Some("jazz".to_string()), children: vec![] },
+        ],
+        None,
+    );
+    let user_id = db.create_user(&request).await.unwrap();
+
+    let stored = db.find_user_by_id(user_id).await.unwrap().metadata;
+    assert_eq!(metadata::values(&stored, "hobby"), vec!["chess", "jazz"]);
+    assert_eq!(stored.iter().map(|m| m.position).collect::<Vec<_>>(), vec![0, 1]);
+
+    // Two rows at the same position are still rejected
+    let mut duplicate = user_request("samepos", None, 0);
+    duplicate.metadata = vec![stored[0].clone(), stored[0].clone()];
+    assert!(db.create_user(&duplicate).await.is_err());
+}
+
+#[actix_web::test]
+async fn test_profile_name_parts_round_trip() {
+    let db = Database::new_test().await.expect("Failed to create test database");
+
+    let profile = UserProfile {
+        first_name: Some("Ada".to_string()),
+        middle_name: Some("Augusta".to_string()),
+        last_name: Some("Lovelace".to_string()),
+        nickname: Some("Addie".to_string()),
+        honorific: Some("Countess".to_string()),
+        suffix: Some("FRS".to_string()),
+        display_name: Some("Lady Lovelace".to_string()),
+        email: None,
+    };
+    let user_id = db.create_user(&user_request("namedparts", Some(profile), 0)).await.unwrap();
+
+    let stored = db.find_user_by_id(user_id).await.unwrap().profile.expect("profile row exists");
+    assert_eq!(stored.middle_name.as_deref(), Some("Augusta"));
+    assert_eq!(stored.nickname.as_deref(), Some("Addie"));
+    assert_eq!(stored.honorific.as_deref(), Some("Countess"));
+    assert_eq!(stored.suffix.as_deref(), Some("FRS"));
+    assert_eq!(stored.display_name.as_deref(), Some("Lady Lovelace"));
+}
+
+#[actix_web::test]
+async fn test_find_users_by_ids_spans_several_batches() {
+    let db = Database::new_test().await.expect("Failed to create test database");
+
+    let first = db.create_user(&user_request("batch1", None, 3)).await.unwrap();
+    let second = db.create_user(&user_request("batch2", None, 0)).await.unwrap();
+
+    // More IDs than fit in one query, with the real users at either end
+    let mut ids = vec![second];
+    ids.extend(10_000..11_000);
+    ids.push(first);
+
+    let users = db.find_users_by_ids(&ids).await.unwrap();
+    assert_eq!(users.len(), 2);
+    assert_eq!(users[0].id, second);
+    assert!(users[0].metadata.is_empty());
+    assert_eq!(users[1].id, first);
+    let properties: Vec<_> = users[1].metadata.iter().map(|m| m.property.as_str()).collect();
+    assert_eq!(properties, ["prop0", "prop1", "prop2"]);
+}
+
+/// Driver error with just the fields the mapping inspects
+#[derive(Debug)]
+struct FakeDriverError {
+    message: &'static str,
+    code: Option<&'static str>,
+    constraint: Option<&'static str>,
+    kind: ErrorKind,
+}
+
+impl std::fmt::Display for FakeDriverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message)
+    }
+}
+
+impl std::error::Error for FakeDriverError {}
+
+impl sqlx::error::DatabaseError for FakeDriverError {
+    fn message(&self) -> &str {
+        self.message
+    }
+
+    fn code(&self) -> Option<Cow<'_, str>> {
+        self.code.map(Cow::Borrowed)
+    }
+
+    fn constraint(&self) -> Option<&str> {
+        self.constraint
+    }
+
+    fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+        self
+    }
+
+    fn kind(&self) -> ErrorKind {
+        // ErrorKind is neither Copy nor Clone
+        match self.kind {
+            ErrorKind::UniqueViolation => ErrorKind::UniqueViolation,
+            ErrorKind::ForeignKeyViolation => ErrorKind::ForeignKeyViolation,
+            ErrorKind::NotNullViolation => ErrorKind::NotNullViolation,
+            ErrorKind::CheckViolation => ErrorKind::CheckViolation,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+fn driver_error(message: &'static str, code: Option<&'static str>, constraint: Option<&'static str>, kind: ErrorKind) -> DatabaseError {
+    from_driver_error(&FakeDriverError { message, code, constraint, kind })
+}
+
+#[test]
+fn test_driver_errors_map_by_code_and_constraint() {
+    // Postgres names the constraint directly
+    assert!(matches!(
+        driver_error("duplicate key", Some("23505"), Some("idx_profile_email"), ErrorKind::UniqueViolation),
+        DatabaseError::DuplicateEmail
+    ));
+    assert!(matches!(
+        driver_error("violates foreign key", Some("23503"), Some("user_profiles_user_id_fkey"), ErrorKind::ForeignKeyViolation),
+        DatabaseError::ForeignKeyViolation(name) if name == "user_profiles_user_id_fkey"
+    ));
+    assert!(matches!(
+        driver_error("could not serialize access", Some("40001"), None, ErrorKind::Other),
+        DatabaseError::SerializationFailure
+    ));
+    assert!(matches!(
+        driver_error("deadlock detected", Some("40P01"), None, ErrorKind::Other),
+        DatabaseError::SerializationFailure
+    ));
+
+    // MySQL and SQLite only mention it in the message
+    assert!(matches!(
+        driver_error("Duplicate entry 'ada' for key 'users.idx_username'", Some("23000"), None, ErrorKind::UniqueViolation),
+        DatabaseError::DuplicateUsername
+    ));
+    assert!(matches!(
+        driver_error("UNIQUE constraint failed: user_profiles.email", Some("2067"), None, ErrorKind::UniqueViolation),
+        DatabaseError::DuplicateEmail
+    ));
+    assert!(matches!(
+        driver_error("NOT NULL constraint failed: users.password", Some("1299"), None, ErrorKind::NotNullViolation),
+        DatabaseError::ConstraintViolation(name) if name == "users.password"
+    ));
+
+    assert!(matches!(
+        driver_error("syntax error", Some("42601"), None, ErrorKind::Other),
+        DatabaseError::QueryError(_)
+    ));
+}
+
+/// A single-connection SQLite pool that gives up waiting after 50ms, with its connection
+/// checked out, wrapped in a `Database`
+async fn exhausted_database() -> (Database, sqlx::pool::PoolConnection<sqlx::Sqlite>) {
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .acquire_timeout(std::time::Duration::from_millis(50))
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    let held = pool.acquire().await.unwrap();
+    (Database::from_sqlite_pool(pool), held)
+}
+
+#[actix_web::test]
+async fn test_exhausted_pool_reports_pool_timeout() {
+    let (db, held) = exhausted_database().await;
+
+    let err = db.find_user_by_id(1).await.unwrap_err();
+    assert!(matches!(err, DatabaseError::PoolTimeout), "{:?}", err);
+    assert!(err.is_retryable());
+
+    drop(held);
+    assert!(!matches!(db.find_user_by_id(1).await, Err(DatabaseError::PoolTimeout)));
+}
+
+#[test]
+fn test_only_transient_errors_are_retryable() {
+    assert!(DatabaseError::PoolTimeout.is_retryable());
+    assert!(DatabaseError::SerializationFailure.is_retryable());
+    assert!(DatabaseError::ConnectionError("refused".to_string()).is_retryable());
+    assert!(!DatabaseError::DuplicateEmail.is_retryable());
+    assert!(!DatabaseError::ForeignKeyViolation("fk".to_string()).is_retryable());
+    assert!(!DatabaseError::UserNotFound.is_retryable());
+}
@@ -0,0 +1,203 @@
+use crate::db::{User, UserMetadata, UserProfile};
+use crate::greeting_templates::{GreetingTemplateError, GreetingTemplates, DEFAULT_TEMPLATE};
+use crate::user_info_formatter::NameStyle;
+use std::path::PathBuf;
+use unic_langid::LanguageIdentifier;
+
+fn en() -> LanguageIdentifier {
+    "en".parse().unwrap()
+}
+
+/// Fresh template directory holding `files`
+fn template_dir(test: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("greeting_templates_{}_{}", std::process::id(), test));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, source) in files {
+        std::fs::write(dir.join(name), source).unwrap();
+    }
+    dir
+}
+
+fn user() -> User {
+    let meta = |parent: Option<&str>, property: &str, value: Option<&str>| UserMetadata {
+        parent_property: parent.map(str::to_string),
+        property: property.to_string(),
+        value: value.map(str::to_string),
+        position: 0,
+    };
+    User {
+        id: 7,
+        username: "ada".to_string(),
+        password: "hashed".to_string(),
+        profile: Some(UserProfile {
+            first_name: Some("Ada".to_string()),
+            last_name: Some("Lovelace".to_string()),
+            email: None,
+            ..Default::default()
+        }),
+        metadata: vec![
+            meta(None, "title", Some("Countess")),
+            meta(None, "address", None),
+            meta(Some("address"), "city", Some("London")),
+        ],
+        created_at: chrono::NaiveDate::from_ymd_opt(2020, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
+        updated_at: chrono::NaiveDate::from_ymd_opt(2020, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
+    }
+}
+
+#[test]
+fn test_directory_templates_render_with_conditionals_and_metadata() {
+    let dir = template_dir(
+        "render",
+        &[
+            ("formal.j2", "Dear {% if title %}{{ title }} {% endif %}{{ last_name }}{% if email %} <{{ email }}>{% endif %}\n"),
+            ("city.j2", "{{ first_name }} from {{ metadata[\"address.city\"] }}{{ metadata.missing }}"),
+            ("notes.txt", "not a template"),
+        ],
+    );
+    let templates = GreetingTemplates::load(&dir).unwrap();
+
+    assert_eq!(templates.names(), ["city", "default", "formal"]);
+    assert_eq!(templates.render("formal", &user(), &en(), NameStyle::Formal).unwrap(), "Dear Countess Lovelace");
+    assert_eq!(templates.render("city", &user(), &en(), NameStyle::Formal).unwrap(), "Ada from London");
+    assert_eq!(
+        templates.render(DEFAULT_TEMPLATE, &user(), &en(), NameStyle::Formal).unwrap(),
+        "Hello Countess Ada Lovelace, welcome!"
+    );
+    assert_eq!(
+        templates.render("casual", &user(), &en(), NameStyle::Formal),
+        Err(GreetingTemplateError::Unknown("casual".to_string()))
+    );
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_directory_can_override_default() {
+    let dir = template_dir("override", &[("default.j2", "Hi {{ name }}")]);
+    let templates = GreetingTemplates::load(&dir).unwrap();
+    assert_eq!(templates.render(DEFAULT_TEMPLATE, &user(), &en(), NameStyle::Formal).unwrap(), "Hi Ada Lovelace");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_invalid_templates_are_rejected_on_load() {
+    let cases = [
+        ("syntax", "broken.j2", "Hello {% if name %}"),
+        ("filter", "shout.j2", "{{ name | no_such_filter }}"),
+        // Only fails for users without a title
+        ("runtime", "upper.j2", "{{ title.upper() }}"),
+    ];
+    for (test, file, source) in cases {
+        let dir = template_dir(test, &[(file, source)]);
+        assert!(
+            matches!(GreetingTemplates::load(&dir), Err(GreetingTemplateError::Invalid { .. })),
+            "{} should be rejected",
+            file
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    let dir = template_dir("name", &[("has space.j2", "Hi")]);
+    assert!(matches!(GreetingTemplates::load(&dir), Err(GreetingTemplateError::InvalidName(_))));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert!(matches!(
+        GreetingTemplates::load(std::env::temp_dir().join("greeting_templates_missing_dir")),
+        Err(GreetingTemplateError::Io { .. })
+    ));
+}
+
+#[test]
+fn test_reload_swaps_only_valid_sets() {
+    let dir = template_dir("reload", &[("short.j2", "Hi {{ username }}")]);
+    let templates = GreetingTemplates::load(&dir).unwrap();
+
+    std::fs::write(dir.join("long.j2"), "Good day, {{ name }}").unwrap();
+    assert_eq!(templates.reload().unwrap(), ["default", "long", "short"]);
+    assert_eq!(templates.render("long", &user(), &en(), NameStyle::Formal).unwrap(), "Good day, Ada Lovelace");
+
+    // A broken file keeps the previous set in use
+    std::fs::write(dir.join("short.j2"), "Hi {{ username").unwrap();
+    assert!(templates.reload().is_err());
+    assert_eq!(templates.render("short", &user(), &en(), NameStyle::Formal).unwrap(), "Hi ada");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_directory_templates_can_translate() {
+    let dir = template_dir("translate", &[("short.j2", "{{ t('greeting-welcome', name=username) }}")]);
+    let templates = GreetingTemplates::load(&dir).unwrap();
+    assert_eq!(templates.render("short", &user(), &en(), NameStyle::Formal).unwrap(), "Hello ada, welcome!");
+    assert_eq!(
+        templates.render("short", &user(), &"de".parse().unwrap(), NameStyle::Formal).unwrap(),
+        "Hallo ada, willkommen!"
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+
+    // Unknown messages, and arguments that are none for some users, are caught on load
+    for (test, source) in [
+        ("translate_unknown", "{{ t('greeting-farewell', name=name) }}"),
+        ("translate_argument", "{{ t('greeting-welcome', name=first_name) }}"),
+    ] {
+        let dir = template_dir(test, &[("bad.j2", source)]);
+        assert!(matches!(
+            GreetingTemplates::load(&dir),
+            Err(GreetingTemplateError::Invalid { name, .. }) if name == "bad"
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
+
+#[test]
+fn test_escape_for_filter() {
+    let dir = template_dir(
+        "escape_for",
+        &[
+            ("html.j2", "<span title=\"{{ title | escape_for('attr') }}\">{{ first_name | escape_for('html') }}</span>"),
+            ("json.j2", "{\"name\": \"{{ name | escape_for('json') }}\", \"email\": \"{{ email | escape_for('json') }}\"}"),
+        ],
+    );
+    let templates = GreetingTemplates::load(&dir).unwrap();
+    let mut user = user();
+    user.profile.as_mut().unwrap().first_name = Some("<Ada>".to_string());
+    user.metadata[0].value = Some("\"Countess\"".to_string());
+
+    assert_eq!(
+        templates.render("html", &user, &en(), NameStyle::Formal).unwrap(),
+        "<span title=\"&quot;Countess&quot;\">&lt;Ada&gt;</span>"
+    );
+    let json: serde_json::Value = serde_json::from_str(&templates.render("json", &user, &en(), NameStyle::Formal).unwrap()).unwrap();
+    assert_eq!(json, serde_json::json!({"name": "<Ada> Lovelace", "email": ""}));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let dir = template_dir("escape_for_unknown", &[("css.j2", "{{ name | escape_for('css') }}")]);
+    assert!(matches!(GreetingTemplates::load(&dir), Err(GreetingTemplateError::Invalid { .. })));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_templates_see_every_name_style() {
+    let dir = template_dir(
+        "names",
+        &[("names.j2", "{{ name }} | {{ names.informal }} | {{ names.initials }} | {{ names.sorted }}{% if suffix %} ({{ suffix }}){% endif %}")],
+    );
+    let templates = GreetingTemplates::load(&dir).unwrap();
+    let mut user = user();
+    let profile = user.profile.as_mut().unwrap();
+    profile.middle_name = Some("Augusta".to_string());
+    profile.nickname = Some("Addie".to_string());
+
+    assert_eq!(
+        templates.render("names", &user, &en(), NameStyle::Formal).unwrap(),
+        "Ada Augusta Lovelace | Addie | A. A. Lovelace | Lovelace, Ada Augusta"
+    );
+    // The configured style picks what `name` is
+    assert_eq!(
+        templates.render("names", &user, &en(), NameStyle::Sorted).unwrap(),
+        "Lovelace, Ada Augusta | Addie | A. A. Lovelace | Lovelace, Ada Augusta"
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -9,12 +9,23 @@ use wiremock::matchers::{method, path};
 use wiremock::{Mock, MockServer, ResponseTemplate};
 
 use crate::db::Database;
-use crate::{create_user, get_user_info, login, AppState};
-
-// Global mutex to serialize tests that use environment variables
-// This is necessary because std::env::set_var is not thread-safe and
-// our implementation relies on std::env::var("LOGGER_URL")
-static TEST_MUTEX: std::sync::Mutex<()> = std::sync::Mutex::new(());
+use crate::greeting_templates::GreetingTemplates;
+use crate::pages::Pages;
+use crate::cors::{CorsConfig, CorsPolicy};
+use crate::csrf::CsrfProtection;
+use crate::session::{SessionKey, CSRF_COOKIE, SESSION_COOKIE};
+use crate::user_info_formatter::NameStyle;
+use crate::web_ui::WebUi;
+use crate::metadata_schema::MetadataSchema;
+use crate::repository::UserRepository;
+use crate::{
+    create_user, delete_metadata_subtree, health_check, get_log_levels, get_metadata_subtree, get_user_info, get_user_metadata,
+    login, login_form, login_page, logout, metrics_endpoint, profile_page, put_metadata_subtree, put_user_metadata,
+    reload_greeting_templates, search_users, signup_form, signup_page, update_log_levels, web_ui_file, web_ui_index,
+    AppState,
+};
+
+use super::TEST_MUTEX;
 
 // ============ Test Helpers ============
 
@@ -35,9 +46,58 @@ async fn setup_test_deps() -> (Database, MockServer, std::sync::MutexGuard<'stat
     (db, mock_logger, guard)
 }
 
-/// Create test app with given database
+/// Create test app with given user store
 fn create_test_app(
-    db: Database,
+    users: impl UserRepository + 'static,
+) -> App<
+    impl actix_web::dev::ServiceFactory<
+        actix_web::dev::ServiceRequest,
+        Config = (),
+        Response = actix_web::dev::ServiceResponse,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    create_test_app_with_schema(users, MetadataSchema::default())
+}
+
+/// Test app validating metadata against `metadata_schema`
+fn create_test_app_with_schema(
+    users: impl UserRepository + 'static,
+    metadata_schema: MetadataSchema,
+) -> App<
+    impl actix_web::dev::ServiceFactory<
+        actix_web::dev::ServiceRequest,
+        Config = (),
+        Response = actix_web::dev::ServiceResponse,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    build_test_app(users, metadata_schema, GreetingTemplates::default(), CorsConfig::default())
+}
+
+/// Test app rendering greetings with `greeting_templates`
+fn create_test_app_with_templates(
+    users: impl UserRepository + 'static,
+    greeting_templates: GreetingTemplates,
+) -> App<
+    impl actix_web::dev::ServiceFactory<
+        actix_web::dev::ServiceRequest,
+        Config = (),
+        Response = actix_web::dev::ServiceResponse,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    build_test_app(users, MetadataSchema::default(), greeting_templates, CorsConfig::default())
+}
+
+fn build_test_app(
+    users: impl UserRepository + 'static,
+    metadata_schema: MetadataSchema,
+    greeting_templates: GreetingTemplates,
+    cors: CorsConfig,
 ) -> App<
     impl actix_web::dev::ServiceFactory<
         actix_web::dev::ServiceRequest,
@@ -48,13 +108,67 @@ fn create_test_app(
     >,
 > {
     App::new()
+        .wrap(CsrfProtection::new(&cors.api))
         .app_data(web::Data::new(AppState {
-            db: db,
+            users: std::sync::Arc::new(users),
             http_client: reqwest::Client::new(),
+            metadata_schema,
+            greeting_templates,
+            name_style: NameStyle::default(),
+            web_ui: WebUi::default(),
+            pages: Pages::default(),
+            sessions: SessionKey::default(),
         }))
-        .route("/api/create-user", web::post().to(create_user))
-        .route("/api/login", web::post().to(login))
-        .route("/api/users/{user_id}", web::get().to(get_user_info))
+        .service(
+            web::resource("/health")
+                .wrap(cors.public.middleware())
+                .route(web::get().to(health_check)),
+        )
+        .service(
+            web::scope("/api")
+                .wrap(cors.api.middleware())
+                .route("/create-user", web::post().to(create_user))
+                .route("/login", web::post().to(login))
+                .route("/users/search", web::post().to(search_users))
+                .route("/users/{user_id}", web::get().to(get_user_info))
+                .route("/users/{user_id}/metadata", web::get().to(get_user_metadata))
+                .route("/users/{user_id}/metadata", web::put().to(put_user_metadata))
+                .route("/users/{user_id}/metadata/{property}", web::get().to(get_metadata_subtree))
+                .route("/users/{user_id}/metadata/{property}", web::put().to(put_metadata_subtree))
+                .route("/users/{user_id}/metadata/{property}", web::delete().to(delete_metadata_subtree)),
+        )
+        .service(
+            web::scope("/admin")
+                .wrap(cors.admin.middleware())
+                .route("/log-levels", web::get().to(get_log_levels))
+                .route("/log-levels", web::put().to(update_log_levels))
+                .route("/greeting-templates/reload", web::post().to(reload_greeting_templates)),
+        )
+        .route("/metrics", web::get().to(metrics_endpoint))
+        .route("/login", web::get().to(login_page))
+        .route("/login", web::post().to(login_form))
+        .route("/signup", web::get().to(signup_page))
+        .route("/signup", web::post().to(signup_form))
+        .route("/profile", web::get().to(profile_page))
+        .route("/logout", web::post().to(logout))
+        .route("/", web::get().to(web_ui_index))
+        .route("/{file}", web::get().to(web_ui_file))
+}
+
+/// Test app applying the `cors` policies
+fn create_test_app_with_cors(
+    users: impl UserRepository + 'static,
+    cors: CorsConfig,
+) -> App<
+    impl actix_web::dev::ServiceFactory<
+        actix_web::dev::ServiceRequest,
+        Config = (),
+        Response = actix_web::dev::ServiceResponse,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    build_test_app(users, MetadataSchema::default(), GreetingTemplates::default(), cors)
 }
 
 /// Insert a test user directly into the database
@@ -67,17 +181,20 @@ async fn create_test_user(db: &Database, username: &str, password: &str) -> i32
             first_name: Some("Test".to_string()),
             last_name: Some("User".to_string()),
             email: Some("test@example.com".to_string()),
+            ..Default::default()
         }),
         metadata: vec![
             UserMetadata {
                 parent_property: None,
                 property: "title".to_string(),
                 value: Some("Engineer".to_string()),
+                position: 0,
             },
             UserMetadata {
                 parent_property: None,
                 property: "hobby".to_string(),
                 value: Some("Coding".to_string()),
+                position: 0,
             },
         ],
     })
@@ -254,6 +371,72 @@ async fn test_create_user_optional_field_too_long() {
     assert_error_response(&body, "VALIDATION_ERROR");
 }
 
+#[actix_web::test]
+async fn test_create_user_cleans_text_fields() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db.clone())).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    // Bidi overrides and other control characters are rejected
+    for (field, value) in [("first_name", "Ev\u{202E}lyn"), ("hobby", "chess\u{7}"), ("last_name", "Doe\u{2066}")] {
+        let req = test::TestRequest::post()
+            .uri("/api/create-user")
+            .set_form([("username", "spoofer"), ("password", "password123"), (field, value)])
+            .to_request();
+        let resp: ServiceResponse = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 400, "{} should be rejected", field);
+        let body: Value = test::read_body_json(resp).await;
+        assert_error_response(&body, "VALIDATION_ERROR");
+        assert!(body["message"].as_str().unwrap().starts_with(field));
+    }
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_json(serde_json::json!({
+            "username": "spoofer",
+            "password": "password123",
+            "extra_metadata": [{"property": "address", "children": [{"property": "city", "value": "Par\u{202D}is"}]}]
+        }))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let body: Value = test::read_body_json(resp).await;
+    assert!(body["message"].as_str().unwrap().starts_with("address.city"));
+
+    // Property names too, where values would have their line breaks normalized
+    for property in ["home\u{202E}town", "home\ntown"] {
+        let req = test::TestRequest::post()
+            .uri("/api/create-user")
+            .set_json(serde_json::json!({
+                "username": "spoofer",
+                "password": "password123",
+                "extra_metadata": [{"property": "address", "children": [{"property": property, "value": "Paris"}]}]
+            }))
+            .to_request();
+        let resp: ServiceResponse = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 400, "{:?} should be rejected", property);
+        let body: Value = test::read_body_json(resp).await;
+        assert_error_response(&body, "VALIDATION_ERROR");
+        assert!(body["message"].as_str().unwrap().starts_with("metadata property name under address"));
+    }
+
+    // Line breaks and tabs are normalized to spaces
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_form([("username", "multiline"), ("password", "password123"), ("first_name", " Mary\r\nJane\t"), ("title", "Dr.\n")])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let user_id: i32 = std::str::from_utf8(&test::read_body(resp).await).unwrap().parse().unwrap();
+    let user = db.find_user_by_id(user_id).await.unwrap();
+    assert_eq!(user.profile.unwrap().first_name.as_deref(), Some("Mary  Jane"));
+    assert_eq!(user.metadata[0].value.as_deref(), Some("Dr."));
+}
+
 #[actix_web::test]
 async fn test_create_user_duplicate_username() {
     let (db, mock_logger, _guard) = setup_test_deps().await;
@@ -281,6 +464,33 @@ async fn test_create_user_duplicate_username() {
     assert_error_response(&body, "DUPLICATE_USERNAME");
 }
 
+#[actix_web::test]
+async fn test_create_user_duplicate_email() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db)).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    for (username, expected) in [("first", 200), ("second", 409)] {
+        let req = test::TestRequest::post()
+            .uri("/api/create-user")
+            .set_form([("username", username), ("password", "password123"), ("email", "shared@example.com")])
+            .to_request();
+        let resp: ServiceResponse = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), expected);
+
+        if expected == 409 {
+            assert!(resp.headers().get("retry-after").is_none());
+            let body: Value = test::read_body_json(resp).await;
+            assert_error_response(&body, "DUPLICATE_EMAIL");
+        }
+    }
+}
+
 #[actix_web::test]
 async fn test_create_user_logs_to_logger() {
     let (db, mock_logger, _guard) = setup_test_deps().await;
@@ -534,17 +744,20 @@ async fn test_get_user_info_with_all_optional_fields() {
                 first_name: Some("John".to_string()),
                 last_name: Some("Doe".to_string()),
                 email: Some("john@example.com".to_string()),
+                ..Default::default()
             }),
             metadata: vec![
                 UserMetadata {
                     parent_property: None,
                     property: "title".to_string(),
                     value: Some("Engineer".to_string()),
+                    position: 0,
                 },
                 UserMetadata {
                     parent_property: None,
                     property: "hobby".to_string(),
                     value: Some("Coding".to_string()),
+                    position: 0,
                 },
             ],
         })
@@ -620,6 +833,2314 @@ async fn test_logger_failure_simulation() {
     assert!(user_id > 0);
 }
 
+#[actix_web::test]
+async fn test_remote_log_never_contains_password() {
+    let (_db, mock_logger, _guard) = setup_test_deps().await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let client = reqwest::Client::new();
+    let payload = crate::LoginPayload {
+        username: "alice".to_string(),
+        password: "hunter2-secret".to_string(),
+    };
+    crate::log_error!(client, "login_user", "alice", "Rejected payload {:?}", payload);
+    crate::log_info!(client, "login_user", "alice", "retrying with password=hunter2-secret");
+    crate::log_info!(client, "login_user", "alice@example.com", "Login attempt");
+
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let requests = mock_logger.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 3);
+    for request in requests {
+        let body = String::from_utf8(request.body).unwrap();
+        assert!(!body.contains("hunter2-secret"), "password leaked: {}", body);
+        assert!(!body.contains("alice@example.com"), "email leaked: {}", body);
+    }
+}
+
+// ============ Remote Log Level Tests ============
+
+#[actix_web::test]
+async fn test_admin_log_levels_requires_token() {
+    let (db, _mock_logger, _guard) = setup_test_deps().await;
+    std::env::set_var("ADMIN_TOKEN", "admin-secret");
+    let app = test::init_service(create_test_app(db)).await;
+
+    let req = test::TestRequest::get().uri("/admin/log-levels").to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 401);
+
+    let req = test::TestRequest::get()
+        .uri("/admin/log-levels")
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let body: Value = test::read_body_json(resp).await;
+    assert!(body.get("min_level").is_some());
+
+    std::env::remove_var("ADMIN_TOKEN");
+}
+
+#[actix_web::test]
+async fn test_admin_log_levels_disabled_without_token() {
+    let (db, _mock_logger, _guard) = setup_test_deps().await;
+    std::env::remove_var("ADMIN_TOKEN");
+    let app = test::init_service(create_test_app(db)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/admin/log-levels")
+        .insert_header(("X-Admin-Token", ""))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+}
+
+#[actix_web::test]
+async fn test_runtime_remote_level_change_filters_logs() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    std::env::set_var("ADMIN_TOKEN", "admin-secret");
+    let app = test::init_service(create_test_app(db)).await;
+    let original = crate::logger::remote_config();
+
+    // Raise the remote threshold to error: the info events from create_user are dropped
+    let req = test::TestRequest::put()
+        .uri("/admin/log-levels")
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .set_json(serde_json::json!({"min_level": "error"}))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&mock_logger)
+        .await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_form([("username", "quietuser"), ("password", "password123")])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    mock_logger.verify().await;
+
+    crate::logger::set_remote_config(original).unwrap();
+    std::env::remove_var("ADMIN_TOKEN");
+}
+
+#[actix_web::test]
+async fn test_app_override_and_sampling() {
+    use crate::logger::{parse_app_levels, should_forward, LogLevel, RemoteLogConfig};
+
+    let (_db, _mock_logger, _guard) = setup_test_deps().await;
+    let original = crate::logger::remote_config();
+
+    crate::logger::set_remote_config(RemoteLogConfig {
+        min_level: LogLevel::Info,
+        app_levels: parse_app_levels("create_user=debug,login_user=warn").unwrap(),
+        info_sample_rate: 0.5,
+    })
+    .unwrap();
+
+    assert!(should_forward(LogLevel::Debug, "create_user"));
+    assert!(!should_forward(LogLevel::Debug, "get_user_info"));
+    assert!(!should_forward(LogLevel::Info, "login_user"));
+    assert!(should_forward(LogLevel::Error, "login_user"));
+
+    // Sampling applies to info events only; the rate itself is covered by `sample_info`
+    assert!(should_forward(LogLevel::Warn, "get_user_info"));
+
+    crate::logger::set_remote_config(original).unwrap();
+}
+
+#[actix_web::test]
+async fn test_update_log_levels_rejects_invalid_config() {
+    let (db, _mock_logger, _guard) = setup_test_deps().await;
+    std::env::set_var("ADMIN_TOKEN", "admin-secret");
+    let app = test::init_service(create_test_app(db)).await;
+
+    let req = test::TestRequest::put()
+        .uri("/admin/log-levels")
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .set_json(serde_json::json!({"min_level": "info", "info_sample_rate": 2.0}))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let body: Value = test::read_body_json(resp).await;
+    assert_error_response(&body, "VALIDATION_ERROR");
+
+    for payload in [
+        serde_json::json!({"min_level": "loud"}),
+        serde_json::json!({"min_level": "info", "app_levels": {"login_user": "loud"}}),
+        serde_json::json!({"min_level": "info", "app_levels": {"": "warn"}}),
+    ] {
+        let req = test::TestRequest::put()
+            .uri("/admin/log-levels")
+            .insert_header(("X-Admin-Token", "admin-secret"))
+            .set_json(&payload)
+            .to_request();
+        let resp: ServiceResponse = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 400, "accepted {}", payload);
+        let body: Value = test::read_body_json(resp).await;
+        assert_error_response(&body, "VALIDATION_ERROR");
+    }
+
+    std::env::remove_var("ADMIN_TOKEN");
+}
+
+// ============ Access Log Tests ============
+
+#[actix_web::test]
+async fn test_access_log_records_route_template() {
+    use crate::access_log::{AccessLog, AccessLogFormat};
+
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let user_id = create_test_user(&db, "accessuser", "password123").await;
+    let app = test::init_service(
+        create_test_app(db).wrap(AccessLog::new(reqwest::Client::new(), AccessLogFormat::Json)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}", user_id))
+        .peer_addr("10.0.0.7:51000".parse().unwrap())
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let requests = mock_logger.received_requests().await.unwrap();
+    let access_entries: Vec<Value> = requests
+        .iter()
+        .map(|r| serde_json::from_slice::<Value>(&r.body).unwrap())
+        .filter(|payload| payload["app"] == "access_log")
+        .collect();
+    assert_eq!(access_entries.len(), 1);
+
+    let line: Value = serde_json::from_str(access_entries[0]["message"].as_str().unwrap()).unwrap();
+    assert_eq!(line["method"], "GET");
+    assert_eq!(line["path"], "/api/users/{user_id}");
+    assert_eq!(line["status"], 200);
+    assert_eq!(line["client_ip"], "10.0.0.7");
+    assert!(line["size"].as_u64().unwrap() > 0);
+    assert!(line["latency_ms"].as_f64().unwrap() >= 0.0);
+}
+
+#[actix_web::test]
+async fn test_access_log_records_failed_requests_and_ignores_spoofed_ip() {
+    use crate::access_log::{AccessLog, AccessLogFormat};
+    use actix_web::dev::Service;
+
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    // An inner middleware failing the request instead of producing a response
+    let app = test::init_service(
+        create_test_app(db)
+            .wrap_fn(|req, srv| {
+                let unavailable = req.path() == "/health";
+                let fut = srv.call(req);
+                async move {
+                    if unavailable {
+                        return Err(actix_web::error::ErrorServiceUnavailable("maintenance"));
+                    }
+                    fut.await
+                }
+            })
+            .wrap(AccessLog::new(reqwest::Client::new(), AccessLogFormat::Json)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/health")
+        .peer_addr("10.0.0.7:51000".parse().unwrap())
+        .insert_header(("X-Forwarded-For", "203.0.113.99"))
+        .to_request();
+    let result = app.call(req).await;
+    assert!(result.is_err());
+
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let requests = mock_logger.received_requests().await.unwrap();
+    let access_entries: Vec<Value> = requests
+        .iter()
+        .map(|r| serde_json::from_slice::<Value>(&r.body).unwrap())
+        .filter(|payload| payload["app"] == "access_log")
+        .collect();
+    assert_eq!(access_entries.len(), 1);
+
+    let line: Value = serde_json::from_str(access_entries[0]["message"].as_str().unwrap()).unwrap();
+    assert_eq!(line["path"], "/health");
+    assert_eq!(line["status"], 503);
+    assert_eq!(line["client_ip"], "10.0.0.7");
+}
+
+// ============ Metrics Tests ============
+
+/// Read a single sample value from Prometheus text output
+fn metric_value(body: &str, series: &str) -> f64 {
+    body.lines()
+        .find(|line| line.starts_with(series) && line[series.len()..].starts_with(' '))
+        .and_then(|line| line.rsplit(' ').next())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0.0)
+}
+
+/// Scrape /metrics from a test service; `ADMIN_TOKEN` must be `admin-secret`
+macro_rules! scrape_metrics {
+    ($app:expr) => {{
+        let req = test::TestRequest::get()
+            .uri("/metrics")
+            .insert_header(("X-Admin-Token", "admin-secret"))
+            .to_request();
+        let body = test::call_and_read_body($app, req).await;
+        String::from_utf8(body.to_vec()).unwrap()
+    }};
+}
+
+#[actix_web::test]
+async fn test_metrics_counts_logins_and_requests() {
+    use crate::metrics::RequestMetrics;
+
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    std::env::set_var("ADMIN_TOKEN", "admin-secret");
+    create_test_user(&db, "metricuser", "password123").await;
+    let app = test::init_service(create_test_app(db).wrap(RequestMetrics)).await;
+
+    let before = scrape_metrics!(&app);
+
+    for password in ["password123", "wrong"] {
+        let req = test::TestRequest::post()
+            .uri("/api/login")
+            .set_form([("username", "metricuser"), ("password", password)])
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_form([("username", "metricnew"), ("password", "password123")])
+        .to_request();
+    test::call_service(&app, req).await;
+
+    let after = scrape_metrics!(&app);
+
+    let delta = |series: &str| metric_value(&after, series) - metric_value(&before, series);
+    assert_eq!(delta(r#"login_attempts_total{result="success"}"#), 1.0);
+    assert_eq!(delta(r#"login_attempts_total{result="invalid_password"}"#), 1.0);
+    assert_eq!(delta(r#"users_created_total{result="success"}"#), 1.0);
+    assert_eq!(
+        delta(r#"http_requests_total{method="POST",route="/api/login",status="200"}"#),
+        1.0
+    );
+    assert_eq!(
+        delta(r#"http_requests_total{method="POST",route="/api/login",status="401"}"#),
+        1.0
+    );
+    assert!(after.contains("http_request_duration_seconds_bucket"));
+    assert!(after.contains("db_pool_connections"));
+    assert!(after.contains("db_pool_idle_connections"));
+    assert!(after.contains("db_pool_acquire_wait_seconds_count"));
+    assert!(after.contains("remote_log_in_flight"));
+
+    std::env::remove_var("ADMIN_TOKEN");
+}
+
+#[actix_web::test]
+async fn test_metrics_counts_failed_requests() {
+    use crate::metrics::RequestMetrics;
+    use actix_web::dev::Service;
+
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    std::env::set_var("ADMIN_TOKEN", "admin-secret");
+    // An inner middleware failing the request instead of producing a response
+    let app = test::init_service(
+        create_test_app(db)
+            .wrap_fn(|req, srv| {
+                let unavailable = req.path() == "/health";
+                let fut = srv.call(req);
+                async move {
+                    if unavailable {
+                        return Err(actix_web::error::ErrorServiceUnavailable("maintenance"));
+                    }
+                    fut.await
+                }
+            })
+            .wrap(RequestMetrics),
+    )
+    .await;
+
+    let before = scrape_metrics!(&app);
+    let req = test::TestRequest::get().uri("/health").to_request();
+    assert!(app.call(req).await.is_err());
+    let after = scrape_metrics!(&app);
+
+    let series = r#"http_requests_total{method="GET",route="/health",status="503"}"#;
+    assert_eq!(metric_value(&after, series) - metric_value(&before, series), 1.0);
+
+    std::env::remove_var("ADMIN_TOKEN");
+}
+
+#[actix_web::test]
+async fn test_metrics_counts_remote_log_drops() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&mock_logger)
+        .await;
+
+    std::env::set_var("ADMIN_TOKEN", "admin-secret");
+    let app = test::init_service(create_test_app(db)).await;
+    let before = scrape_metrics!(&app);
+
+    let client = reqwest::Client::new();
+    crate::log_info!(client, "metrics_test", "SYSTEM", "this event will be rejected");
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let after = scrape_metrics!(&app);
+    assert_eq!(
+        metric_value(&after, "remote_log_dropped_total") - metric_value(&before, "remote_log_dropped_total"),
+        1.0
+    );
+
+    std::env::remove_var("ADMIN_TOKEN");
+}
+
+#[actix_web::test]
+async fn test_metrics_requires_admin_token() {
+    let (db, _mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db)).await;
+
+    std::env::remove_var("ADMIN_TOKEN");
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    std::env::set_var("ADMIN_TOKEN", "admin-secret");
+    for token in [None, Some("wrong")] {
+        let mut req = test::TestRequest::get().uri("/metrics");
+        if let Some(token) = token {
+            req = req.insert_header(("X-Admin-Token", token));
+        }
+        let resp: ServiceResponse = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let body = test::read_body(resp).await;
+        assert!(!std::str::from_utf8(&body).unwrap().contains("db_pool_connections"));
+    }
+
+    std::env::remove_var("ADMIN_TOKEN");
+}
+
+// ============ Tracing Tests ============
+
+/// Process-wide span exporter. A scoped `set_default` subscriber is not enough here:
+/// spans that finish on sqlx's SQLite worker thread release their parent through that
+/// thread's default dispatcher, so the handler's spans would never close.
+fn span_exporter() -> &'static opentelemetry_sdk::trace::InMemorySpanExporter {
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+
+    static EXPORTER: std::sync::OnceLock<(SdkTracerProvider, InMemorySpanExporter)> = std::sync::OnceLock::new();
+
+    &EXPORTER
+        .get_or_init(|| {
+            let exporter = InMemorySpanExporter::default();
+            let provider = SdkTracerProvider::builder()
+                .with_simple_exporter(exporter.clone())
+                .build();
+            tracing::subscriber::set_global_default(crate::telemetry::subscriber(&provider))
+                .expect("Failed to install test tracing subscriber");
+            (provider, exporter)
+        })
+        .1
+}
+
+/// Finished spans of `trace_hex` once all `names` have been exported
+async fn wait_for_spans(trace_hex: &str, names: &[&str]) -> Vec<opentelemetry_sdk::trace::SpanData> {
+    let trace_id = opentelemetry::trace::TraceId::from_hex(trace_hex).unwrap();
+    let mut spans = Vec::new();
+
+    for _ in 0..100 {
+        spans = span_exporter()
+            .get_finished_spans()
+            .unwrap()
+            .into_iter()
+            .filter(|s| s.span_context.trace_id() == trace_id)
+            .collect();
+        if names.iter().all(|name| spans.iter().any(|s| s.name == *name)) {
+            break;
+        }
+        // The SQLite worker thread may still hold the last query's span
+        actix_web::rt::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    spans
+}
+
+#[actix_web::test]
+async fn test_tracing_continues_incoming_traceparent() {
+    use crate::telemetry::RequestTracing;
+
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    span_exporter();
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let app = test::init_service(create_test_app(db).wrap(RequestTracing)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .insert_header((
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        ))
+        .set_form([("username", "traceuser"), ("password", "password123")])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let expected_spans = ["POST /api/create-user", "create_user", "db.create_user", "db.insert_user", "db.commit"];
+    let spans = wait_for_spans("4bf92f3577b34da6a3ce929d0e0e4736", &expected_spans).await;
+
+    for name in expected_spans {
+        assert!(spans.iter().any(|s| s.name == name), "span {} not in caller's trace", name);
+    }
+
+    let db_span = spans.iter().find(|s| s.name == "db.create_user").unwrap();
+    assert!(db_span
+        .attributes
+        .iter()
+        .any(|kv| kv.key.as_str() == "user_id" && kv.value.as_str().parse::<i64>().unwrap() > 0));
+
+    // Exported spans bypass log redaction, so they must not carry the username
+    for span in &spans {
+        assert!(
+            span.attributes.iter().all(|kv| !kv.value.as_str().contains("traceuser")),
+            "span {} exports the username",
+            span.name
+        );
+    }
+}
+
+#[actix_web::test]
+async fn test_tracing_records_find_user_queries() {
+    use crate::telemetry::RequestTracing;
+
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    span_exporter();
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let user_id = create_test_user(&db, "traceinfo", "password123").await;
+
+    let app = test::init_service(create_test_app(db).wrap(RequestTracing)).await;
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}", user_id))
+        .insert_header((
+            "traceparent",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        ))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let spans = wait_for_spans(
+        "0af7651916cd43dd8448eb211c80319c",
+        &["GET /api/users/{user_id}", "db.find_user_by_id", "db.select_users"],
+    )
+    .await;
+
+    let server = spans.iter().find(|s| s.name == "GET /api/users/{user_id}").expect("missing server span");
+    assert!(server
+        .attributes
+        .iter()
+        .any(|kv| kv.key.as_str() == "http.response.status_code" && kv.value.as_str() == "200"));
+
+    // One joined row per metadata entry, in a single query
+    let select = spans.iter().find(|s| s.name == "db.select_users").expect("missing select span");
+    assert!(select
+        .attributes
+        .iter()
+        .any(|kv| kv.key.as_str() == "rows" && kv.value.as_str() == "2"));
+    assert_eq!(spans.iter().filter(|s| s.name == "db.select_users").count(), 1);
+}
+
+#[actix_web::test]
+async fn test_tracing_marks_failed_requests() {
+    use crate::telemetry::RequestTracing;
+    use actix_web::dev::Service;
+
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    span_exporter();
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    // An inner middleware failing the request instead of producing a response
+    let app = test::init_service(
+        create_test_app(db)
+            .wrap_fn(|req, srv| {
+                let unavailable = req.path() == "/health";
+                let fut = srv.call(req);
+                async move {
+                    if unavailable {
+                        return Err(actix_web::error::ErrorServiceUnavailable("maintenance"));
+                    }
+                    fut.await
+                }
+            })
+            .wrap(RequestTracing),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/health")
+        .insert_header((
+            "traceparent",
+            "00-5b8efff798038103d269b633813fc60c-eee19b7ec3c1b174-01",
+        ))
+        .to_request();
+    assert!(app.call(req).await.is_err());
+
+    let spans = wait_for_spans("5b8efff798038103d269b633813fc60c", &["GET /health"]).await;
+    let server = spans.iter().find(|s| s.name == "GET /health").expect("missing server span");
+    assert!(server
+        .attributes
+        .iter()
+        .any(|kv| kv.key.as_str() == "http.response.status_code" && kv.value.as_str() == "503"));
+    assert!(matches!(server.status, opentelemetry::trace::Status::Error { .. }));
+}
+
+// ============ Metadata Tree Tests ============
+
+#[actix_web::test]
+async fn test_create_user_with_nested_metadata_json() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db)).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_json(serde_json::json!({
+            "username": "nested",
+            "password": "password123",
+            "hobby": "Chess",
+            "extra_metadata": [
+                {"property": "address", "children": [
+                    {"property": "city", "value": "Berlin"},
+                    {"property": "geo", "children": [{"property": "lat", "value": "52.52"}]}
+                ]}
+            ]
+        }))
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    let user_id = String::from_utf8(body.to_vec()).unwrap();
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}/metadata", user_id))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let tree: Value = test::read_body_json(resp).await;
+    assert_eq!(tree[0]["property"], "hobby");
+    assert_eq!(tree[1]["property"], "address");
+    assert_eq!(tree[1]["children"][1]["children"][0]["value"], "52.52");
+    assert!(tree[0].get("children").is_none());
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}/metadata/geo", user_id))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let geo: Value = test::read_body_json(resp).await;
+    assert_eq!(geo["children"][0]["property"], "lat");
+}
+
+#[actix_web::test]
+async fn test_create_user_rejects_too_deep_metadata() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db)).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let mut node = serde_json::json!({"property": "leaf"});
+    for i in 0..crate::metadata::MAX_METADATA_DEPTH {
+        node = serde_json::json!({"property": format!("level{}", i), "children": [node]});
+    }
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_json(serde_json::json!({
+            "username": "deep",
+            "password": "password123",
+            "extra_metadata": [node]
+        }))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let body: Value = test::read_body_json(resp).await;
+    assert_error_response(&body, "VALIDATION_ERROR");
+}
+
+#[actix_web::test]
+async fn test_create_user_rejects_too_many_metadata_properties() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db)).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    // Repeated names would be the slowest to check if every sibling were compared
+    let nodes: Vec<Value> = (0..=crate::metadata::MAX_METADATA_NODES)
+        .map(|_| serde_json::json!({"property": "pet", "value": "cat"}))
+        .collect();
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_json(serde_json::json!({
+            "username": "crowded",
+            "password": "password123",
+            "extra_metadata": nodes
+        }))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let body: Value = test::read_body_json(resp).await;
+    assert_error_response(&body, "VALIDATION_ERROR");
+    assert!(body["message"].as_str().unwrap().contains("at most"));
+}
+
+#[actix_web::test]
+async fn test_replace_metadata_subtree() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    std::env::set_var("ADMIN_TOKEN", "admin-secret");
+    let user_id = create_test_user(&db, "subtree", "password123").await;
+    let app = test::init_service(create_test_app(db)).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    // Adds a new top-level property, then replaces it
+    for city in ["Oslo", "Bergen"] {
+        let req = test::TestRequest::put()
+            .uri(&format!("/api/users/{}/metadata/address", user_id))
+            .insert_header(("X-Admin-Token", "admin-secret"))
+            .set_json(serde_json::json!({"children": [{"property": "city", "value": city}]}))
+            .to_request();
+        let resp: ServiceResponse = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}/metadata/address", user_id))
+        .to_request();
+    let address: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(address["children"].as_array().unwrap().len(), 1);
+    assert_eq!(address["children"][0]["value"], "Bergen");
+
+    // Existing properties are untouched
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}/metadata", user_id))
+        .to_request();
+    let tree: Value = test::call_and_read_body_json(&app, req).await;
+    let properties: Vec<_> = tree.as_array().unwrap().iter().map(|n| n["property"].as_str().unwrap()).collect();
+    assert_eq!(properties, ["title", "hobby", "address"]);
+
+    std::env::remove_var("ADMIN_TOKEN");
+}
+
+#[actix_web::test]
+async fn test_multi_valued_metadata_subtree() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    std::env::set_var("ADMIN_TOKEN", "admin-secret");
+    let app = test::init_service(create_test_app(db)).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_json(serde_json::json!({"username": "hobbyist", "password": "password123", "hobby": ["chess", "golf"]}))
+        .to_request();
+    let user_id: i32 = std::str::from_utf8(&test::call_and_read_body(&app, req).await).unwrap().parse().unwrap();
+    let hobby = format!("/api/users/{}/metadata/hobby", user_id);
+
+    let req = test::TestRequest::get().uri(&hobby).to_request();
+    let values: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        values,
+        serde_json::json!([{"property": "hobby", "value": "chess"}, {"property": "hobby", "value": "golf"}])
+    );
+
+    // A PUT replaces every value
+    let req = test::TestRequest::put()
+        .uri(&hobby)
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .set_json(serde_json::json!({"value": "jazz"}))
+        .to_request();
+    let values: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(values, serde_json::json!([{"property": "hobby", "value": "jazz"}]));
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}/metadata", user_id))
+        .to_request();
+    let tree: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tree, serde_json::json!([{"property": "hobby", "value": "jazz"}]));
+
+    // A DELETE removes every value
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/users/{}/metadata", user_id))
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .set_json(serde_json::json!([
+            {"property": "hobby", "value": "chess"},
+            {"property": "title", "value": "Dr"},
+            {"property": "hobby", "value": "golf"}
+        ]))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 200);
+
+    let req = test::TestRequest::delete()
+        .uri(&hobby)
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 204);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}/metadata", user_id))
+        .to_request();
+    let tree: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tree, serde_json::json!([{"property": "title", "value": "Dr"}]));
+
+    let req = test::TestRequest::get().uri(&hobby).to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+
+    std::env::remove_var("ADMIN_TOKEN");
+}
+
+#[actix_web::test]
+async fn test_create_user_form_accepts_repeated_keys() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db)).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_form([
+            ("username", "formhobby"),
+            ("password", "password123"),
+            ("title", "Dr"),
+            ("hobby", "chess"),
+            ("hobby", "golf"),
+        ])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let user_id = std::str::from_utf8(&test::read_body(resp).await).unwrap().to_string();
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}/metadata", user_id))
+        .to_request();
+    let tree: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        tree,
+        serde_json::json!([
+            {"property": "title", "value": "Dr"},
+            {"property": "hobby", "value": "chess"},
+            {"property": "hobby", "value": "golf"}
+        ])
+    );
+
+    // Only the list fields may repeat
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_form([("username", "formtwice"), ("username", "again"), ("password", "password123")])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn test_metadata_endpoints_not_found() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    std::env::set_var("ADMIN_TOKEN", "admin-secret");
+    let user_id = create_test_user(&db, "nometa", "password123").await;
+    let app = test::init_service(create_test_app(db)).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}/metadata/missing", user_id))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+    let body: Value = test::read_body_json(resp).await;
+    assert_error_response(&body, "METADATA_NOT_FOUND");
+
+    let req = test::TestRequest::put()
+        .uri("/api/users/99999/metadata/address")
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .set_json(serde_json::json!({"value": "x"}))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+    let body: Value = test::read_body_json(resp).await;
+    assert_error_response(&body, "USER_NOT_FOUND");
+
+    let req = test::TestRequest::get().uri("/api/users/abc/metadata").to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    std::env::remove_var("ADMIN_TOKEN");
+}
+
+#[actix_web::test]
+async fn test_metadata_is_validated_and_typed_by_schema() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    std::env::set_var("ADMIN_TOKEN", "admin-secret");
+    let schema = MetadataSchema::from_json(
+        r#"{"properties": {"age": {"type": "int"}, "profile": {}, "profile.verified": {"type": "bool"}}}"#,
+    )
+    .unwrap();
+    let app = test::init_service(create_test_app_with_schema(db, schema)).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_json(serde_json::json!({
+            "username": "badage",
+            "password": "password123",
+            "extra_metadata": [{"property": "age", "value": "old"}]
+        }))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let body: Value = test::read_body_json(resp).await;
+    assert_error_response(&body, "VALIDATION_ERROR");
+    assert!(body["message"].as_str().unwrap().contains("age"));
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_json(serde_json::json!({
+            "username": "typed",
+            "password": "password123",
+            "extra_metadata": [{"property": "age", "value": 42}]
+        }))
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    let user_id = String::from_utf8(body.to_vec()).unwrap();
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}/metadata", user_id))
+        .to_request();
+    let tree: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tree[0]["value"], 42);
+
+    // Updates are checked against the registered type of the nested path
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/users/{}/metadata/profile", user_id))
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .set_json(serde_json::json!({"children": [{"property": "verified", "value": "maybe"}]}))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/users/{}/metadata/profile", user_id))
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .set_json(serde_json::json!({"children": [{"property": "verified", "value": true}]}))
+        .to_request();
+    let profile: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(profile["children"][0]["value"], true);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}/metadata/verified", user_id))
+        .to_request();
+    let verified: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(verified["value"], true);
+
+    std::env::remove_var("ADMIN_TOKEN");
+}
+
+#[actix_web::test]
+async fn test_delete_and_bulk_replace_metadata() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    std::env::set_var("ADMIN_TOKEN", "admin-secret");
+    let user_id = create_test_user(&db, "bulkmeta", "password123").await;
+    let schema = MetadataSchema::from_json(r#"{"properties": {"newsletter": {"type": "bool", "required": true}}}"#).unwrap();
+    let app = test::init_service(create_test_app_with_schema(db, schema)).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/users/{}/metadata", user_id))
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .set_json(serde_json::json!([
+            {"property": "newsletter", "value": true},
+            {"property": "address", "children": [{"property": "city", "value": "Oslo"}]}
+        ]))
+        .to_request();
+    let tree: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tree[0]["value"], true);
+
+    // The title and hobby rows from creation are gone
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}/metadata", user_id))
+        .to_request();
+    let tree: Value = test::call_and_read_body_json(&app, req).await;
+    let properties: Vec<_> = tree.as_array().unwrap().iter().map(|n| n["property"].as_str().unwrap()).collect();
+    assert_eq!(properties, ["newsletter", "address"]);
+
+    // Siblings must have distinct names
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/users/{}/metadata", user_id))
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .set_json(serde_json::json!([
+            {"property": "newsletter", "value": true},
+            {"property": "pet", "value": "cat"},
+            {"property": "pet", "value": "dog"}
+        ]))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let body: Value = test::read_body_json(resp).await;
+    assert_error_response(&body, "VALIDATION_ERROR");
+
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/users/{}/metadata/address", user_id))
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 204);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}/metadata/city", user_id))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/users/{}/metadata/address", user_id))
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+    let body: Value = test::read_body_json(resp).await;
+    assert_error_response(&body, "METADATA_NOT_FOUND");
+
+    // Required properties stay
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/users/{}/metadata/newsletter", user_id))
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let req = test::TestRequest::put()
+        .uri("/api/users/99999/metadata")
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .set_json(serde_json::json!([{"property": "newsletter", "value": false}]))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+    let body: Value = test::read_body_json(resp).await;
+    assert_error_response(&body, "USER_NOT_FOUND");
+
+    std::env::remove_var("ADMIN_TOKEN");
+}
+
+/// Sign in through the login page and return the session cookie
+macro_rules! sign_in {
+    ($app:expr, $username:expr, $password:expr) => {{
+        let req = test::TestRequest::get().uri("/login").to_request();
+        let (csrf, token) = form_token(test::call_service($app, req).await).await;
+        let req = test::TestRequest::post()
+            .uri("/login")
+            .cookie(csrf)
+            .set_form([("username", $username), ("password", $password), ("csrf_token", token.as_str())])
+            .to_request();
+        let resp: ServiceResponse = test::call_service($app, req).await;
+        assert_eq!(resp.status().as_u16(), 303);
+        session_cookie(&resp)
+    }};
+}
+
+#[actix_web::test]
+async fn test_metadata_changes_require_owner_or_admin() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let owner = create_test_user(&db, "metaowner", "password123").await;
+    let app = test::init_service(create_test_app(db)).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_form([("username", "metaother"), ("password", "password123")])
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 200);
+
+    std::env::set_var("ADMIN_TOKEN", "admin-secret");
+    let owner_session = sign_in!(&app, "metaowner", "password123");
+    let other_session = sign_in!(&app, "metaother", "password123");
+
+    let subtree = format!("/api/users/{}/metadata/address", owner);
+    let whole = format!("/api/users/{}/metadata", owner);
+    let requests = || {
+        [
+            test::TestRequest::put().uri(&subtree).set_json(serde_json::json!({"value": "Oslo"})),
+            test::TestRequest::put().uri(&whole).set_json(serde_json::json!([{"property": "address", "value": "Oslo"}])),
+            test::TestRequest::delete().uri(&subtree),
+        ]
+    };
+
+    for req in requests() {
+        let resp: ServiceResponse = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let body: Value = test::read_body_json(resp).await;
+        assert_error_response(&body, "AUTHENTICATION_REQUIRED");
+    }
+    for req in requests() {
+        let resp: ServiceResponse = test::call_service(&app, req.cookie(other_session.clone()).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 403);
+        let body: Value = test::read_body_json(resp).await;
+        assert_error_response(&body, "FORBIDDEN");
+    }
+    for req in requests() {
+        let req = req.cookie(other_session.clone()).insert_header(("X-Admin-Token", "wrong"));
+        let resp: ServiceResponse = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    // Nothing was changed
+    let req = test::TestRequest::get().uri(&subtree).to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+
+    let expected = [200, 200, 204];
+    for (req, status) in requests().into_iter().zip(expected) {
+        let resp: ServiceResponse = test::call_service(&app, req.cookie(owner_session.clone()).to_request()).await;
+        assert_eq!(resp.status().as_u16(), status);
+    }
+    for (req, status) in requests().into_iter().zip(expected) {
+        let req = req.insert_header(("X-Admin-Token", "admin-secret"));
+        let resp: ServiceResponse = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), status);
+    }
+
+    std::env::remove_var("ADMIN_TOKEN");
+}
+
+#[actix_web::test]
+async fn test_search_users_by_metadata() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db)).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    for (username, title, hobby) in [("drchess", "Dr", "Chess"), ("mrchess", "Mr", "chess"), ("drgo", "Dr", "Go")] {
+        let req = test::TestRequest::post()
+            .uri("/api/create-user")
+            .set_form([("username", username), ("password", "password123"), ("title", title), ("hobby", hobby)])
+            .to_request();
+        let resp: ServiceResponse = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+
+    let req = test::TestRequest::post()
+        .uri("/api/users/search")
+        .set_json(serde_json::json!({
+            "conditions": [
+                {"property": "title", "value": "Dr"},
+                {"property": "hobby", "value": "CHESS", "ignore_case": true}
+            ]
+        }))
+        .to_request();
+    let users: Value = test::call_and_read_body_json(&app, req).await;
+    let users = users.as_array().unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0]["username"], "drchess");
+    assert_eq!(users[0]["hobby"], "Chess");
+
+    let req = test::TestRequest::post()
+        .uri("/api/users/search")
+        .set_json(serde_json::json!({
+            "combine": "any",
+            "conditions": [
+                {"property": "hobby", "value": "ch", "match": "prefix"},
+                {"property": "hobby", "value": "G", "match": "prefix"}
+            ]
+        }))
+        .to_request();
+    let users: Value = test::call_and_read_body_json(&app, req).await;
+    let names: Vec<_> = users.as_array().unwrap().iter().map(|u| u["username"].as_str().unwrap()).collect();
+    assert_eq!(names, ["mrchess", "drgo"]);
+
+    let req = test::TestRequest::post()
+        .uri("/api/users/search")
+        .set_json(serde_json::json!({"conditions": []}))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let body: Value = test::read_body_json(resp).await;
+    assert_error_response(&body, "VALIDATION_ERROR");
+}
+
+#[actix_web::test]
+async fn test_search_users_orders_names_for_the_locale() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db)).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    for (username, locale) in [("yamada", Some("ja")), ("smith", None)] {
+        let mut extra_metadata = vec![serde_json::json!({"property": "team", "value": "search"})];
+        if let Some(locale) = locale {
+            extra_metadata.push(serde_json::json!({"property": "locale", "value": locale}));
+        }
+        let req = test::TestRequest::post()
+            .uri("/api/create-user")
+            .set_json(serde_json::json!({
+                "username": username,
+                "password": "password123",
+                "first_name": "Taro",
+                "last_name": "Yamada",
+                "extra_metadata": extra_metadata
+            }))
+            .to_request();
+        let resp: ServiceResponse = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+
+    let search = |accept_language: Option<&'static str>| {
+        let mut req = test::TestRequest::post()
+            .uri("/api/users/search")
+            .set_json(serde_json::json!({"conditions": [{"property": "team", "value": "search"}]}));
+        if let Some(accept_language) = accept_language {
+            req = req.insert_header(("Accept-Language", accept_language));
+        }
+        req.to_request()
+    };
+    let names = |users: Value| -> Vec<String> {
+        users.as_array().unwrap().iter().map(|u| u["name"].as_str().unwrap().to_string()).collect()
+    };
+
+    // A user's `locale` metadata wins over the header
+    let resp: ServiceResponse = test::call_service(&app, search(None)).await;
+    assert!(resp.headers().get("vary").unwrap().to_str().unwrap().contains("Accept-Language"));
+    let users: Value = test::read_body_json(resp).await;
+    assert_eq!(names(users), ["Yamada Taro", "Taro Yamada"]);
+
+    let users: Value = test::call_and_read_body_json(&app, search(Some("ja-JP, en;q=0.5"))).await;
+    assert_eq!(names(users), ["Yamada Taro", "Yamada Taro"]);
+}
+
+#[actix_web::test]
+async fn test_user_info_greeting_template_selection_and_reload() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    std::env::set_var("ADMIN_TOKEN", "admin-secret");
+    let user_id = create_test_user(&db, "templated", "password123").await;
+
+    let dir = std::env::temp_dir().join(format!("greeting_templates_{}_handler", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("short.j2"), "Hi {{ first_name }}").unwrap();
+    let app = test::init_service(create_test_app_with_templates(db, GreetingTemplates::load(&dir).unwrap())).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}?template=short", user_id))
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    assert_eq!(body, "Hi Test");
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}?template=formal", user_id))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    // A template added on disk is picked up by a reload
+    std::fs::write(dir.join("formal.j2"), "Dear {{ last_name }}").unwrap();
+    let req = test::TestRequest::post().uri("/admin/greeting-templates/reload").to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 401);
+
+    let req = test::TestRequest::post()
+        .uri("/admin/greeting-templates/reload")
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["templates"], serde_json::json!(["default", "formal", "short"]));
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}?template=formal", user_id))
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    assert_eq!(body, "Dear User");
+
+    // An invalid template is reported and the loaded set stays in use
+    std::fs::write(dir.join("formal.j2"), "Dear {{ last_name").unwrap();
+    let req = test::TestRequest::post()
+        .uri("/admin/greeting-templates/reload")
+        .insert_header(("X-Admin-Token", "admin-secret"))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let body: Value = test::read_body_json(resp).await;
+    assert_error_response(&body, "INVALID_TEMPLATES");
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}?template=formal", user_id))
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    assert_eq!(body, "Dear User");
+
+    std::env::remove_var("ADMIN_TOKEN");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[actix_web::test]
+async fn test_user_info_greeting_is_localized() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db.clone())).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let user_id = create_test_user(&db, "localized", "password123").await;
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}", user_id))
+        .insert_header(("Accept-Language", "fr;q=0.9, de-AT;q=0.8, en;q=0.5"))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers().get("content-language").unwrap(), "de");
+    // The CORS middleware appends Origin and the preflight headers
+    assert!(resp.headers().get("vary").unwrap().to_str().unwrap().starts_with("Accept, Accept-Language, Origin"));
+    let body = test::read_body(resp).await;
+    assert_eq!(
+        body,
+        "Hallo Engineer Test User, willkommen! Wenn wir Interessantes über Coding hören, sagen wir Ihnen unter test@example.com Bescheid!"
+    );
+
+    // A `locale` metadata property wins over the header
+    let locale = crate::metadata::MetadataNode { property: "locale".to_string(), value: Some("ja-JP".to_string()), children: vec![] };
+    db.replace_metadata_subtree(user_id, &locale, &MetadataSchema::default()).await.unwrap();
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}", user_id))
+        .insert_header(("Accept-Language", "de"))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get("content-language").unwrap(), "ja");
+    let body = test::read_body(resp).await;
+    assert_eq!(
+        std::str::from_utf8(&body).unwrap(),
+        "Engineer User Testさん、ようこそ！Codingについて面白いニュースがあればtest@example.comにお知らせします！"
+    );
+}
+
+#[actix_web::test]
+async fn test_user_info_formats() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db.clone())).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let user_id = create_test_user(&db, "formatted", "password123").await;
+    let get = |uri: String, accept: &str| {
+        test::TestRequest::get()
+            .uri(&uri)
+            .insert_header(("Accept", accept.to_string()))
+            .to_request()
+    };
+
+    let resp: ServiceResponse = test::call_service(&app, get(format!("/api/users/{}", user_id), "application/json")).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "application/json");
+    // The CORS middleware appends Origin and the preflight headers
+    assert!(resp.headers().get("vary").unwrap().to_str().unwrap().starts_with("Accept, Accept-Language, Origin"));
+    assert!(resp.headers().get("content-language").is_none());
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["username"], "formatted");
+    assert_eq!(body["title"], "Engineer");
+
+    let resp: ServiceResponse = test::call_service(&app, get(format!("/api/users/{}", user_id), "text/html, */*;q=0.8")).await;
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/html; charset=utf-8");
+    assert_eq!(resp.headers().get("content-language").unwrap(), "en");
+    let body = test::read_body(resp).await;
+    let html = std::str::from_utf8(&body).unwrap();
+    assert!(html.starts_with(&format!("<section class=\"user-info\" data-user-id=\"{}\" lang=\"en\">", user_id)));
+    assert!(html.contains("<p class=\"greeting\">Hello Engineer Test User, welcome!"));
+
+    // ?format= wins over Accept
+    let resp: ServiceResponse =
+        test::call_service(&app, get(format!("/api/users/{}?format=markdown", user_id), "application/json")).await;
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/markdown; charset=utf-8");
+    let body = test::read_body(resp).await;
+    assert!(std::str::from_utf8(&body).unwrap().starts_with("## Test User\n\nHello Engineer Test User"));
+
+    let resp: ServiceResponse = test::call_service(&app, get(format!("/api/users/{}?format=vcard", user_id), "*/*")).await;
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/vcard; charset=utf-8");
+    let body = test::read_body(resp).await;
+    let vcard = std::str::from_utf8(&body).unwrap();
+    assert!(vcard.starts_with("BEGIN:VCARD\r\nVERSION:4.0\r\nFN:Test User\r\nN:User;Test;;;\r\n"));
+    assert!(vcard.contains("EMAIL:test@example.com\r\n"));
+
+    let resp: ServiceResponse = test::call_service(&app, get(format!("/api/users/{}", user_id), "image/png")).await;
+    assert_eq!(resp.status().as_u16(), 406);
+    let resp: ServiceResponse = test::call_service(&app, get(format!("/api/users/{}?format=pdf", user_id), "*/*")).await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn test_user_info_neutralizes_stored_control_characters() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db.clone())).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    // Written straight to the store, as rows created before input cleaning could be
+    use crate::db::{CreateUserRequest, UserProfile};
+    let user_id = db
+        .create_user(&CreateUserRequest {
+            username: "legacy".to_string(),
+            password: "password123".to_string(),
+            profile: Some(UserProfile {
+                first_name: Some("<b>Eve</b>\u{202E}".to_string()),
+                last_name: Some("\u{1B}[31mRed".to_string()),
+                email: None,
+                ..Default::default()
+            }),
+            metadata: vec![],
+        })
+        .await
+        .unwrap();
+
+    let req = test::TestRequest::get().uri(&format!("/api/users/{}", user_id)).to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    assert_eq!(std::str::from_utf8(&body).unwrap(), "Hello <b>Eve</b> \u{FFFD}[31mRed, welcome!");
+
+    let req = test::TestRequest::get().uri(&format!("/api/users/{}?format=html", user_id)).to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    let html = std::str::from_utf8(&body).unwrap();
+    assert!(html.contains("<h2>&lt;b&gt;Eve&lt;/b&gt; \u{FFFD}[31mRed</h2>"), "{}", html);
+    assert!(!html.contains('\u{202E}') && !html.contains('\u{1B}'));
+}
+
+#[actix_web::test]
+async fn test_user_info_uses_profile_name_parts() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db.clone())).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_json(serde_json::json!({
+            "username": "countess",
+            "password": "password123",
+            "first_name": "Ada",
+            "middle_name": "Augusta",
+            "last_name": "Lovelace",
+            "nickname": "Addie",
+            "honorific": "Countess",
+            "suffix": "FRS"
+        }))
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    let user_id: i32 = std::str::from_utf8(&body).unwrap().parse().unwrap();
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}?format=json", user_id))
+        .to_request();
+    let info: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(info["name"], "Countess Ada Augusta Lovelace, FRS");
+    assert_eq!(info["nickname"], "Addie");
+    assert_eq!(info["display_name"], Value::Null);
+
+    let req = test::TestRequest::get().uri(&format!("/api/users/{}", user_id)).to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    assert_eq!(body, "Hello Countess Ada Augusta Lovelace, FRS, welcome!");
+
+    let long_suffix = "x".repeat(256);
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_form([("username", "longsuffix"), ("password", "password123"), ("suffix", long_suffix.as_str())])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["message"], "suffix must be max 255 characters");
+}
+
+#[actix_web::test]
+async fn test_create_user_with_several_hobbies() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db.clone())).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_json(serde_json::json!({
+            "username": "hobbyist",
+            "password": "password123",
+            "first_name": "Ada",
+            "title": "Countess",
+            "hobby": ["chess", "hiking", "jazz"]
+        }))
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    let user_id: i32 = std::str::from_utf8(&body).unwrap().parse().unwrap();
+
+    let req = test::TestRequest::get().uri(&format!("/api/users/{}", user_id)).to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    assert_eq!(
+        body,
+        "Hello Countess Ada, welcome! If we hear interesting news about chess, hiking and jazz, we will let you know!"
+    );
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}?format=json", user_id))
+        .to_request();
+    let info: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(info["hobbies"], serde_json::json!(["chess", "hiking", "jazz"]));
+    assert_eq!(info["hobby"], "chess");
+    assert_eq!(info["titles"], serde_json::json!(["Countess"]));
+
+    // Each value is checked on its own
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_json(serde_json::json!({
+            "username": "longhobby",
+            "password": "password123",
+            "hobby": ["chess", "h".repeat(256)]
+        }))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["message"], "hobby must be max 255 characters");
+
+    // Properties without a multi-valued registration still appear once
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_json(serde_json::json!({
+            "username": "repeats",
+            "password": "password123",
+            "extra_metadata": [{"property": "pet", "value": "cat"}, {"property": "pet", "value": "dog"}]
+        }))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn test_web_ui_shares_the_api_origin() {
+    let (db, _mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db)).await;
+
+    let req = test::TestRequest::get().uri("/").to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/html; charset=utf-8");
+    let body = test::read_body(resp).await;
+    let page = std::str::from_utf8(&body).unwrap();
+    assert!(page.contains("fetch('/api/login'"));
+    assert!(!page.contains("localhost:8080"));
+
+    let req = test::TestRequest::get().uri("/style.css").to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers().get("cache-control").unwrap(), "public, max-age=3600");
+
+    // API routes registered before the UI keep their handlers
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_ne!(resp.status().as_u16(), 404);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "application/json");
+
+    let req = test::TestRequest::get().uri("/missing.html").to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+}
+
+// ============ Page Tests ============
+
+/// Session cookie a page response set
+fn session_cookie(resp: &ServiceResponse) -> actix_web::cookie::Cookie<'static> {
+    resp.response()
+        .cookies()
+        .find(|c| c.name() == SESSION_COOKIE)
+        .expect("response sets the session cookie")
+        .into_owned()
+}
+
+/// CSRF cookie a rendered page set and the token its forms carry
+async fn form_token(resp: ServiceResponse) -> (actix_web::cookie::Cookie<'static>, String) {
+    let cookie = resp
+        .response()
+        .cookies()
+        .find(|c| c.name() == CSRF_COOKIE)
+        .expect("page sets the CSRF cookie")
+        .into_owned();
+    let body = test::read_body(resp).await;
+    let page = std::str::from_utf8(&body).unwrap();
+    let marker = r#"name="csrf_token" value=""#;
+    let start = page.find(marker).expect("page has a CSRF field") + marker.len();
+    let token = page[start..].split('"').next().unwrap().to_string();
+    (cookie, token)
+}
+
+#[actix_web::test]
+async fn test_signup_page_signs_in_and_shows_profile() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db)).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let req = test::TestRequest::get().uri("/signup").to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers().get("cache-control").unwrap(), "no-store");
+    let (csrf, token) = form_token(resp).await;
+    assert_eq!(csrf.same_site(), Some(actix_web::cookie::SameSite::Strict));
+
+    let req = test::TestRequest::post()
+        .uri("/signup")
+        .cookie(csrf)
+        .set_form([
+            ("username", "pageuser"),
+            ("password", "secret"),
+            ("first_name", "Page"),
+            ("last_name", ""),
+            ("email", ""),
+            ("title", "Dr."),
+            ("hobby", "chess"),
+            ("csrf_token", &token),
+        ])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 303);
+    assert_eq!(resp.headers().get("location").unwrap(), "/profile");
+    let cookie = session_cookie(&resp);
+    assert_eq!(cookie.http_only(), Some(true));
+    assert_eq!(cookie.same_site(), Some(actix_web::cookie::SameSite::Lax));
+
+    let req = test::TestRequest::get().uri("/profile").cookie(cookie).to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/html; charset=utf-8");
+    let body = test::read_body(resp).await;
+    let page = std::str::from_utf8(&body).unwrap();
+    assert!(page.contains("Hello Dr. Page, welcome!"), "{}", page);
+    assert!(page.contains("<dd>pageuser</dd>"));
+    assert!(page.contains("<dd>chess</dd>"));
+    assert!(page.contains(r#"action="/logout""#));
+    assert!(!page.contains("<script"));
+}
+
+#[actix_web::test]
+async fn test_signup_page_shows_errors_inline() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db.clone())).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let req = test::TestRequest::get().uri("/signup").to_request();
+    let (csrf, token) = form_token(test::call_service(&app, req).await).await;
+
+    let req = test::TestRequest::post()
+        .uri("/signup")
+        .cookie(csrf.clone())
+        .set_form([("username", ""), ("password", "secret"), ("first_name", "<b>Kept</b>"), ("csrf_token", &token)])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    assert!(resp.response().cookies().next().is_none(), "neither signed in nor given a new token");
+    let body = test::read_body(resp).await;
+    let page = std::str::from_utf8(&body).unwrap();
+    assert!(page.contains(r#"<p id="username-error" class="field-error">Username is required"#), "{}", page);
+    assert!(page.contains(r#"value="&lt;b&gt;Kept&lt;&#x2f;b&gt;""#), "{}", page);
+    assert!(page.contains(&format!(r#"name="csrf_token" value="{}""#, token)));
+    assert!(!page.contains("secret"), "the password is not echoed back");
+
+    create_test_user(&db, "taken", "password").await;
+    let req = test::TestRequest::post()
+        .uri("/signup")
+        .cookie(csrf)
+        .set_form([("username", "taken"), ("password", "secret"), ("csrf_token", &token)])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 409);
+    let body = test::read_body(resp).await;
+    let page = std::str::from_utf8(&body).unwrap();
+    assert!(page.contains("Username &#x27;taken&#x27; already exists"), "{}", page);
+}
+
+#[actix_web::test]
+async fn test_login_page_shows_errors_inline() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db.clone())).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    create_test_user(&db, "pagelogin", "correct_password").await;
+    let req = test::TestRequest::get().uri("/login").to_request();
+    let (csrf, token) = form_token(test::call_service(&app, req).await).await;
+
+    let req = test::TestRequest::post()
+        .uri("/login")
+        .cookie(csrf.clone())
+        .set_form([("username", "pagelogin"), ("csrf_token", &token)])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let body = test::read_body(resp).await;
+    let page = std::str::from_utf8(&body).unwrap();
+    assert!(page.contains("Password is required"));
+    assert!(page.contains(r#"value="pagelogin""#));
+
+    let req = test::TestRequest::post()
+        .uri("/login")
+        .cookie(csrf.clone())
+        .set_form([("username", "pagelogin"), ("password", "wrong"), ("csrf_token", &token)])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 401);
+    assert!(resp.response().cookies().next().is_none());
+    let body = test::read_body(resp).await;
+    assert!(std::str::from_utf8(&body).unwrap().contains("Invalid username or password"));
+
+    let req = test::TestRequest::post()
+        .uri("/login")
+        .cookie(csrf.clone())
+        .set_form([("username", "pagelogin"), ("password", "correct_password"), ("csrf_token", &token)])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 303);
+    assert_eq!(resp.headers().get("location").unwrap(), "/profile");
+
+    let session = session_cookie(&resp);
+    let rotated = resp
+        .response()
+        .cookies()
+        .find(|c| c.name() == CSRF_COOKIE)
+        .expect("signing in rotates the CSRF cookie")
+        .into_owned();
+    assert_ne!(rotated.value(), token);
+
+    // Signed in, the login page moves on to the profile
+    let req = test::TestRequest::get().uri("/login").cookie(session.clone()).to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 303);
+    assert_eq!(resp.headers().get("location").unwrap(), "/profile");
+
+    // The token from before signing in no longer passes; the rotated one does
+    for (csrf, status) in [(csrf, 403), (rotated, 303)] {
+        let req = test::TestRequest::post()
+            .uri("/logout")
+            .cookie(session.clone())
+            .cookie(csrf.clone())
+            .set_form([("csrf_token", csrf.value())])
+            .to_request();
+        let resp: ServiceResponse = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), status);
+    }
+}
+
+#[actix_web::test]
+async fn test_profile_page_requires_a_valid_session() {
+    let (db, _mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db)).await;
+
+    let req = test::TestRequest::get().uri("/profile").to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 303);
+    assert_eq!(resp.headers().get("location").unwrap(), "/login");
+
+    // Signed by another key, as after a restart without SESSION_SECRET
+    let forged = SessionKey::default().cookie(1);
+    let req = test::TestRequest::get().uri("/profile").cookie(forged).to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 303);
+    assert_eq!(resp.headers().get("location").unwrap(), "/login");
+
+    let req = test::TestRequest::get().uri("/login").to_request();
+    let (csrf, token) = form_token(test::call_service(&app, req).await).await;
+    let req = test::TestRequest::post()
+        .uri("/logout")
+        .cookie(csrf)
+        .set_form([("csrf_token", &token)])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 303);
+    assert_eq!(resp.headers().get("location").unwrap(), "/login");
+    let cookie = session_cookie(&resp);
+    assert_eq!(cookie.value(), "");
+    assert_eq!(cookie.max_age(), Some(actix_web::cookie::time::Duration::ZERO));
+}
+
+#[actix_web::test]
+async fn test_page_forms_require_the_csrf_token() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db.clone())).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    create_test_user(&db, "csrfuser", "correct_password").await;
+    let credentials = [("username", "csrfuser"), ("password", "correct_password")];
+
+    // No cookie and no token, as from a forged cross-site form
+    let req = test::TestRequest::post().uri("/login").set_form(credentials).to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+    assert!(resp.response().cookies().all(|c| c.name() != SESSION_COOKIE));
+    let (csrf, token) = form_token(resp).await;
+
+    // A token that doesn't match the cookie
+    let other = SessionKey::default().csrf_token(&test::TestRequest::default().to_http_request()).0;
+    let req = test::TestRequest::post()
+        .uri("/login")
+        .cookie(csrf.clone())
+        .set_form([credentials[0], credentials[1], ("csrf_token", &other)])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+    let body = test::read_body(resp).await;
+    assert!(std::str::from_utf8(&body).unwrap().contains("This form has expired"));
+
+    let req = test::TestRequest::post()
+        .uri("/signup")
+        .set_form([("username", "forged"), ("password", "secret")])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+    assert!(matches!(db.authenticate_user("forged").await, Err(crate::db::DatabaseError::UserNotFound)));
+
+    let req = test::TestRequest::post().uri("/logout").set_form([("csrf_token", "")]).to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    // The token the 403 page was rendered with works
+    let req = test::TestRequest::post()
+        .uri("/login")
+        .cookie(csrf)
+        .set_form([credentials[0], credentials[1], ("csrf_token", &token)])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 303);
+}
+
+#[actix_web::test]
+async fn test_cross_origin_form_posts_are_rejected() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db.clone())).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    create_test_user(&db, "originuser", "correct_password").await;
+    let credentials = [("username", "originuser"), ("password", "correct_password")];
+
+    let req = test::TestRequest::post()
+        .uri("/api/login")
+        .insert_header(("Origin", "https://evil.example"))
+        .set_form(credentials)
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+    let json: Value = test::read_body_json(resp).await;
+    assert_eq!(json["error"], "CSRF_REJECTED");
+    assert!(json["message"].as_str().unwrap().contains("https://evil.example"));
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .insert_header(("Referer", "https://evil.example/attack.html"))
+        .set_form([("username", "crossorigin"), ("password", "secret")])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+    assert!(matches!(db.authenticate_user("crossorigin").await, Err(crate::db::DatabaseError::UserNotFound)));
+
+    let req = test::TestRequest::post()
+        .uri("/login")
+        .insert_header(("Origin", "null"))
+        .set_form(credentials)
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    // The service's own pages, and clients that name no origin at all, get through
+    let req = test::TestRequest::post()
+        .uri("/api/login")
+        .insert_header(("Origin", "http://localhost:8080"))
+        .set_form(credentials)
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let req = test::TestRequest::post().uri("/api/login").set_form(credentials).to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    // JSON needs a CORS preflight, so the origin check leaves it to the CORS policy
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .insert_header(("Origin", "https://evil.example"))
+        .set_json(serde_json::json!({"username": "jsonorigin", "password": "secret"}))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+}
+
+// ============ CORS Tests ============
+
+/// CORS config whose `api` scope allows `https://app.example.com` and `https://*.partner.test`
+/// with credentials; `/admin` keeps its default of no cross-origin access
+fn partner_cors() -> CorsConfig {
+    std::env::set_var("TEST_HANDLER_CORS_ALLOWED_ORIGINS", "https://app.example.com, https://*.partner.test");
+    std::env::set_var("TEST_HANDLER_CORS_ALLOW_CREDENTIALS", "true");
+    CorsConfig {
+        api: CorsPolicy::api().with_env("TEST_HANDLER_CORS").unwrap(),
+        ..CorsConfig::default()
+    }
+}
+
+/// Preflight for a cross-origin `method` request to `uri`
+fn preflight(uri: &str, origin: &str, method: &str) -> test::TestRequest {
+    test::TestRequest::default()
+        .method(actix_web::http::Method::OPTIONS)
+        .uri(uri)
+        .insert_header(("Origin", origin))
+        .insert_header(("Access-Control-Request-Method", method))
+}
+
+#[actix_web::test]
+async fn test_cors_allows_configured_origins_on_the_api() {
+    let (db, _mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app_with_cors(db, partner_cors())).await;
+
+    for origin in ["https://app.example.com", "https://eu.shop.partner.test"] {
+        let resp: ServiceResponse = test::call_service(&app, preflight("/api/users/search", origin, "POST").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200, "{}", origin);
+        assert_eq!(resp.headers().get("access-control-allow-origin").unwrap(), origin);
+        assert_eq!(resp.headers().get("access-control-allow-credentials").unwrap(), "true");
+        assert_eq!(resp.headers().get("access-control-max-age").unwrap(), "3600");
+        let methods = resp.headers().get("access-control-allow-methods").unwrap().to_str().unwrap();
+        assert!(methods.contains("POST") && !methods.contains("PATCH"), "{}", methods);
+    }
+
+    let resp: ServiceResponse = test::call_service(&app, preflight("/api/users/search", "https://evil.test", "POST").to_request()).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    assert!(resp.headers().get("access-control-allow-origin").is_none());
+
+    let resp: ServiceResponse = test::call_service(&app, preflight("/api/users/1", "https://app.example.com", "PATCH").to_request()).await;
+    assert_eq!(resp.status().as_u16(), 400, "methods outside the allow-list are refused");
+
+    // Actual requests from other origins get no CORS headers, so the browser hides the response
+    let req = test::TestRequest::get().uri("/api/users/1").insert_header(("Origin", "https://evil.test")).to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert!(resp.headers().get("access-control-allow-origin").is_none());
+
+    let req = test::TestRequest::get().uri("/api/users/1").insert_header(("Origin", "https://app.example.com")).to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get("access-control-allow-origin").unwrap(), "https://app.example.com");
+
+    // The same origins may post forms; others are refused before the handler
+    for (origin, status) in [("https://app.example.com", 401), ("https://evil.test", 403)] {
+        let req = test::TestRequest::post()
+            .uri("/api/login")
+            .insert_header(("Origin", origin))
+            .set_form([("username", "nobody"), ("password", "password123")])
+            .to_request();
+        let resp: ServiceResponse = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), status, "{}", origin);
+    }
+}
+
+#[actix_web::test]
+async fn test_cors_policies_are_per_scope() {
+    let (db, _mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app_with_cors(db, partner_cors())).await;
+
+    // The health check is public, without credentials
+    let req = test::TestRequest::get().uri("/health").insert_header(("Origin", "https://evil.test")).to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers().get("access-control-allow-origin").unwrap(), "*");
+    assert!(resp.headers().get("access-control-allow-credentials").is_none());
+
+    let resp: ServiceResponse = test::call_service(&app, preflight("/health", "https://evil.test", "DELETE").to_request()).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    // An origin the API trusts is still refused by the admin scope
+    let resp: ServiceResponse = test::call_service(&app, preflight("/admin/log-levels", "https://app.example.com", "PUT").to_request()).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    // Pages and the web UI are same-origin only
+    let req = test::TestRequest::get().uri("/login").insert_header(("Origin", "https://app.example.com")).to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert!(resp.headers().get("access-control-allow-origin").is_none());
+}
+
+#[actix_web::test]
+async fn test_cors_defaults_keep_the_api_same_origin() {
+    let (db, mock_logger, _guard) = setup_test_deps().await;
+    let app = test::init_service(create_test_app(db.clone())).await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let resp: ServiceResponse = test::call_service(&app, preflight("/api/create-user", "https://evil.test", "POST").to_request()).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    // The web UI's own same-origin posts carry an Origin header and still work
+    create_test_user(&db, "sameorigin", "correct_password").await;
+    let req = test::TestRequest::post()
+        .uri("/api/login")
+        .insert_header(("Origin", "http://localhost:8080"))
+        .set_form([("username", "sameorigin"), ("password", "correct_password")])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+}
+
+// ============ Repository Tests ============
+
+/// Store whose every operation fails with the error the function builds
+struct FailingRepository(fn() -> crate::db::DatabaseError);
+
+#[async_trait::async_trait]
+impl UserRepository for FailingRepository {
+    async fn create_user(&self, _: &crate::db::CreateUserRequest) -> Result<i32, crate::db::DatabaseError> {
+        Err((self.0)())
+    }
+
+    async fn authenticate_user(&self, _: &str) -> Result<(i32, String), crate::db::DatabaseError> {
+        Err((self.0)())
+    }
+
+    async fn find_user_by_id(&self, _: i32) -> Result<crate::db::User, crate::db::DatabaseError> {
+        Err((self.0)())
+    }
+
+    async fn find_users_by_ids(&self, _: &[i32]) -> Result<Vec<crate::db::User>, crate::db::DatabaseError> {
+        Err((self.0)())
+    }
+
+    async fn find_users_by_metadata(&self, _: &crate::metadata_search::MetadataQuery) -> Result<Vec<crate::db::User>, crate::db::DatabaseError> {
+        Err((self.0)())
+    }
+
+    async fn find_metadata_subtree(&self, _: i32, _: &str) -> Result<crate::metadata::MetadataSubtree, crate::db::DatabaseError> {
+        Err((self.0)())
+    }
+
+    async fn replace_metadata_subtree(&self, _: i32, _: &crate::metadata::MetadataNode, _: &MetadataSchema) -> Result<Vec<crate::db::UserMetadata>, crate::db::DatabaseError> {
+        Err((self.0)())
+    }
+
+    async fn delete_metadata_subtree(&self, _: i32, _: &str, _: &MetadataSchema) -> Result<(), crate::db::DatabaseError> {
+        Err((self.0)())
+    }
+
+    async fn replace_metadata(&self, _: i32, _: &[crate::metadata::MetadataNode], _: &MetadataSchema) -> Result<(), crate::db::DatabaseError> {
+        Err((self.0)())
+    }
+}
+
+#[actix_web::test]
+async fn test_handlers_work_with_in_memory_store() {
+    use crate::repository::InMemoryUserRepository;
+
+    let (_db, mock_logger, _guard) = setup_test_deps().await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let app = test::init_service(create_test_app(InMemoryUserRepository::new())).await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_form([("username", "memuser"), ("password", "password123"), ("first_name", "Mem")])
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    let user_id = String::from_utf8(body.to_vec()).unwrap();
+
+    let req = test::TestRequest::post()
+        .uri("/api/login")
+        .set_form([("username", "memuser"), ("password", "password123")])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/users/{}", user_id))
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let body = test::read_body(resp).await;
+    assert!(String::from_utf8(body.to_vec()).unwrap().contains("Mem"));
+}
+
+#[actix_web::test]
+async fn test_unavailable_store_returns_service_unavailable() {
+    let (_db, mock_logger, _guard) = setup_test_deps().await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let app = test::init_service(create_test_app(FailingRepository(|| {
+        crate::db::DatabaseError::ConnectionError("injected".to_string())
+    }))).await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_form([("username", "nobody"), ("password", "password123")])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 503);
+    let body: Value = test::read_body_json(resp).await;
+    assert_error_response(&body, "DATABASE_UNAVAILABLE");
+
+    let req = test::TestRequest::post()
+        .uri("/api/login")
+        .set_form([("username", "nobody"), ("password", "password123")])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 503);
+
+    let req = test::TestRequest::get().uri("/api/users/1").to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 503);
+}
+
+#[actix_web::test]
+async fn test_transient_store_errors_ask_clients_to_retry() {
+    use crate::db::DatabaseError;
+
+    let (_db, mock_logger, _guard) = setup_test_deps().await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let cases = [
+        (FailingRepository(|| DatabaseError::PoolTimeout), 503, "DATABASE_BUSY", true),
+        (FailingRepository(|| DatabaseError::SerializationFailure), 503, "TRANSACTION_CONFLICT", true),
+        (
+            FailingRepository(|| DatabaseError::ForeignKeyViolation("fk_user".to_string())),
+            409,
+            "CONSTRAINT_VIOLATION",
+            false,
+        ),
+    ];
+
+    for (repo, status, code, retry) in cases {
+        let app = test::init_service(create_test_app(repo)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/create-user")
+            .set_form([("username", "someone"), ("password", "password123")])
+            .to_request();
+        let resp: ServiceResponse = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), status, "{}", code);
+        assert_eq!(resp.headers().get("retry-after").is_some(), retry, "{}", code);
+        let body: Value = test::read_body_json(resp).await;
+        assert_error_response(&body, code);
+
+        let req = test::TestRequest::get().uri("/api/users/1").to_request();
+        let resp: ServiceResponse = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), status, "{}", code);
+        assert_eq!(resp.headers().get("retry-after").is_some(), retry, "{}", code);
+    }
+}
+
+#[actix_web::test]
+async fn test_exhausted_pool_returns_database_busy() {
+    let (_db, mock_logger, _guard) = setup_test_deps().await;
+
+    Mock::given(method("POST"))
+        .and(path("/logs"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_logger)
+        .await;
+
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .acquire_timeout(std::time::Duration::from_millis(50))
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    let _held = pool.acquire().await.unwrap();
+    let app = test::init_service(create_test_app(Database::from_sqlite_pool(pool))).await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/create-user")
+        .set_form([("username", "someone"), ("password", "password123")])
+        .to_request();
+    let resp: ServiceResponse = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 503);
+    assert!(resp.headers().get("retry-after").is_some());
+    let body: Value = test::read_body_json(resp).await;
+    assert_error_response(&body, "DATABASE_BUSY");
+}
+
 // ============ Database Isolation Tests ============
 
 #[actix_web::test]
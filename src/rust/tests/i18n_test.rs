@@ -0,0 +1,136 @@
+use crate::db::{User, UserMetadata, UserProfile};
+use crate::greeting_templates::{GreetingTemplates, DEFAULT_TEMPLATE};
+use crate::i18n::{catalog, negotiate_locale, CATALOGS, DEFAULT_LOCALE};
+use crate::user_info_formatter::NameStyle;
+use fluent_bundle::FluentArgs;
+use fluent_syntax::ast;
+use unic_langid::LanguageIdentifier;
+
+fn locale(tag: &str) -> LanguageIdentifier {
+    tag.parse().unwrap()
+}
+
+fn user(metadata: &[(&str, &str)]) -> User {
+    User {
+        id: 1,
+        username: "tyamada".to_string(),
+        password: "hashed".to_string(),
+        profile: Some(UserProfile {
+            first_name: Some("Taro".to_string()),
+            last_name: Some("Yamada".to_string()),
+            email: Some("taro@example.com".to_string()),
+            ..Default::default()
+        }),
+        metadata: metadata
+            .iter()
+            .map(|(property, value)| UserMetadata {
+                parent_property: None,
+                property: property.to_string(),
+                value: Some(value.to_string()),
+                position: 0,
+            })
+            .collect(),
+        created_at: chrono::NaiveDate::from_ymd_opt(2020, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
+        updated_at: chrono::NaiveDate::from_ymd_opt(2020, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
+    }
+}
+
+/// Message IDs of a Fluent source, in file order
+fn message_ids(source: &str) -> Vec<String> {
+    let resource = fluent_syntax::parser::parse(source).unwrap();
+    resource
+        .body
+        .iter()
+        .filter_map(|entry| match entry {
+            ast::Entry::Message(message) => Some(message.id.name.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_every_catalog_has_the_default_locales_messages() {
+    let (_, default_source) = CATALOGS.iter().find(|(l, _)| *l == DEFAULT_LOCALE).unwrap();
+    let expected = message_ids(default_source);
+    assert!(!expected.is_empty());
+    for (tag, source) in CATALOGS {
+        assert_eq!(message_ids(source), expected, "catalog '{}'", tag);
+    }
+    assert_eq!(catalog().locales().len(), CATALOGS.len());
+}
+
+#[test]
+fn test_negotiation_picks_best_available_locale() {
+    let negotiate = |tags: &[&str]| catalog().negotiate(&tags.iter().map(|t| locale(t)).collect::<Vec<_>>());
+    assert_eq!(negotiate(&["de-CH"]), locale("de"));
+    assert_eq!(negotiate(&["fr", "ja"]), locale("ja"));
+    assert_eq!(negotiate(&["fr"]), locale("en"));
+    assert_eq!(negotiate(&[]), locale("en"));
+}
+
+#[test]
+fn test_negotiate_locale_prefers_user_metadata() {
+    assert_eq!(negotiate_locale(&user(&[]), Some("ja, en;q=0.5")), locale("ja"));
+    assert_eq!(negotiate_locale(&user(&[]), Some("fr-FR")), locale("en"));
+    assert_eq!(negotiate_locale(&user(&[]), Some("not a ### tag")), locale("en"));
+    assert_eq!(negotiate_locale(&user(&[]), None), locale("en"));
+    assert_eq!(negotiate_locale(&user(&[("locale", "de-DE")]), Some("ja")), locale("de"));
+    // An unsupported or malformed preference falls back to the header
+    assert_eq!(negotiate_locale(&user(&[("locale", "fr")]), Some("ja")), locale("ja"));
+    assert_eq!(negotiate_locale(&user(&[("locale", "###")]), Some("de")), locale("de"));
+}
+
+#[test]
+fn test_format_falls_back_to_default_locale_and_reports_missing_arguments() {
+    let mut args = FluentArgs::new();
+    args.set("name", "Taro");
+    assert_eq!(
+        catalog().format(&locale("fr"), "greeting-welcome", &args).as_deref(),
+        Some("Hello Taro, welcome!")
+    );
+    assert_eq!(catalog().format(&locale("de"), "greeting-unknown", &args), None);
+    assert_eq!(catalog().format(&locale("de"), "greeting-news", &args), None);
+}
+
+#[test]
+fn test_default_greeting_in_each_locale() {
+    let templates = GreetingTemplates::default();
+    let user = user(&[("title", "Dr."), ("hobby", "go")]);
+    let render = |tag: &str| templates.render(DEFAULT_TEMPLATE, &user, &locale(tag), NameStyle::Formal).unwrap();
+
+    assert_eq!(
+        render("en"),
+        "Hello Dr. Taro Yamada, welcome! If we hear interesting news about go, we will let you know at taro@example.com!"
+    );
+    assert_eq!(
+        render("de"),
+        "Hallo Dr. Taro Yamada, willkommen! Wenn wir Interessantes über go hören, sagen wir Ihnen unter taro@example.com Bescheid!"
+    );
+    // Japanese puts the family name first
+    assert_eq!(
+        render("ja"),
+        "Dr. Yamada Taroさん、ようこそ！goについて面白いニュースがあればtaro@example.comにお知らせします！"
+    );
+}
+
+#[test]
+fn test_format_list_in_each_locale() {
+    let list = |tag: &str, items: &[&str], limit: usize| {
+        let items: Vec<String> = items.iter().map(|s| s.to_string()).collect();
+        catalog().format_list(&locale(tag), &items, limit)
+    };
+
+    assert_eq!(list("en", &[], 3), "");
+    assert_eq!(list("en", &["chess"], 3), "chess");
+    assert_eq!(list("en", &["chess", "jazz"], 3), "chess and jazz");
+    assert_eq!(list("en", &["chess", "hiking", "jazz"], 3), "chess, hiking and jazz");
+    assert_eq!(list("en", &["chess", "hiking", "jazz", "go"], 2), "chess, hiking and 2 more");
+    assert_eq!(list("en", &["chess", "hiking"], 1), "chess and one more");
+
+    assert_eq!(list("de", &["Schach", "Wandern", "Jazz"], 3), "Schach, Wandern und Jazz");
+    assert_eq!(list("de", &["Schach", "Wandern", "Jazz"], 2), "Schach, Wandern und ein weiteres");
+
+    assert_eq!(list("ja", &["チェス", "ハイキング", "ジャズ"], 3), "チェス、ハイキング、ジャズ");
+    assert_eq!(list("ja", &["チェス", "ハイキング"], 3), "チェスとハイキング");
+    assert_eq!(list("ja", &["チェス", "ハイキング", "ジャズ", "囲碁"], 2), "チェス、ハイキング、他2件");
+}
@@ -0,0 +1,135 @@
+use super::TEST_MUTEX;
+use crate::logger::{parse_app_levels, sample_info, LogLevel, RemoteLogConfig};
+
+#[test]
+fn test_log_level_ordering() {
+    assert!(LogLevel::Debug < LogLevel::Info);
+    assert!(LogLevel::Info < LogLevel::Warn);
+    assert!(LogLevel::Warn < LogLevel::Error);
+}
+
+#[test]
+fn test_parse_app_levels() {
+    let levels = parse_app_levels("login_user=warn, get_user_info = ERROR,").unwrap();
+    assert_eq!(levels.len(), 2);
+    assert_eq!(levels.get("login_user"), Some(&LogLevel::Warn));
+    assert_eq!(levels.get("get_user_info"), Some(&LogLevel::Error));
+    assert!(parse_app_levels("").unwrap().is_empty());
+}
+
+#[test]
+fn test_parse_app_levels_rejects_malformed_pairs() {
+    for (value, culprit) in [("login_user=warn,bogus", "bogus"), ("main=loud", "loud"), ("=warn", "=warn")] {
+        let err = parse_app_levels(value).unwrap_err();
+        assert!(err.contains(culprit), "{} for {}", err, value);
+    }
+}
+
+#[test]
+fn test_default_config_forwards_everything() {
+    let config = RemoteLogConfig::default();
+    assert_eq!(config.min_level, LogLevel::Debug);
+    assert_eq!(config.info_sample_rate, 1.0);
+    assert_eq!(config.level_for("create_user"), LogLevel::Debug);
+}
+
+#[test]
+fn test_app_override_takes_precedence() {
+    let config = RemoteLogConfig {
+        min_level: LogLevel::Info,
+        app_levels: parse_app_levels("login_user=error").unwrap(),
+        info_sample_rate: 1.0,
+    };
+    assert_eq!(config.level_for("login_user"), LogLevel::Error);
+    assert_eq!(config.level_for("create_user"), LogLevel::Info);
+}
+
+#[test]
+fn test_validate_sample_rate() {
+    let mut config = RemoteLogConfig::default();
+    assert!(config.validate().is_ok());
+
+    config.info_sample_rate = 1.5;
+    assert!(config.validate().is_err());
+
+    config.info_sample_rate = -0.1;
+    assert!(config.validate().is_err());
+
+    config.info_sample_rate = f64::NAN;
+    assert!(config.validate().is_err());
+
+    config.info_sample_rate = 1.0;
+    config.app_levels.insert(" ".to_string(), LogLevel::Warn);
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_info_sampling() {
+    let forwarded = |rate: f64| (0..100).filter(|&n| sample_info(n, rate)).count();
+    assert_eq!(forwarded(1.0), 100);
+    assert_eq!(forwarded(0.5), 50);
+    assert_eq!(forwarded(0.1), 10);
+    assert_eq!(forwarded(0.0), 0);
+
+    // Every other event, starting with the second
+    assert!(!sample_info(0, 0.5));
+    assert!(sample_info(1, 0.5));
+}
+
+#[test]
+fn test_from_env_rejects_invalid_values() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+
+    // Only invalid values, so a config initialized meanwhile by another test keeps the defaults
+    let cases = [
+        ("LOGGER_INFO_SAMPLE_RATE", "5"),
+        ("LOGGER_INFO_SAMPLE_RATE", "-1"),
+        ("LOGGER_INFO_SAMPLE_RATE", "NaN"),
+        ("LOGGER_INFO_SAMPLE_RATE", "half"),
+        ("LOGGER_REMOTE_LEVEL", "loud"),
+        ("LOGGER_REMOTE_APP_LEVELS", "login_user=warn,bogus"),
+        ("LOGGER_REMOTE_APP_LEVELS", "login_user=loud"),
+    ];
+    for (name, value) in cases {
+        std::env::set_var(name, value);
+        let result = RemoteLogConfig::from_env();
+        std::env::remove_var(name);
+        let err = result.expect_err(&format!("accepted {}={}", name, value));
+        assert!(err.contains(name), "{}", err);
+    }
+}
+
+#[test]
+fn test_from_env_reads_levels() {
+    let _guard = TEST_MUTEX.lock().unwrap();
+
+    std::env::set_var("LOGGER_REMOTE_LEVEL", "Warning");
+    std::env::set_var("LOGGER_REMOTE_APP_LEVELS", "login_user=error");
+    let result = RemoteLogConfig::from_env();
+    std::env::remove_var("LOGGER_REMOTE_LEVEL");
+    std::env::remove_var("LOGGER_REMOTE_APP_LEVELS");
+
+    let config = result.unwrap();
+    assert_eq!(config.min_level, LogLevel::Warn);
+    assert_eq!(config.level_for("login_user"), LogLevel::Error);
+}
+
+#[test]
+fn test_config_json_round_trip() {
+    let json = serde_json::json!({
+        "min_level": "warn",
+        "app_levels": {"create_user": "debug"}
+    });
+    let config: RemoteLogConfig = serde_json::from_value(json).unwrap();
+    assert_eq!(config.min_level, LogLevel::Warn);
+    assert_eq!(config.app_levels.get("create_user"), Some(&LogLevel::Debug));
+    assert_eq!(config.info_sample_rate, 1.0);
+
+    let value = serde_json::to_value(&config).unwrap();
+    assert_eq!(value["min_level"], "warn");
+
+    // The JSON form accepts the environment spellings and rejects unknown levels
+    let config: RemoteLogConfig = serde_json::from_value(serde_json::json!({"min_level": "WARNING"})).unwrap();
+    assert_eq!(config.min_level, LogLevel::Warn);
+    assert!(serde_json::from_value::<RemoteLogConfig>(serde_json::json!({"min_level": "loud"})).is_err());
+}
@@ -72,21 +72,29 @@ fn test_user_response_serialization() {
     let user_response = UserInfoResponse {
         id: 1,
         username: "testuser".to_string(),
+        name: "Test User".to_string(),
         first_name: Some("Test".to_string()),
+        middle_name: None,
         last_name: Some("User".to_string()),
+        nickname: None,
+        honorific: None,
+        suffix: None,
+        display_name: None,
         email: Some("test@example.com".to_string()),
         title: Some("Engineer".to_string()),
         hobby: Some("Reading".to_string()),
+        titles: vec!["Engineer".to_string()],
+        hobbies: vec!["Reading".to_string()],
         metadata: vec![
-            UserMetadata {
-                parent_property: None,
+            TypedMetadataNode {
                 property: "title".to_string(),
-                value: Some("Engineer".to_string()),
+                value: "Engineer".into(),
+                children: vec![],
             },
-            UserMetadata {
-                parent_property: None,
+            TypedMetadataNode {
                 property: "hobby".to_string(),
-                value: Some("Reading".to_string()),
+                value: "Reading".into(),
+                children: vec![],
             }
         ],
     };
@@ -177,11 +185,19 @@ fn test_user_info_response_fields() {
     let response = UserInfoResponse {
         id: 123,
         username: "john_doe".to_string(),
+        name: "John Doe".to_string(),
         first_name: Some("John".to_string()),
+        middle_name: None,
         last_name: Some("Doe".to_string()),
+        nickname: None,
+        honorific: None,
+        suffix: None,
+        display_name: None,
         email: Some("john@example.com".to_string()),
         title: Some("Manager".to_string()),
         hobby: Some("Gaming".to_string()),
+        titles: vec!["Manager".to_string()],
+        hobbies: vec!["Gaming".to_string()],
         metadata: vec![],
     };
 
@@ -0,0 +1,189 @@
+use crate::metadata::{MetadataNode, MetadataSubtree};
+use crate::metadata_schema::{MetadataSchema, SchemaError, TypedMetadataSubtree};
+use serde_json::json;
+
+fn node(property: &str, value: Option<&str>, children: Vec<MetadataNode>) -> MetadataNode {
+    MetadataNode {
+        property: property.to_string(),
+        value: value.map(str::to_string),
+        children,
+    }
+}
+
+fn leaf(property: &str, value: &str) -> MetadataNode {
+    node(property, Some(value), vec![])
+}
+
+fn schema() -> MetadataSchema {
+    MetadataSchema::from_json(
+        r#"{
+            "allow_unknown": false,
+            "properties": {
+                "age": {"type": "int"},
+                "newsletter": {"type": "bool", "required": true},
+                "birthday": {"type": "date"},
+                "size": {"type": "enum", "values": ["S", "M", "L"]},
+                "homepage": {"type": "url"},
+                "nickname": {"max_length": 5},
+                "hobby": {"multi_valued": true},
+                "address": {},
+                "address.city": {"required": true}
+            }
+        }"#,
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_valid_tree_passes() {
+    let tree = vec![
+        leaf("newsletter", "true"),
+        leaf("age", "42"),
+        leaf("birthday", "1990-02-28"),
+        leaf("size", "M"),
+        leaf("homepage", "https://example.com/me"),
+        leaf("nickname", "Ace"),
+        leaf("hobby", "chess"),
+        leaf("hobby", "jazz"),
+        node("address", None, vec![leaf("city", "Berlin")]),
+    ];
+    assert_eq!(schema().validate(&tree), Ok(()));
+}
+
+#[test]
+fn test_values_must_match_their_type() {
+    let schema = schema();
+    let with = |extra: MetadataNode| vec![leaf("newsletter", "false"), extra];
+
+    for bad in [
+        leaf("age", "forty"),
+        leaf("newsletter", "yes"),
+        leaf("birthday", "1990-02-30"),
+        leaf("size", "XL"),
+        leaf("homepage", "ftp://example.com"),
+        leaf("homepage", "not a url"),
+    ] {
+        let property = bad.property.clone();
+        let tree = if property == "newsletter" { vec![bad] } else { with(bad) };
+        assert!(
+            matches!(schema.validate(&tree), Err(SchemaError::InvalidValue { property: p, .. }) if p == property),
+            "{} should be rejected",
+            property
+        );
+    }
+
+    assert_eq!(
+        schema.validate(&with(leaf("nickname", "Maverick"))),
+        Err(SchemaError::TooLong { property: "nickname".to_string(), max: 5 })
+    );
+}
+
+#[test]
+fn test_required_repeated_and_unknown_properties() {
+    let schema = schema();
+
+    assert_eq!(schema.validate(&[]), Err(SchemaError::Missing("newsletter".to_string())));
+
+    // Nested requirements only apply when the parent is present
+    assert_eq!(
+        schema.validate(&[leaf("newsletter", "true"), node("address", None, vec![])]),
+        Err(SchemaError::Missing("address.city".to_string()))
+    );
+
+    assert_eq!(
+        schema.validate(&[leaf("newsletter", "true"), leaf("newsletter", "false")]),
+        Err(SchemaError::Repeated("newsletter".to_string()))
+    );
+
+    assert_eq!(
+        schema.validate(&[leaf("newsletter", "true"), leaf("shoe", "44")]),
+        Err(SchemaError::Unknown("shoe".to_string()))
+    );
+}
+
+#[test]
+fn test_default_schema_accepts_unknown_properties() {
+    let schema = MetadataSchema::default();
+    assert_eq!(schema.validate(&[leaf("anything", "goes"), leaf("title", "Dr")]), Ok(()));
+    assert!(matches!(
+        schema.validate(&[leaf("title", &"t".repeat(256))]),
+        Err(SchemaError::TooLong { .. })
+    ));
+
+    // Titles and hobbies may repeat; unregistered properties may not
+    assert_eq!(schema.validate(&[leaf("hobby", "chess"), leaf("hobby", "jazz"), leaf("title", "Dr")]), Ok(()));
+    assert_eq!(
+        schema.validate(&[leaf("anything", "goes"), leaf("anything", "again")]),
+        Err(SchemaError::Repeated("anything".to_string()))
+    );
+}
+
+#[test]
+fn test_enum_without_values_is_rejected() {
+    assert!(MetadataSchema::from_json(r#"{"properties": {"size": {"type": "enum"}}}"#).is_err());
+    assert!(MetadataSchema::from_json(r#"{"properties": {"size": {"type": "color"}}}"#).is_err());
+}
+
+#[test]
+fn test_typed_tree_converts_values() {
+    let tree = vec![
+        leaf("age", "42"),
+        leaf("newsletter", "true"),
+        leaf("birthday", "1990-02-28"),
+        node("address", None, vec![leaf("city", "Berlin")]),
+        // Stored before the type was registered
+        leaf("age", "unknown"),
+    ];
+
+    let typed = serde_json::to_value(schema().typed_tree(&tree)).unwrap();
+    assert_eq!(
+        typed,
+        json!([
+            {"property": "age", "value": 42},
+            {"property": "newsletter", "value": true},
+            {"property": "birthday", "value": "1990-02-28"},
+            {"property": "address", "value": null, "children": [{"property": "city", "value": "Berlin"}]},
+            {"property": "age", "value": "unknown"}
+        ])
+    );
+}
+
+#[test]
+fn test_typed_subtree_uses_its_path() {
+    let schema = MetadataSchema::from_json(r#"{"properties": {"stats.age": {"type": "int"}}}"#).unwrap();
+    let subtree = MetadataSubtree {
+        path: "stats.age".to_string(),
+        nodes: vec![leaf("age", "7")],
+    };
+    assert!(matches!(schema.typed_subtree(&subtree), TypedMetadataSubtree::Single(node) if node.value == json!(7)));
+}
+
+#[test]
+fn test_typed_subtree_lists_multi_valued_properties() {
+    let schema = MetadataSchema::default();
+    let hobby = MetadataSubtree {
+        path: "hobby".to_string(),
+        nodes: vec![leaf("hobby", "chess")],
+    };
+    assert_eq!(
+        serde_json::to_value(schema.typed_subtree(&hobby)).unwrap(),
+        json!([{"property": "hobby", "value": "chess"}])
+    );
+}
+
+#[test]
+fn test_node_values_accept_json_scalars() {
+    let parsed: MetadataNode = serde_json::from_value(json!({
+        "property": "stats",
+        "children": [
+            {"property": "age", "value": 42},
+            {"property": "active", "value": false},
+            {"property": "nothing", "value": null}
+        ]
+    }))
+    .unwrap();
+    let values: Vec<_> = parsed.children.iter().map(|c| c.value.as_deref()).collect();
+    assert_eq!(values, [Some("42"), Some("false"), None]);
+
+    assert!(serde_json::from_value::<MetadataNode>(json!({"property": "x", "value": [1]})).is_err());
+}
@@ -0,0 +1,83 @@
+use crate::db::UserMetadata;
+use crate::metadata_search::{
+    escape_glob, escape_like, Combine, MatchMode, MetadataCondition, MetadataQuery, SearchError, MAX_SEARCH_LIMIT,
+};
+
+fn row(parent: Option<&str>, property: &str, value: &str) -> UserMetadata {
+    UserMetadata {
+        parent_property: parent.map(str::to_string),
+        property: property.to_string(),
+        value: Some(value.to_string()),
+        position: 0,
+    }
+}
+
+fn query(json: serde_json::Value) -> MetadataQuery {
+    serde_json::from_value(json).unwrap()
+}
+
+#[test]
+fn test_query_defaults() {
+    let q = query(serde_json::json!({"conditions": [{"property": "hobby", "value": "chess"}]}));
+    assert_eq!(q.combine, Combine::All);
+    assert_eq!(q.conditions[0].mode, MatchMode::Exact);
+    assert!(!q.conditions[0].ignore_case);
+    assert_eq!((q.limit, q.offset), (20, 0));
+}
+
+#[test]
+fn test_condition_targets_top_level_or_parent() {
+    let target = |property: &str| {
+        MetadataCondition {
+            property: property.to_string(),
+            value: String::new(),
+            mode: MatchMode::Exact,
+            ignore_case: false,
+        }
+        .target()
+        .map(|(parent, property)| (parent.map(str::to_string), property.to_string()))
+    };
+
+    assert_eq!(target("hobby"), Ok((None, "hobby".to_string())));
+    assert_eq!(target("address.city"), Ok((Some("address".to_string()), "city".to_string())));
+    for invalid in ["", "a.b.c", ".city", "address."] {
+        assert_eq!(target(invalid), Err(SearchError::InvalidProperty(invalid.to_string())));
+    }
+}
+
+#[test]
+fn test_matching_modes_and_combination() {
+    let rows = vec![row(None, "hobby", "Chess"), row(None, "title", "Dr"), row(Some("address"), "city", "Oslo")];
+    let matches = |json: serde_json::Value| query(json).matches(&rows);
+
+    assert!(matches(serde_json::json!({"conditions": [{"property": "hobby", "value": "Chess"}]})));
+    assert!(!matches(serde_json::json!({"conditions": [{"property": "hobby", "value": "chess"}]})));
+    assert!(matches(serde_json::json!({"conditions": [{"property": "hobby", "value": "chess", "ignore_case": true}]})));
+    assert!(matches(serde_json::json!({"conditions": [{"property": "hobby", "value": "Ch", "match": "prefix"}]})));
+    assert!(matches(serde_json::json!({"conditions": [{"property": "address.city", "value": "Oslo"}]})));
+    assert!(!matches(serde_json::json!({"conditions": [{"property": "city", "value": "Oslo"}]})));
+
+    let both = serde_json::json!([{"property": "title", "value": "Dr"}, {"property": "hobby", "value": "Go"}]);
+    assert!(!matches(serde_json::json!({"conditions": both})));
+    assert!(matches(serde_json::json!({"conditions": both, "combine": "any"})));
+}
+
+#[test]
+fn test_query_validation() {
+    assert_eq!(query(serde_json::json!({"conditions": []})).validate(), Err(SearchError::NoConditions));
+
+    let one = serde_json::json!([{"property": "hobby", "value": "chess"}]);
+    for limit in [0, MAX_SEARCH_LIMIT + 1] {
+        assert_eq!(
+            query(serde_json::json!({"conditions": one, "limit": limit})).validate(),
+            Err(SearchError::InvalidLimit)
+        );
+    }
+    assert_eq!(query(serde_json::json!({"conditions": one, "limit": MAX_SEARCH_LIMIT})).validate(), Ok(()));
+}
+
+#[test]
+fn test_pattern_escaping() {
+    assert_eq!(escape_like("50%_off!"), "50!%!_off!!");
+    assert_eq!(escape_glob("a*b?[c]"), "a[*]b[?][[]c]");
+}
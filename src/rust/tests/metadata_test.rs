@@ -0,0 +1,273 @@
+use crate::db::UserMetadata;
+use crate::metadata::{
+    build_tree, build_tree_lossy, flatten, flatten_tree, replace_subtree, subtree, subtree_rows, validate, values,
+    without_subtree, MetadataNode, MetadataTreeError, MAX_METADATA_DEPTH, MAX_METADATA_NODES,
+};
+
+fn row(parent: Option<&str>, property: &str, value: Option<&str>) -> UserMetadata {
+    UserMetadata {
+        parent_property: parent.map(str::to_string),
+        property: property.to_string(),
+        value: value.map(str::to_string),
+        position: 0,
+    }
+}
+
+fn leaf(property: &str, value: &str) -> MetadataNode {
+    MetadataNode {
+        property: property.to_string(),
+        value: Some(value.to_string()),
+        children: vec![],
+    }
+}
+
+fn address_rows() -> Vec<UserMetadata> {
+    vec![
+        row(None, "title", Some("Dr")),
+        row(None, "address", None),
+        row(Some("address"), "city", Some("Berlin")),
+        row(Some("address"), "geo", None),
+        row(Some("geo"), "lat", Some("52.52")),
+        row(Some("address"), "zip", Some("10115")),
+    ]
+}
+
+#[test]
+fn test_build_tree_nests_children_in_row_order() {
+    let tree = build_tree(&address_rows()).unwrap();
+
+    assert_eq!(tree.len(), 2);
+    assert_eq!(tree[0], leaf("title", "Dr"));
+    let address = &tree[1];
+    let children: Vec<_> = address.children.iter().map(|c| c.property.as_str()).collect();
+    assert_eq!(children, ["city", "geo", "zip"]);
+    assert_eq!(address.children[1].children, vec![leaf("lat", "52.52")]);
+}
+
+#[test]
+fn test_build_tree_accepts_children_before_parents() {
+    let rows = vec![row(Some("address"), "city", Some("Oslo")), row(None, "address", None)];
+    let tree = build_tree(&rows).unwrap();
+    assert_eq!(tree.len(), 1);
+    assert_eq!(tree[0].children, vec![leaf("city", "Oslo")]);
+}
+
+#[test]
+fn test_flatten_round_trips_through_build_tree() {
+    let tree = build_tree(&address_rows()).unwrap();
+    let rows = flatten(&tree, None);
+
+    assert_eq!(rows.len(), 6);
+    assert_eq!(rows[4].parent_property.as_deref(), Some("geo"));
+    assert_eq!(build_tree(&rows).unwrap(), tree);
+}
+
+#[test]
+fn test_orphan_is_rejected() {
+    let rows = vec![row(Some("missing"), "city", None)];
+    assert_eq!(
+        validate(&rows),
+        Err(MetadataTreeError::Orphan {
+            property: "city".to_string(),
+            parent: "missing".to_string()
+        })
+    );
+}
+
+#[test]
+fn test_cycles_are_rejected() {
+    let self_parent = vec![row(Some("loop"), "loop", None)];
+    assert!(matches!(validate(&self_parent), Err(MetadataTreeError::Cycle(_))));
+
+    // A row hanging off a cycle is reported through the cycle, not as too deep
+    let cycle = vec![
+        row(Some("b"), "a", None),
+        row(Some("a"), "b", None),
+        row(Some("a"), "c", None),
+    ];
+    assert!(matches!(validate(&cycle), Err(MetadataTreeError::Cycle(_))));
+}
+
+#[test]
+fn test_ambiguous_parent_is_rejected() {
+    let rows = vec![
+        row(None, "home", None),
+        row(Some("home"), "address", None),
+        row(None, "work", None),
+        row(Some("work"), "address", None),
+        row(Some("address"), "city", None),
+    ];
+    assert_eq!(validate(&rows), Err(MetadataTreeError::Ambiguous("address".to_string())));
+
+    // The same name under different parents is fine as long as nothing hangs off it
+    assert!(validate(&rows[..4]).is_ok());
+}
+
+#[test]
+fn test_repeated_siblings_are_rejected() {
+    assert_eq!(
+        validate(&[row(None, "hobby", Some("chess")), row(None, "hobby", Some("jazz"))]),
+        Err(MetadataTreeError::Duplicate("hobby".to_string()))
+    );
+    assert_eq!(
+        validate(&[
+            row(None, "address", None),
+            row(Some("address"), "city", Some("Oslo")),
+            row(Some("address"), "city", Some("Bergen")),
+        ]),
+        Err(MetadataTreeError::Duplicate("city".to_string()))
+    );
+}
+
+#[test]
+fn test_repeated_siblings_are_numbered() {
+    let rows = flatten(&[leaf("hobby", "chess"), leaf("title", "Dr"), leaf("hobby", "jazz")], None);
+    let positions: Vec<(&str, i32)> = rows.iter().map(|r| (r.property.as_str(), r.position)).collect();
+    assert_eq!(positions, vec![("hobby", 0), ("title", 0), ("hobby", 1)]);
+    assert!(validate(&rows).is_ok());
+    assert_eq!(build_tree(&rows).unwrap().len(), 3);
+}
+
+#[test]
+fn test_values_follow_position_order() {
+    let mut rows = vec![row(None, "hobby", Some("jazz")), row(None, "hobby", Some("chess")), row(None, "title", Some("Dr"))];
+    rows[0].position = 1;
+    assert_eq!(values(&rows, "hobby"), vec!["chess", "jazz"]);
+    assert_eq!(values(&rows, "title"), vec!["Dr"]);
+    assert!(values(&rows, "city").is_empty());
+
+    // Only top-level rows count
+    let nested = vec![row(None, "work", None), row(Some("work"), "hobby", Some("chess"))];
+    assert!(values(&nested, "hobby").is_empty());
+}
+
+#[test]
+fn test_depth_limit() {
+    let chain = |depth: usize| -> Vec<UserMetadata> {
+        (0..depth)
+            .map(|i| {
+                let parent = (i > 0).then(|| format!("level{}", i - 1));
+                row(parent.as_deref(), &format!("level{}", i), None)
+            })
+            .collect()
+    };
+
+    assert!(validate(&chain(MAX_METADATA_DEPTH)).is_ok());
+    assert_eq!(
+        validate(&chain(MAX_METADATA_DEPTH + 1)),
+        Err(MetadataTreeError::TooDeep(format!("level{}", MAX_METADATA_DEPTH)))
+    );
+}
+
+#[test]
+fn test_node_limit() {
+    let leaves = |count: usize| -> Vec<MetadataNode> { (0..count).map(|i| leaf(&format!("p{}", i), "x")).collect() };
+
+    assert_eq!(flatten_tree(&leaves(MAX_METADATA_NODES)).unwrap().len(), MAX_METADATA_NODES);
+    assert_eq!(flatten_tree(&leaves(MAX_METADATA_NODES + 1)), Err(MetadataTreeError::TooMany));
+    assert_eq!(validate(&flatten(&leaves(MAX_METADATA_NODES + 1), None)), Err(MetadataTreeError::TooMany));
+
+    // Children count too
+    let nested = MetadataNode {
+        property: "parent".to_string(),
+        value: None,
+        children: leaves(MAX_METADATA_NODES),
+    };
+    assert_eq!(flatten_tree(&[nested]), Err(MetadataTreeError::TooMany));
+}
+
+#[test]
+fn test_invalid_property_names_are_rejected() {
+    assert_eq!(validate(&[row(None, "", None)]), Err(MetadataTreeError::InvalidProperty));
+    assert_eq!(validate(&[row(None, &"p".repeat(256), None)]), Err(MetadataTreeError::InvalidProperty));
+    assert_eq!(validate(&[row(None, "a.b", None)]), Err(MetadataTreeError::InvalidProperty));
+}
+
+#[test]
+fn test_build_tree_lossy_lists_invalid_rows_flat() {
+    let rows = vec![row(None, "title", Some("Dr")), row(Some("missing"), "city", Some("Oslo"))];
+    let tree = build_tree_lossy(&rows);
+    assert_eq!(tree, vec![leaf("title", "Dr"), leaf("city", "Oslo")]);
+}
+
+#[test]
+fn test_subtree_extracts_nested_property() {
+    let rows = address_rows();
+
+    let geo = subtree(&rows, "geo").unwrap().unwrap();
+    assert_eq!(geo.path, "address.geo");
+    assert_eq!(geo.nodes[0].children, vec![leaf("lat", "52.52")]);
+    let address = subtree(&rows, "address").unwrap().unwrap();
+    assert_eq!(address.path, "address");
+    assert_eq!(address.nodes[0].children.len(), 3);
+    assert!(subtree(&rows, "nothing").unwrap().is_none());
+}
+
+#[test]
+fn test_subtree_covers_every_value_of_a_multi_valued_property() {
+    let mut rows = flatten(&[leaf("hobby", "chess"), leaf("title", "Dr"), leaf("hobby", "golf")], None);
+    rows.swap(0, 2);
+
+    let hobby = subtree(&rows, "hobby").unwrap().unwrap();
+    assert_eq!(hobby.path, "hobby");
+    assert_eq!(hobby.nodes, vec![leaf("hobby", "chess"), leaf("hobby", "golf")]);
+    assert_eq!(subtree_rows(&rows, "hobby").unwrap(), Some(vec![0, 2]));
+
+    // Replacing or removing the property covers all its values
+    let (removed, inserted) = replace_subtree(&rows, &leaf("hobby", "jazz")).unwrap();
+    assert_eq!(removed, [0, 2]);
+    assert_eq!(inserted, [row(None, "hobby", Some("jazz"))]);
+    assert_eq!(without_subtree(&rows, "hobby").unwrap(), Some(vec![row(None, "title", Some("Dr"))]));
+}
+
+#[test]
+fn test_children_of_a_multi_valued_property_hang_off_its_first_value() {
+    let chess = MetadataNode {
+        property: "hobby".to_string(),
+        value: Some("chess".to_string()),
+        children: vec![leaf("since", "2010")],
+    };
+    let nodes = vec![chess.clone(), leaf("hobby", "golf")];
+    let rows = flatten_tree(&nodes).unwrap();
+    assert_eq!(build_tree(&rows).unwrap(), nodes);
+    assert_eq!(subtree(&rows, "since").unwrap().unwrap().path, "hobby.since");
+
+    // Rows can't say which later value a child belongs to
+    assert_eq!(
+        flatten_tree(&[leaf("hobby", "golf"), chess]),
+        Err(MetadataTreeError::ChildrenOnLaterValue("hobby".to_string()))
+    );
+}
+
+#[test]
+fn test_replace_subtree_keeps_position_in_tree() {
+    let rows = address_rows();
+    let replacement = MetadataNode {
+        property: "geo".to_string(),
+        value: None,
+        children: vec![leaf("lat", "59.91"), leaf("lon", "10.75")],
+    };
+
+    let (removed, inserted) = replace_subtree(&rows, &replacement).unwrap();
+    assert_eq!(removed, [3, 4]);
+    assert_eq!(inserted[0].parent_property.as_deref(), Some("address"));
+    assert_eq!(inserted.len(), 3);
+
+    // A new property is added at the top level
+    let (removed, inserted) = replace_subtree(&rows, &leaf("nickname", "Ace")).unwrap();
+    assert!(removed.is_empty());
+    assert_eq!(inserted, [row(None, "nickname", Some("Ace"))]);
+
+    // The result must still be a valid tree
+    let too_deep = (0..MAX_METADATA_DEPTH).fold(leaf("bottom", "x"), |child, i| MetadataNode {
+        property: format!("n{}", i),
+        value: None,
+        children: vec![child],
+    });
+    let nested = MetadataNode {
+        property: "geo".to_string(),
+        value: None,
+        children: vec![too_deep],
+    };
+    assert!(matches!(replace_subtree(&rows, &nested), Err(MetadataTreeError::TooDeep(_))));
+}
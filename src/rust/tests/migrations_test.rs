@@ -0,0 +1,180 @@
+use crate::db::{Backend, CreateUserRequest, Database, DatabaseError, UserMetadata};
+use crate::migrations::{Migration, MYSQL_MIGRATIONS, POSTGRES_MIGRATIONS, SQLITE_MIGRATIONS};
+
+#[test]
+fn test_dialects_define_the_same_versions() {
+    let mysql: Vec<(i64, &str)> = MYSQL_MIGRATIONS.iter().map(|m| (m.version, m.name)).collect();
+    let sqlite: Vec<(i64, &str)> = SQLITE_MIGRATIONS.iter().map(|m| (m.version, m.name)).collect();
+    let postgres: Vec<(i64, &str)> = POSTGRES_MIGRATIONS.iter().map(|m| (m.version, m.name)).collect();
+    assert_eq!(mysql, sqlite, "every migration needs a file for each dialect");
+    assert_eq!(mysql, postgres, "every migration needs a file for each dialect");
+}
+
+#[test]
+fn test_versions_are_strictly_increasing() {
+    for migrations in [MYSQL_MIGRATIONS, SQLITE_MIGRATIONS, POSTGRES_MIGRATIONS] {
+        for pair in migrations.windows(2) {
+            assert!(pair[0].version < pair[1].version);
+        }
+    }
+}
+
+#[test]
+fn test_checksum_is_stable_sha256() {
+    let migration = Migration { version: 1, name: "test", sql: "" };
+    assert_eq!(
+        migration.checksum(),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(SQLITE_MIGRATIONS[0].checksum(), SQLITE_MIGRATIONS[0].checksum());
+    assert_ne!(SQLITE_MIGRATIONS[0].checksum(), MYSQL_MIGRATIONS[0].checksum());
+}
+
+#[actix_web::test]
+async fn test_migrations_apply_and_are_recorded() {
+    let db = Database::new_test().await.expect("Failed to create test database");
+
+    // new_test already migrated; a second run is a no-op
+    let report = db.run_migrations().await.unwrap();
+    assert!(report.applied.is_empty());
+    assert_eq!(report.already_applied.len(), db.backend().migrations().len());
+}
+
+#[actix_web::test]
+async fn test_pending_migrations_are_applied_in_order() {
+    let db = Database::new_test().await.expect("Failed to create test database");
+
+    let mut migrations = db.backend().migrations().to_vec();
+    migrations.push(Migration {
+        version: 9001,
+        name: "add_nickname",
+        sql: "ALTER TABLE user_profiles ADD COLUMN test_nickname VARCHAR(64);",
+    });
+
+    let report = db.apply_migrations(&migrations).await.unwrap();
+    assert_eq!(report.applied, vec![9001]);
+
+    let report = db.apply_migrations(&migrations).await.unwrap();
+    assert!(report.applied.is_empty());
+}
+
+#[actix_web::test]
+async fn test_concurrent_runs_apply_each_migration_once() {
+    let db = Database::new_test().await.expect("Failed to create test database");
+    // SQLite has no migration lock
+    if db.backend() == Backend::Sqlite {
+        return;
+    }
+
+    let mut migrations = db.backend().migrations().to_vec();
+    migrations.push(Migration {
+        version: 9004,
+        name: "add_pronouns",
+        sql: "ALTER TABLE user_profiles ADD COLUMN test_pronouns VARCHAR(64);",
+    });
+
+    let (first, second) = tokio::join!(db.apply_migrations(&migrations), db.apply_migrations(&migrations));
+    let mut applied = [first.unwrap().applied, second.unwrap().applied];
+    applied.sort();
+    assert_eq!(applied, [vec![], vec![9004]]);
+}
+
+#[actix_web::test]
+async fn test_modified_migration_is_rejected() {
+    let db = Database::new_test().await.expect("Failed to create test database");
+
+    let mut migrations = db.backend().migrations().to_vec();
+    migrations[0].sql = "CREATE TABLE IF NOT EXISTS edited (id INTEGER);";
+
+    let result = db.apply_migrations(&migrations).await;
+    assert!(matches!(result, Err(DatabaseError::MigrationError(msg)) if msg.contains("checksum")));
+}
+
+#[actix_web::test]
+async fn test_unknown_applied_migration_is_rejected() {
+    let db = Database::new_test().await.expect("Failed to create test database");
+
+    let result = db.apply_migrations(&[]).await;
+    assert!(matches!(result, Err(DatabaseError::MigrationError(_))));
+}
+
+#[actix_web::test]
+async fn test_failed_migration_is_not_recorded() {
+    let db = Database::new_test().await.expect("Failed to create test database");
+
+    let mut migrations = db.backend().migrations().to_vec();
+    migrations.push(Migration {
+        version: 9002,
+        name: "broken",
+        sql: "ALTER TABLE does_not_exist ADD COLUMN x INTEGER;",
+    });
+
+    assert!(db.apply_migrations(&migrations).await.is_err());
+
+    migrations.pop();
+    let report = db.apply_migrations(&migrations).await.unwrap();
+    assert!(report.applied.is_empty());
+}
+
+#[actix_web::test]
+async fn test_metadata_properties_are_unique_per_parent() {
+    let db = Database::new_test().await.expect("Failed to create test database");
+    db.create_user(&CreateUserRequest {
+        username: "uniquemeta".to_string(),
+        password: "hashed".to_string(),
+        profile: None,
+        metadata: vec![UserMetadata {
+            parent_property: None,
+            property: "title".to_string(),
+            value: Some("Dr".to_string()),
+            position: 0,
+        }],
+    })
+    .await
+    .unwrap();
+
+    // Top-level rows have a NULL parent, which the index still treats as equal
+    let mut migrations = db.backend().migrations().to_vec();
+    migrations.push(Migration {
+        version: 9003,
+        name: "duplicate_metadata",
+        sql: "INSERT INTO user_metadata (user_id, parent_property, property, value) \
+              SELECT user_id, parent_property, property, 'Prof' FROM user_metadata;",
+    });
+
+    assert!(db.apply_migrations(&migrations).await.is_err());
+}
+
+#[actix_web::test]
+async fn test_repeated_metadata_rows_are_numbered_not_deleted() {
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    let db = Database::from_sqlite_pool(pool.clone());
+    db.apply_migrations(&SQLITE_MIGRATIONS[..2]).await.unwrap();
+
+    // Rows as the service stored them before properties were unique
+    sqlx::raw_sql(
+        "INSERT INTO users (username, password) VALUES ('repeats', 'hashed');
+         INSERT INTO user_metadata (user_id, parent_property, property, value) VALUES
+             (1, NULL, 'hobby', 'chess'), (1, NULL, 'title', 'Dr'), (1, NULL, 'hobby', 'golf'),
+             (1, NULL, 'hobby', 'jazz');",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    db.run_migrations().await.unwrap();
+    let user = db.find_user_by_id(1).await.unwrap();
+    let rows: Vec<(&str, &str, i32)> = user
+        .metadata
+        .iter()
+        .map(|m| (m.property.as_str(), m.value.as_deref().unwrap(), m.position))
+        .collect();
+    assert_eq!(
+        rows,
+        [("hobby", "chess", 0), ("title", "Dr", 0), ("hobby", "golf", 1), ("hobby", "jazz", 2)]
+    );
+}
@@ -0,0 +1,72 @@
+use minijinja::context;
+
+use crate::pages::{LoginForm, Pages, SignupForm};
+
+#[test]
+fn test_pages_escape_submitted_values() {
+    let page = Pages::default()
+        .render("login.html", context! { username => "\"><script>alert(1)</script>", error => "<b>no</b>" })
+        .unwrap();
+    assert!(!page.contains("<script>alert"), "{}", page);
+    assert!(page.contains("&quot;&gt;&lt;script&gt;"), "{}", page);
+    assert!(page.contains("&lt;b&gt;no&lt;&#x2f;b&gt;"), "{}", page);
+}
+
+#[test]
+fn test_pages_mark_invalid_fields() {
+    let errors = std::collections::BTreeMap::from([("email", "Email address is already registered")]);
+    let page = Pages::default()
+        .render("signup.html", context! { form => SignupForm::default(), errors })
+        .unwrap();
+    assert!(page.contains(r#"aria-describedby="email-error""#), "{}", page);
+    assert!(page.contains(r#"<p id="email-error" class="field-error">Email address is already registered</p>"#));
+    assert_eq!(page.matches("aria-invalid").count(), 1);
+}
+
+#[test]
+fn test_signup_form_leaves_empty_fields_unset() {
+    let form = SignupForm {
+        username: "alice".to_string(),
+        password: "secret".to_string(),
+        first_name: "Alice".to_string(),
+        last_name: "  ".to_string(),
+        hobby: "chess".to_string(),
+        ..Default::default()
+    };
+    let payload = form.to_payload();
+    assert_eq!(payload.username, "alice");
+    assert_eq!(payload.first_name.as_deref(), Some("Alice"));
+    assert_eq!(payload.last_name, None);
+    assert_eq!(payload.email, None);
+    assert!(payload.title.is_empty());
+    assert_eq!(payload.hobby, vec!["chess".to_string()]);
+}
+
+#[test]
+fn test_signup_form_does_not_serialize_the_password() {
+    let form = SignupForm { username: "alice".to_string(), password: "secret".to_string(), ..Default::default() };
+    let json = serde_json::to_value(&form).unwrap();
+    assert_eq!(json["username"], "alice");
+    assert!(json.get("password").is_none());
+}
+
+#[test]
+fn test_form_debug_output_leaves_out_the_password() {
+    let login = LoginForm {
+        username: "alice".to_string(),
+        password: "hunter2-login".to_string(),
+        csrf_token: "token".to_string(),
+    };
+    let debug = format!("{:?}", login);
+    assert!(!debug.contains("hunter2-login"), "{}", debug);
+    assert!(debug.contains("alice") && debug.contains("[REDACTED]"), "{}", debug);
+
+    let signup = SignupForm {
+        username: "alice".to_string(),
+        password: "hunter2-signup".to_string(),
+        ..Default::default()
+    };
+    let debug = format!("{:?}", signup);
+    assert!(!debug.contains("hunter2-signup"), "{}", debug);
+    assert!(debug.contains("alice") && debug.contains("[REDACTED]"), "{}", debug);
+}
@@ -0,0 +1,247 @@
+use crate::db::{CreateUserRequest, Database, DatabaseError, UserMetadata, UserProfile};
+use crate::metadata::{self, MetadataNode};
+use crate::metadata_schema::{MetadataSchema, SchemaError};
+use crate::metadata_search::{Combine, MatchMode, MetadataCondition, MetadataQuery};
+use crate::repository::{InMemoryUserRepository, UserRepository};
+
+fn request(username: &str) -> CreateUserRequest {
+    CreateUserRequest {
+        username: username.to_string(),
+        password: "password123".to_string(),
+        profile: Some(UserProfile {
+            first_name: Some("Ada".to_string()),
+            last_name: None,
+            email: None,
+            ..Default::default()
+        }),
+        metadata: vec![UserMetadata {
+            parent_property: None,
+            property: "hobby".to_string(),
+            value: Some("Chess".to_string()),
+            position: 0,
+        }],
+    }
+}
+
+/// The same contract every store must satisfy
+async fn check_contract(repo: &dyn UserRepository) {
+    let id = repo.create_user(&request("contract")).await.unwrap();
+    assert!(id > 0);
+
+    let (auth_id, password) = repo.authenticate_user("contract").await.unwrap();
+    assert_eq!(auth_id, id);
+    assert_eq!(password, "password123");
+
+    let user = repo.find_user_by_id(id).await.unwrap();
+    assert_eq!(user.username, "contract");
+    assert_eq!(user.profile.unwrap().first_name.as_deref(), Some("Ada"));
+    assert_eq!(user.metadata.len(), 1);
+
+    assert!(matches!(
+        repo.create_user(&request("contract")).await,
+        Err(DatabaseError::DuplicateUsername)
+    ));
+    assert!(matches!(
+        repo.authenticate_user("nobody").await,
+        Err(DatabaseError::UserNotFound)
+    ));
+    assert!(matches!(
+        repo.find_user_by_id(id + 1000).await,
+        Err(DatabaseError::UserNotFound)
+    ));
+
+    let second = repo.create_user(&request("second")).await.unwrap();
+    assert_ne!(second, id);
+
+    // Batches keep the requested order, drop repeats and skip unknown IDs
+    let batch = repo.find_users_by_ids(&[second, id + 1000, id, second]).await.unwrap();
+    let names: Vec<_> = batch.iter().map(|u| u.username.as_str()).collect();
+    assert_eq!(names, ["second", "contract"]);
+    assert_eq!(batch[1].metadata.len(), 1);
+    assert!(repo.find_users_by_ids(&[]).await.unwrap().is_empty());
+
+    // Metadata subtrees
+    let schema = MetadataSchema::default();
+    let address = MetadataNode {
+        property: "address".to_string(),
+        value: None,
+        children: vec![MetadataNode {
+            property: "city".to_string(),
+            value: Some("Oslo".to_string()),
+            children: vec![],
+        }],
+    };
+    repo.replace_metadata_subtree(id, &address, &schema).await.unwrap();
+    assert_eq!(repo.find_metadata_subtree(id, "address").await.unwrap().nodes, std::slice::from_ref(&address));
+    let city = repo.find_metadata_subtree(id, "city").await.unwrap();
+    assert_eq!(city.path, "address.city");
+    assert_eq!(city.nodes[0].value.as_deref(), Some("Oslo"));
+    assert!(matches!(
+        repo.find_metadata_subtree(id, "missing").await,
+        Err(DatabaseError::MetadataNotFound(_))
+    ));
+    assert!(matches!(
+        repo.find_metadata_subtree(id + 1000, "address").await,
+        Err(DatabaseError::UserNotFound)
+    ));
+    assert_eq!(repo.find_user_by_id(id).await.unwrap().metadata.len(), 3);
+
+    repo.delete_metadata_subtree(id, "address", &schema).await.unwrap();
+    assert_eq!(repo.find_user_by_id(id).await.unwrap().metadata.len(), 1);
+    assert!(matches!(
+        repo.delete_metadata_subtree(id, "address", &schema).await,
+        Err(DatabaseError::MetadataNotFound(_))
+    ));
+
+    // Bulk replace swaps the whole tree; repeated siblings are rejected before anything changes
+    repo.replace_metadata(id, std::slice::from_ref(&address), &schema).await.unwrap();
+    let metadata = repo.find_user_by_id(id).await.unwrap().metadata;
+    assert_eq!(metadata::build_tree(&metadata).unwrap(), vec![address.clone()]);
+    assert!(matches!(
+        repo.replace_metadata(id, &[address.clone(), address.clone()], &schema).await,
+        Err(DatabaseError::InvalidMetadata(_))
+    ));
+    assert_eq!(repo.find_user_by_id(id).await.unwrap().metadata.len(), 2);
+    assert!(matches!(
+        repo.replace_metadata(id + 1000, &[], &schema).await,
+        Err(DatabaseError::UserNotFound)
+    ));
+
+    // The schema is checked against the stored rows inside the write; a violation changes nothing
+    let strict = MetadataSchema::from_json(
+        r#"{"allow_unknown": false, "properties": {"address": {"required": true}, "address.city": {}}}"#,
+    )
+    .unwrap();
+    assert!(matches!(
+        repo.delete_metadata_subtree(id, "address", &strict).await,
+        Err(DatabaseError::SchemaViolation(SchemaError::Missing(_)))
+    ));
+    let pet = MetadataNode { property: "pet".to_string(), value: Some("cat".to_string()), children: vec![] };
+    assert!(matches!(
+        repo.replace_metadata_subtree(id, &pet, &strict).await,
+        Err(DatabaseError::SchemaViolation(SchemaError::Unknown(_)))
+    ));
+    assert_eq!(repo.find_user_by_id(id).await.unwrap().metadata.len(), 2);
+    let rows = repo.replace_metadata_subtree(id, &address, &strict).await.unwrap();
+    assert_eq!(metadata::build_tree(&rows).unwrap(), vec![address.clone()]);
+
+    let mut orphan = request("orphan");
+    orphan.metadata[0].parent_property = Some("missing".to_string());
+    assert!(matches!(
+        repo.create_user(&orphan).await,
+        Err(DatabaseError::InvalidMetadata(_))
+    ));
+
+    let mut with_email = request("third");
+    with_email.profile.as_mut().unwrap().email = Some("ada@example.com".to_string());
+    repo.create_user(&with_email).await.unwrap();
+    with_email.username = "fourth".to_string();
+    assert!(matches!(
+        repo.create_user(&with_email).await,
+        Err(DatabaseError::DuplicateEmail)
+    ));
+}
+
+fn condition(property: &str, value: &str, mode: MatchMode, ignore_case: bool) -> MetadataCondition {
+    MetadataCondition {
+        property: property.to_string(),
+        value: value.to_string(),
+        mode,
+        ignore_case,
+    }
+}
+
+/// Usernames of the users `conditions` find
+async fn search(repo: &dyn UserRepository, combine: Combine, conditions: Vec<MetadataCondition>) -> Vec<String> {
+    let query = MetadataQuery {
+        conditions,
+        combine,
+        limit: 20,
+        offset: 0,
+    };
+    repo.find_users_by_metadata(&query)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|u| u.username)
+        .collect()
+}
+
+/// Metadata search must behave the same in every store
+async fn check_search_contract(repo: &dyn UserRepository) {
+    let row = |parent: Option<&str>, property: &str, value: Option<&str>| UserMetadata {
+        parent_property: parent.map(str::to_string),
+        property: property.to_string(),
+        value: value.map(str::to_string),
+        position: 0,
+    };
+    for (username, metadata) in [
+        ("searchdr", vec![row(None, "sport", Some("Chess")), row(None, "degree", Some("Dr"))]),
+        ("searchclub", vec![row(None, "sport", Some("chess club")), row(None, "place", None), row(Some("place"), "sport", Some("Go"))]),
+        ("searchwild", vec![row(None, "sport", Some("50%_off*[x]"))]),
+    ] {
+        let mut user = request(username);
+        user.metadata = metadata;
+        repo.create_user(&user).await.unwrap();
+    }
+
+    use MatchMode::{Exact, Prefix};
+    assert_eq!(search(repo, Combine::All, vec![condition("sport", "Chess", Exact, false)]).await, ["searchdr"]);
+    assert!(search(repo, Combine::All, vec![condition("sport", "chess", Exact, false)]).await.is_empty());
+    assert_eq!(search(repo, Combine::All, vec![condition("sport", "CHESS", Exact, true)]).await, ["searchdr"]);
+    assert_eq!(search(repo, Combine::All, vec![condition("sport", "chess", Prefix, false)]).await, ["searchclub"]);
+    assert_eq!(
+        search(repo, Combine::All, vec![condition("sport", "CH", Prefix, true)]).await,
+        ["searchdr", "searchclub"]
+    );
+
+    // Wildcards in the value are literal
+    assert_eq!(search(repo, Combine::All, vec![condition("sport", "50%_off*[", Prefix, false)]).await, ["searchwild"]);
+    assert!(search(repo, Combine::All, vec![condition("sport", "5_", Prefix, false)]).await.is_empty());
+    assert!(search(repo, Combine::All, vec![condition("sport", "%", Prefix, false)]).await.is_empty());
+
+    // Nested properties are addressed through their parent
+    assert_eq!(search(repo, Combine::All, vec![condition("place.sport", "Go", Exact, false)]).await, ["searchclub"]);
+    assert!(search(repo, Combine::All, vec![condition("sport", "Go", Exact, false)]).await.is_empty());
+
+    let dr_and_chess = vec![condition("sport", "chess", Prefix, true), condition("degree", "Dr", Exact, false)];
+    assert_eq!(search(repo, Combine::All, dr_and_chess.clone()).await, ["searchdr"]);
+    let wild_or_go = vec![condition("sport", "50", Prefix, false), condition("place.sport", "Go", Exact, false)];
+    assert_eq!(search(repo, Combine::Any, wild_or_go.clone()).await, ["searchclub", "searchwild"]);
+
+    let page = MetadataQuery {
+        conditions: wild_or_go,
+        combine: Combine::Any,
+        limit: 1,
+        offset: 1,
+    };
+    let users = repo.find_users_by_metadata(&page).await.unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].username, "searchwild");
+    assert_eq!(users[0].metadata.len(), 1);
+
+    let invalid = MetadataQuery {
+        conditions: vec![condition("a.b.c", "x", Exact, false)],
+        ..page
+    };
+    assert!(matches!(
+        repo.find_users_by_metadata(&invalid).await,
+        Err(DatabaseError::InvalidSearch(_))
+    ));
+}
+
+#[actix_web::test]
+async fn test_sql_database_satisfies_contract() {
+    let db = Database::new_test().await.expect("Failed to create test database");
+    check_contract(&db).await;
+    check_search_contract(&db).await;
+    assert!(UserRepository::pool_stats(&db).is_some());
+}
+
+#[actix_web::test]
+async fn test_in_memory_store_satisfies_contract() {
+    let repo = InMemoryUserRepository::new();
+    check_contract(&repo).await;
+    check_search_contract(&repo).await;
+    assert!(repo.pool_stats().is_none());
+}
@@ -0,0 +1,81 @@
+use crate::metadata::MetadataNode;
+use crate::sanitize::{check_name, clean_field, clean_metadata, clean_optional, UnsafeTextError};
+
+fn node(property: &str, value: Option<&str>, children: Vec<MetadataNode>) -> MetadataNode {
+    MetadataNode {
+        property: property.to_string(),
+        value: value.map(str::to_string),
+        children,
+    }
+}
+
+#[test]
+fn test_clean_field_normalizes_line_breaks_and_tabs() {
+    assert_eq!(clean_field("title", "Senior\tEngineer").unwrap(), "Senior Engineer");
+    assert_eq!(clean_field("title", "  Line one\r\nline two\u{2028}\n").unwrap(), "Line one  line two");
+    assert_eq!(clean_field("title", "Zoë 山田 مرحبا").unwrap(), "Zoë 山田 مرحبا");
+    assert_eq!(clean_optional("hobby", None).unwrap(), None);
+    assert_eq!(clean_optional("hobby", Some(" go ".to_string())).unwrap().as_deref(), Some("go"));
+}
+
+#[test]
+fn test_clean_field_rejects_controls_and_bidi_overrides() {
+    assert_eq!(
+        clean_field("first_name", "Ada\u{0}"),
+        Err(UnsafeTextError::Control { field: "first_name".to_string(), code: 0 })
+    );
+    assert_eq!(
+        clean_field("last_name", "\u{1B}[2J"),
+        Err(UnsafeTextError::Control { field: "last_name".to_string(), code: 0x1B })
+    );
+    assert!(matches!(clean_field("email", "a\u{7F}@b"), Err(UnsafeTextError::Control { .. })));
+    assert!(matches!(clean_field("email", "a\u{9B}@b"), Err(UnsafeTextError::Control { .. })));
+    for code in [0x202A, 0x202B, 0x202C, 0x202D, 0x202E, 0x2066, 0x2067, 0x2068, 0x2069] {
+        let value = format!("evil{}gpj.exe", char::from_u32(code).unwrap());
+        assert_eq!(
+            clean_field("hobby", &value),
+            Err(UnsafeTextError::Bidi { field: "hobby".to_string(), code })
+        );
+    }
+    // Directional marks are ordinary characters in right-to-left text
+    assert!(clean_field("hobby", "שלום\u{200F}").is_ok());
+
+    let message = clean_field("first_name", "A\u{202E}").unwrap_err().to_string();
+    assert_eq!(message, "first_name contains a bidirectional text control (U+202E)");
+}
+
+#[test]
+fn test_clean_metadata_walks_the_tree() {
+    let mut nodes = vec![
+        node("title", Some("Dr.\n"), vec![]),
+        node("address", None, vec![node("city", Some("\tLondon"), vec![])]),
+    ];
+    clean_metadata(&mut nodes, None).unwrap();
+    assert_eq!(nodes[0].value.as_deref(), Some("Dr."));
+    assert_eq!(nodes[1].value, None);
+    assert_eq!(nodes[1].children[0].value.as_deref(), Some("London"));
+
+    let mut nodes = vec![node("address", None, vec![node("city", Some("Lon\u{202E}don"), vec![])])];
+    assert_eq!(
+        clean_metadata(&mut nodes, None),
+        Err(UnsafeTextError::Bidi { field: "address.city".to_string(), code: 0x202E })
+    );
+}
+
+#[test]
+fn test_clean_metadata_rejects_unsafe_property_names() {
+    assert!(check_name("name", "favourite colour").is_ok());
+    assert!(check_name("name", "שלום\u{200F}").is_ok());
+
+    let mut nodes = vec![node("hob\u{202E}by", Some("chess"), vec![])];
+    assert_eq!(
+        clean_metadata(&mut nodes, None),
+        Err(UnsafeTextError::Bidi { field: "metadata property name".to_string(), code: 0x202E })
+    );
+
+    // Line breaks are rejected rather than normalized, so the name stays the one that was sent
+    let mut nodes = vec![node("address", None, vec![node("ci\nty", Some("London"), vec![])])];
+    let message = clean_metadata(&mut nodes, None).unwrap_err().to_string();
+    assert_eq!(message, "metadata property name under address contains a control character (U+000A)");
+    assert_eq!(nodes[0].children[0].property, "ci\nty");
+}
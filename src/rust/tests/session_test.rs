@@ -0,0 +1,159 @@
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::test::TestRequest;
+
+use crate::session::{SessionKey, CSRF_COOKIE, SESSION_COOKIE, SESSION_TTL_SECS};
+
+const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+fn request_with(value: &str) -> actix_web::HttpRequest {
+    TestRequest::default().cookie(Cookie::new(SESSION_COOKIE, value.to_string())).to_http_request()
+}
+
+#[test]
+fn test_signed_cookie_round_trips() {
+    let key = SessionKey::new(SECRET).unwrap();
+    let cookie = key.cookie(42);
+    assert_eq!(cookie.name(), SESSION_COOKIE);
+    assert_eq!(cookie.path(), Some("/"));
+    assert_eq!(cookie.http_only(), Some(true));
+    assert_eq!(cookie.secure(), Some(false));
+    assert_eq!(cookie.max_age().unwrap().whole_seconds(), SESSION_TTL_SECS);
+
+    assert_eq!(key.user_id(&request_with(cookie.value())), Some(42));
+    // Another key with the same secret accepts it, as after a restart
+    assert_eq!(SessionKey::new(SECRET).unwrap().user_id(&request_with(cookie.value())), Some(42));
+}
+
+#[test]
+fn test_tampered_cookies_are_rejected() {
+    let key = SessionKey::new(SECRET).unwrap();
+    let value = key.cookie(42).into_owned();
+    let (payload, signature) = value.value().rsplit_once('.').unwrap();
+    let (_, expires) = payload.split_once('.').unwrap();
+
+    assert_eq!(key.user_id(&request_with(&format!("43.{}.{}", expires, signature))), None);
+    assert_eq!(key.user_id(&request_with(payload)), None);
+    assert_eq!(key.user_id(&request_with(&format!("{}.zz", payload))), None);
+    assert_eq!(key.user_id(&request_with("")), None);
+    assert_eq!(key.user_id(&TestRequest::default().to_http_request()), None);
+    assert_eq!(SessionKey::default().user_id(&request_with(value.value())), None);
+}
+
+#[test]
+fn test_expired_cookies_are_rejected() {
+    let key = SessionKey::new(SECRET).unwrap();
+    let now = chrono::Utc::now().timestamp();
+    assert_eq!(key.user_id(&request_with(&key.sign(42, now + 60))), Some(42));
+    assert_eq!(key.user_id(&request_with(&key.sign(42, now - 1))), None);
+}
+
+#[test]
+fn test_short_secrets_are_rejected() {
+    let error = SessionKey::new("too short").err().unwrap();
+    assert!(error.contains("32 bytes"), "{}", error);
+}
+
+#[test]
+fn test_removal_cookie_expires_the_session() {
+    let cookie = SessionKey::default().removal_cookie();
+    assert_eq!(cookie.name(), SESSION_COOKIE);
+    assert_eq!(cookie.value(), "");
+    assert_eq!(cookie.max_age(), Some(actix_web::cookie::time::Duration::ZERO));
+}
+
+#[test]
+fn test_csrf_tokens_are_reused_while_valid() {
+    let key = SessionKey::new(SECRET).unwrap();
+    let (token, cookie) = key.csrf_token(&TestRequest::default().to_http_request());
+    let cookie = cookie.expect("a new token comes with its cookie");
+    assert_eq!(cookie.name(), CSRF_COOKIE);
+    assert_eq!(cookie.value(), token);
+    assert_eq!(cookie.same_site(), Some(SameSite::Strict));
+    assert_eq!(cookie.http_only(), Some(true));
+
+    let req = TestRequest::default().cookie(cookie.clone()).to_http_request();
+    assert_eq!(key.csrf_token(&req), (token.clone(), None));
+    assert!(key.verify_csrf(&req, &token));
+
+    // Another key's token is replaced
+    let (_, replaced) = SessionKey::default().csrf_token(&req);
+    assert!(replaced.is_some());
+}
+
+#[test]
+fn test_csrf_tokens_must_match_the_cookie() {
+    let key = SessionKey::new(SECRET).unwrap();
+    let (token, cookie) = key.csrf_token(&TestRequest::default().to_http_request());
+    let (other, _) = key.csrf_token(&TestRequest::default().to_http_request());
+    assert_ne!(token, other);
+
+    let req = TestRequest::default().cookie(cookie.unwrap()).to_http_request();
+    assert!(!key.verify_csrf(&req, &other));
+    assert!(!key.verify_csrf(&req, ""));
+    assert!(!key.verify_csrf(&TestRequest::default().to_http_request(), &token));
+
+    // Attacker-planted cookie and field that agree but weren't signed by the service
+    let planted = "0123456789abcdef.00";
+    let req = TestRequest::default().cookie(Cookie::new(CSRF_COOKIE, planted)).to_http_request();
+    assert!(!key.verify_csrf(&req, planted));
+}
+
+#[test]
+fn test_session_values_are_not_csrf_tokens() {
+    let key = SessionKey::new(SECRET).unwrap();
+    let session = key.sign(42, chrono::Utc::now().timestamp() + 60);
+    let req = TestRequest::default().cookie(Cookie::new(CSRF_COOKIE, session.clone())).to_http_request();
+    assert!(!key.verify_csrf(&req, &session));
+
+    let (token, _) = key.csrf_token(&TestRequest::default().to_http_request());
+    assert_eq!(key.user_id(&request_with(&token)), None);
+}
+
+#[test]
+fn test_csrf_tokens_are_bound_to_the_session() {
+    let key = SessionKey::new(SECRET).unwrap();
+    let (anonymous, cookie) = key.csrf_token(&TestRequest::default().to_http_request());
+    let anonymous_cookie = cookie.unwrap();
+
+    // Signing in rotates the token: the one issued before belongs to no session
+    let [session, csrf] = key.sign_in(42);
+    assert_eq!(session.name(), SESSION_COOKIE);
+    assert_eq!(csrf.name(), CSRF_COOKIE);
+    assert_eq!(csrf.same_site(), Some(SameSite::Strict));
+    assert_ne!(csrf.value(), anonymous);
+
+    let signed_in = |csrf: &Cookie<'static>, session: &Cookie<'static>| {
+        TestRequest::default().cookie(csrf.clone()).cookie(session.clone()).to_http_request()
+    };
+    let req = signed_in(&csrf, &session);
+    assert!(key.verify_csrf(&req, csrf.value()));
+    assert_eq!(key.csrf_token(&req), (csrf.value().to_string(), None));
+
+    let req = signed_in(&anonymous_cookie, &session);
+    assert!(!key.verify_csrf(&req, &anonymous));
+    assert!(key.csrf_token(&req).1.is_some(), "the stale token is replaced");
+
+    // Another session of the same user has tokens of its own
+    let other_session = Cookie::new(SESSION_COOKIE, key.sign(42, chrono::Utc::now().timestamp() + 60));
+    assert!(!key.verify_csrf(&signed_in(&csrf, &other_session), csrf.value()));
+    let [_, other_csrf] = key.sign_in(7);
+    assert!(!key.verify_csrf(&signed_in(&other_csrf, &session), other_csrf.value()));
+
+    // Signed out, the session's token no longer passes
+    assert!(!key.verify_csrf(&TestRequest::default().cookie(csrf.clone()).to_http_request(), csrf.value()));
+}
+
+#[test]
+fn test_same_site_policy() {
+    let key = SessionKey::new(SECRET).unwrap().with_cookie_policy(false, SameSite::Strict).unwrap();
+    assert_eq!(key.cookie(1).same_site(), Some(SameSite::Strict));
+    assert_eq!(key.removal_cookie().same_site(), Some(SameSite::Strict));
+
+    let error = SessionKey::new(SECRET).unwrap().with_cookie_policy(false, SameSite::None).err().unwrap();
+    assert!(error.contains("SESSION_COOKIE_SECURE"), "{}", error);
+
+    let key = SessionKey::new(SECRET).unwrap().with_cookie_policy(true, SameSite::None).unwrap();
+    let cookie = key.cookie(1);
+    assert_eq!(cookie.same_site(), Some(SameSite::None));
+    assert_eq!(cookie.secure(), Some(true));
+}
@@ -1,40 +1,127 @@
 use crate::db::{User, UserProfile, UserMetadata};
-use crate::user_info_formatter::{construct_name, format_user_greeting};
+use crate::greeting_templates::{GreetingTemplates, DEFAULT_TEMPLATE};
+use crate::i18n::DEFAULT_LOCALE;
+use crate::user_info_formatter::{
+    construct_name, escape, escape_markdown, escape_vcard, render_html, render_markdown, render_vcard,
+    EscapeContext, FormatError, NameOrder, NameStyle, UserInfoFormat,
+};
+use crate::UserInfoResponse;
+
+/// Greeting from the built-in default template
+fn format_user_greeting(user: &User) -> String {
+    GreetingTemplates::default()
+        .render(DEFAULT_TEMPLATE, user, &DEFAULT_LOCALE.parse().unwrap(), NameStyle::Formal)
+        .unwrap()
+}
+
+/// Profile with the given name parts
+fn names(first: Option<&str>, last: Option<&str>) -> UserProfile {
+    UserProfile {
+        first_name: first.map(str::to_string),
+        last_name: last.map(str::to_string),
+        ..Default::default()
+    }
+}
+
+fn full_profile() -> UserProfile {
+    UserProfile {
+        first_name: Some("Ada".to_string()),
+        middle_name: Some("Augusta".to_string()),
+        last_name: Some("Lovelace".to_string()),
+        nickname: Some("Addie".to_string()),
+        honorific: Some("Countess".to_string()),
+        suffix: Some("FRS".to_string()),
+        ..Default::default()
+    }
+}
 
 #[test]
 fn test_construct_name_full_name() {
-    let result = construct_name(
-        Some("John".to_string()),
-        Some("Doe".to_string()),
-        "jdoe".to_string(),
-    );
+    let result = construct_name(Some(&names(Some("John"), Some("Doe"))), "jdoe", NameStyle::Formal, NameOrder::GivenFirst);
     assert_eq!(result, "John Doe");
 }
 
 #[test]
 fn test_construct_name_first_only() {
-    let result = construct_name(
-        Some("John".to_string()),
-        None,
-        "jdoe".to_string(),
-    );
+    let result = construct_name(Some(&names(Some("John"), None)), "jdoe", NameStyle::Formal, NameOrder::GivenFirst);
     assert_eq!(result, "John");
 }
 
 #[test]
 fn test_construct_name_last_only() {
-    let result = construct_name(
-        None,
-        Some("Doe".to_string()),
-        "jdoe".to_string(),
-    );
+    let result = construct_name(Some(&names(None, Some("Doe"))), "jdoe", NameStyle::Formal, NameOrder::GivenFirst);
     assert_eq!(result, "Doe");
 }
 
 #[test]
 fn test_construct_name_username_fallback() {
-    let result = construct_name(None, None, "jdoe".to_string());
+    let result = construct_name(None, "jdoe", NameStyle::Formal, NameOrder::GivenFirst);
     assert_eq!(result, "jdoe");
+    let blank = names(Some("  "), None);
+    assert_eq!(construct_name(Some(&blank), "jdoe", NameStyle::Sorted, NameOrder::GivenFirst), "jdoe");
+}
+
+#[test]
+fn test_construct_name_family_first() {
+    let result = construct_name(Some(&names(Some("Taro"), Some("Yamada"))), "tyamada", NameStyle::Formal, NameOrder::FamilyFirst);
+    assert_eq!(result, "Yamada Taro");
+
+    let result = construct_name(Some(&names(Some("Taro"), None)), "tyamada", NameStyle::Formal, NameOrder::FamilyFirst);
+    assert_eq!(result, "Taro");
+}
+
+#[test]
+fn test_construct_name_styles() {
+    let profile = full_profile();
+    let name = |style, order| construct_name(Some(&profile), "ada", style, order);
+
+    assert_eq!(name(NameStyle::Formal, NameOrder::GivenFirst), "Countess Ada Augusta Lovelace, FRS");
+    assert_eq!(name(NameStyle::Formal, NameOrder::FamilyFirst), "Countess Lovelace Ada Augusta, FRS");
+    assert_eq!(name(NameStyle::Informal, NameOrder::GivenFirst), "Addie");
+    assert_eq!(name(NameStyle::Initials, NameOrder::GivenFirst), "A. A. Lovelace");
+    assert_eq!(name(NameStyle::Initials, NameOrder::FamilyFirst), "Lovelace A. A.");
+    assert_eq!(name(NameStyle::Sorted, NameOrder::GivenFirst), "Lovelace, Ada Augusta, FRS");
+
+    // Without a nickname the informal name is the given name
+    let plain = names(Some("Ada"), Some("Lovelace"));
+    assert_eq!(construct_name(Some(&plain), "ada", NameStyle::Informal, NameOrder::GivenFirst), "Ada");
+    assert_eq!(construct_name(Some(&plain), "ada", NameStyle::Sorted, NameOrder::GivenFirst), "Lovelace, Ada");
+    let given_only = names(Some("Ada"), None);
+    assert_eq!(construct_name(Some(&given_only), "ada", NameStyle::Initials, NameOrder::GivenFirst), "Ada");
+    assert_eq!(construct_name(Some(&given_only), "ada", NameStyle::Sorted, NameOrder::GivenFirst), "Ada");
+}
+
+#[test]
+fn test_construct_name_display_name_override() {
+    let profile = UserProfile { display_name: Some("Lady Lovelace".to_string()), ..full_profile() };
+    let name = |style| construct_name(Some(&profile), "ada", style, NameOrder::GivenFirst);
+    assert_eq!(name(NameStyle::Formal), "Lady Lovelace");
+    // The nickname still wins for informal names; structured styles use the name parts
+    assert_eq!(name(NameStyle::Informal), "Addie");
+    assert_eq!(name(NameStyle::Sorted), "Lovelace, Ada Augusta, FRS");
+
+    let override_only = UserProfile { display_name: Some("The Analyst".to_string()), ..Default::default() };
+    for style in NameStyle::ALL {
+        assert_eq!(construct_name(Some(&override_only), "ada", style, NameOrder::GivenFirst), "The Analyst");
+    }
+    let nickname_only = UserProfile { nickname: Some("Addie".to_string()), ..Default::default() };
+    assert_eq!(construct_name(Some(&nickname_only), "ada", NameStyle::Formal, NameOrder::GivenFirst), "Addie");
+}
+
+#[test]
+fn test_name_style_parsing() {
+    assert_eq!("Sorted".parse(), Ok(NameStyle::Sorted));
+    assert_eq!("informal".parse(), Ok(NameStyle::Informal));
+    assert!("casual".parse::<NameStyle>().is_err());
+    assert_eq!(NameStyle::default(), NameStyle::Formal);
+}
+
+#[test]
+fn test_name_order_for_locale() {
+    assert_eq!(NameOrder::for_locale(&"ja".parse().unwrap()), NameOrder::FamilyFirst);
+    assert_eq!(NameOrder::for_locale(&"hu-HU".parse().unwrap()), NameOrder::FamilyFirst);
+    assert_eq!(NameOrder::for_locale(&"de".parse().unwrap()), NameOrder::GivenFirst);
+    assert_eq!(NameOrder::for_locale(&"en-US".parse().unwrap()), NameOrder::GivenFirst);
 }
 
 #[test]
@@ -47,17 +134,20 @@ fn test_format_user_greeting_all_fields() {
             first_name: Some("John".to_string()),
             last_name: Some("Doe".to_string()),
             email: Some("john@email.com".to_string()),
+            ..Default::default()
         }),
         metadata: vec![
             UserMetadata {
                 parent_property: None,
                 property: "title".to_string(),
                 value: Some("Software Engineer".to_string()),
+                position: 0,
             },
             UserMetadata {
                 parent_property: None,
                 property: "hobby".to_string(),
                 value: Some("hiking".to_string()),
+                position: 0,
             }
         ],
         created_at: chrono::NaiveDate::from_ymd_opt(2020, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
@@ -95,12 +185,14 @@ fn test_format_user_greeting_no_hobby() {
             first_name: Some("John".to_string()),
             last_name: Some("Doe".to_string()),
             email: Some("john@email.com".to_string()),
+            ..Default::default()
         }),
         metadata: vec![
             UserMetadata {
                 parent_property: None,
                 property: "title".to_string(),
                 value: Some("Software Engineer".to_string()),
+                position: 0,
             }
         ],
         created_at: chrono::NaiveDate::from_ymd_opt(2020, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
@@ -120,12 +212,14 @@ fn test_format_user_greeting_no_title() {
             first_name: Some("John".to_string()),
             last_name: Some("Doe".to_string()),
             email: Some("john@email.com".to_string()),
+            ..Default::default()
         }),
         metadata: vec![
             UserMetadata {
                 parent_property: None,
                 property: "hobby".to_string(),
                 value: Some("hiking".to_string()),
+                position: 0,
             }
         ],
         created_at: chrono::NaiveDate::from_ymd_opt(2020, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
@@ -148,17 +242,20 @@ fn test_format_user_greeting_hobby_no_email() {
             first_name: Some("John".to_string()),
             last_name: Some("Doe".to_string()),
             email: None,
+            ..Default::default()
         }),
         metadata: vec![
             UserMetadata {
                 parent_property: None,
                 property: "title".to_string(),
                 value: Some("Software Engineer".to_string()),
+                position: 0,
             },
             UserMetadata {
                 parent_property: None,
                 property: "hobby".to_string(),
                 value: Some("hiking".to_string()),
+                position: 0,
             }
         ],
         created_at: chrono::NaiveDate::from_ymd_opt(2020, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
@@ -170,3 +267,218 @@ fn test_format_user_greeting_hobby_no_email() {
         "Hello Software Engineer John Doe, welcome! If we hear interesting news about hiking, we will let you know!"
     );
 }
+
+/// John Doe with `(property, value, position)` metadata rows, in the given row order
+fn user_with_values(rows: &[(&str, &str, i32)]) -> User {
+    User {
+        id: 1,
+        username: "jdoe".to_string(),
+        password: "pass".to_string(),
+        profile: Some(names(Some("John"), Some("Doe"))),
+        metadata: rows
+            .iter()
+            .map(|(property, value, position)| UserMetadata {
+                parent_property: None,
+                property: property.to_string(),
+                value: Some(value.to_string()),
+                position: *position,
+            })
+            .collect(),
+        created_at: chrono::NaiveDate::from_ymd_opt(2020, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
+        updated_at: chrono::NaiveDate::from_ymd_opt(2020, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
+    }
+}
+
+#[test]
+fn test_format_user_greeting_lists_every_hobby_in_position_order() {
+    // Rows are listed by position, not by the order they were loaded in
+    let user = user_with_values(&[("hobby", "jazz", 2), ("hobby", "chess", 0), ("hobby", "hiking", 1)]);
+    assert_eq!(
+        format_user_greeting(&user),
+        "Hello John Doe, welcome! If we hear interesting news about chess, hiking and jazz, we will let you know!"
+    );
+
+    let user = user_with_values(&[("hobby", "chess", 0), ("hobby", "jazz", 1)]);
+    assert_eq!(
+        format_user_greeting(&user),
+        "Hello John Doe, welcome! If we hear interesting news about chess and jazz, we will let you know!"
+    );
+}
+
+#[test]
+fn test_format_user_greeting_lists_every_title() {
+    let user = user_with_values(&[("title", "Engineer", 0), ("title", "Manager", 1)]);
+    assert_eq!(format_user_greeting(&user), "Hello Engineer and Manager John Doe, welcome!");
+}
+
+#[test]
+fn test_format_user_greeting_caps_long_lists() {
+    let hobbies = ["chess", "hiking", "jazz", "go", "tennis"];
+    let rows: Vec<(&str, &str, i32)> = hobbies.iter().enumerate().map(|(i, h)| ("hobby", *h, i as i32)).collect();
+    let user = user_with_values(&rows);
+    let en = DEFAULT_LOCALE.parse().unwrap();
+
+    // Three by default
+    assert_eq!(
+        format_user_greeting(&user),
+        "Hello John Doe, welcome! If we hear interesting news about chess, hiking, jazz and 2 more, we will let you know!"
+    );
+
+    let render = |limit: usize| {
+        GreetingTemplates::default()
+            .with_list_limit(limit)
+            .render(DEFAULT_TEMPLATE, &user, &en, NameStyle::Formal)
+            .unwrap()
+    };
+    assert_eq!(
+        render(4),
+        "Hello John Doe, welcome! If we hear interesting news about chess, hiking, jazz, go and one more, we will let you know!"
+    );
+    assert_eq!(
+        render(5),
+        "Hello John Doe, welcome! If we hear interesting news about chess, hiking, jazz, go and tennis, we will let you know!"
+    );
+    assert_eq!(
+        render(1),
+        "Hello John Doe, welcome! If we hear interesting news about chess and 4 more, we will let you know!"
+    );
+}
+
+#[test]
+fn test_user_info_response_lists_values_in_position_order() {
+    let user = user_with_values(&[("hobby", "jazz", 1), ("title", "Engineer", 0), ("hobby", "chess", 0)]);
+    let info = UserInfoResponse::from_user(user, &Default::default(), NameStyle::Formal, NameOrder::GivenFirst);
+    assert_eq!(info.hobbies, vec!["chess", "jazz"]);
+    assert_eq!(info.hobby.as_deref(), Some("chess"));
+    assert_eq!(info.titles, vec!["Engineer"]);
+    assert_eq!(info.title.as_deref(), Some("Engineer"));
+}
+
+fn user_info() -> UserInfoResponse {
+    UserInfoResponse {
+        id: 3,
+        username: "jdoe".to_string(),
+        name: "John Doe <Jr>".to_string(),
+        first_name: Some("John".to_string()),
+        middle_name: None,
+        last_name: Some("Doe <Jr>".to_string()),
+        nickname: None,
+        honorific: None,
+        suffix: None,
+        display_name: None,
+        email: Some("john@email.com".to_string()),
+        title: Some("R&D; \"Lead\"".to_string()),
+        hobby: None,
+        titles: vec!["R&D; \"Lead\"".to_string()],
+        hobbies: vec![],
+        metadata: vec![],
+    }
+}
+
+#[test]
+fn test_format_selection() {
+    let select = UserInfoFormat::select;
+    assert_eq!(select(None, None), Ok(UserInfoFormat::Text));
+    assert_eq!(select(None, Some("*/*")), Ok(UserInfoFormat::Text));
+    assert_eq!(select(None, Some("application/json")), Ok(UserInfoFormat::Json));
+    assert_eq!(
+        select(None, Some("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8")),
+        Ok(UserInfoFormat::Html)
+    );
+    assert_eq!(select(None, Some("text/*;q=0.5, text/vcard")), Ok(UserInfoFormat::VCard));
+    // The most specific range sets a format's quality
+    assert_eq!(select(None, Some("text/*, text/plain;q=0.1")), Ok(UserInfoFormat::Html));
+    assert_eq!(select(None, Some("text/markdown;q=0.4, application/json;q=0.3")), Ok(UserInfoFormat::Markdown));
+    assert_eq!(select(None, Some("image/png")), Err(FormatError::NotAcceptable));
+    assert_eq!(select(None, Some("*/*;q=0")), Err(FormatError::NotAcceptable));
+
+    // The query parameter overrides Accept
+    assert_eq!(select(Some("vcard"), Some("application/json")), Ok(UserInfoFormat::VCard));
+    assert_eq!(select(Some("MD"), None), Ok(UserInfoFormat::Markdown));
+    assert_eq!(select(Some("pdf"), None), Err(FormatError::Unknown("pdf".to_string())));
+}
+
+#[test]
+fn test_render_html_escapes_values() {
+    let html = render_html(&user_info(), "John Doe <Jr>", "Hi <script>alert('x')</script>", &"en".parse().unwrap());
+    assert_eq!(
+        html,
+        "<section class=\"user-info\" data-user-id=\"3\" lang=\"en\">\n  <h2>John Doe &lt;Jr&gt;</h2>\n  \
+         <p class=\"greeting\">Hi &lt;script&gt;alert('x')&lt;/script&gt;</p>\n  <dl>\n    \
+         <dt>Username</dt><dd>jdoe</dd>\n    <dt>Email</dt><dd>john@email.com</dd>\n    \
+         <dt>Title</dt><dd>R&amp;D; \"Lead\"</dd>\n  </dl>\n</section>\n"
+    );
+}
+
+#[test]
+fn test_escape_contexts() {
+    let value = "<a href=\"x\">Tom & 'Jerry'</a>`";
+    assert_eq!(escape(value, EscapeContext::Text), value);
+    assert_eq!(escape(value, EscapeContext::Html), "&lt;a href=\"x\"&gt;Tom &amp; 'Jerry'&lt;/a&gt;`");
+    assert_eq!(
+        escape(value, EscapeContext::Attribute),
+        "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;&#96;"
+    );
+    assert_eq!(
+        escape("</script>\"\\\n\u{2028}", EscapeContext::Json),
+        "\\u003c/script\\u003e\\\"\\\\\\n\\u2028"
+    );
+    let json = format!("\"{}\"", escape(value, EscapeContext::Json));
+    assert_eq!(serde_json::from_str::<String>(&json).unwrap(), value);
+
+    // Bidi controls are dropped and other control characters replaced in every context
+    for context in [EscapeContext::Text, EscapeContext::Html, EscapeContext::Attribute, EscapeContext::Json] {
+        assert_eq!(escape("a\u{202E}b\u{2067}c\u{0}d\u{9B}e", context), "abc\u{FFFD}d\u{FFFD}e");
+    }
+    assert_eq!(escape("one\ntwo\tthree", EscapeContext::Text), "one\ntwo\tthree");
+
+    assert_eq!("attr".parse(), Ok(EscapeContext::Attribute));
+    assert!("css".parse::<EscapeContext>().is_err());
+}
+
+#[test]
+fn test_render_markdown_escapes_values() {
+    let markdown = render_markdown(&user_info(), "John Doe <Jr>", "Hello *John*,\nwelcome!");
+    assert_eq!(
+        markdown,
+        "## John Doe \\<Jr\\>\n\nHello \\*John\\*, welcome!\n\n- **Username:** jdoe\n\
+         - **Email:** john@email.com\n- **Title:** R&D; \"Lead\"\n"
+    );
+    assert_eq!(escape_markdown("[x](y) `z` _w_"), "\\[x\\](y) \\`z\\` \\_w\\_");
+}
+
+#[test]
+fn test_render_vcard() {
+    let vcard = render_vcard(&user_info(), "John Doe <Jr>");
+    assert_eq!(
+        vcard,
+        "BEGIN:VCARD\r\nVERSION:4.0\r\nFN:John Doe <Jr>\r\nN:Doe <Jr>;John;;;\r\n\
+         EMAIL:john@email.com\r\nTITLE:R&D\\; \"Lead\"\r\nEND:VCARD\r\n"
+    );
+    assert_eq!(escape_vcard("a,b;c\\d\r\ne"), "a\\,b\\;c\\\\d\\ne");
+
+    // Every name part goes into its N component
+    let mut info = user_info();
+    info.middle_name = Some("Q.".to_string());
+    info.honorific = Some("Dr.".to_string());
+    info.suffix = Some("Jr., PhD".to_string());
+    info.nickname = Some("Johnny".to_string());
+    let vcard = render_vcard(&info, "Dr. John Q. Doe");
+    assert!(vcard.contains("\r\nN:Doe <Jr>;John;Q.;Dr.;Jr.\\, PhD\r\nNICKNAME:Johnny\r\n"), "{}", vcard);
+
+    // Each title and hobby gets its own property
+    let mut info = user_info();
+    info.hobbies = vec!["chess".to_string(), "jazz".to_string()];
+    let vcard = render_vcard(&info, "John Doe");
+    assert!(vcard.contains("\r\nHOBBY:chess\r\nHOBBY:jazz\r\n"), "{}", vcard);
+
+    // Long lines fold at 75 octets without splitting a character
+    let mut info = user_info();
+    info.hobbies = vec!["é".repeat(100)];
+    let vcard = render_vcard(&info, "John Doe");
+    let hobby: Vec<&str> = vcard.split("\r\n").skip_while(|l| !l.starts_with("HOBBY:")).take(3).collect();
+    assert!(hobby.iter().all(|line| line.len() <= 75), "{:?}", hobby);
+    assert!(hobby[1].starts_with(' ') && hobby[2].starts_with(' '));
+    let unfolded: String = hobby.iter().enumerate().map(|(i, l)| if i == 0 { *l } else { &l[1..] }).collect();
+    assert_eq!(unfolded, format!("HOBBY:{}", "é".repeat(100)));
+}
@@ -0,0 +1,100 @@
+use actix_web::body::MessageBody;
+use actix_web::http::header;
+use actix_web::test::TestRequest;
+use std::path::PathBuf;
+
+use crate::web_ui::{WebUi, EMBEDDED, INDEX};
+
+/// Fresh directory holding `files`
+fn ui_dir(test: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("web_ui_{}_{}", std::process::id(), test));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, contents) in files {
+        std::fs::write(dir.join(name), contents).unwrap();
+    }
+    dir
+}
+
+fn body(response: actix_web::HttpResponse) -> String {
+    String::from_utf8(response.into_body().try_into_bytes().unwrap().to_vec()).unwrap()
+}
+
+#[test]
+fn test_embedded_pages_are_served() {
+    let ui = WebUi::default();
+    for (name, contents) in EMBEDDED {
+        let response = ui.respond(&TestRequest::default().to_http_request(), name);
+        assert_eq!(response.status().as_u16(), 200, "{}", name);
+        assert_eq!(body(response), *contents);
+    }
+
+    let response = ui.respond(&TestRequest::default().to_http_request(), INDEX);
+    assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), "text/html; charset=utf-8");
+    assert_eq!(response.headers().get(header::CACHE_CONTROL).unwrap(), "no-cache");
+    assert_eq!(response.headers().get(header::X_CONTENT_TYPE_OPTIONS).unwrap(), "nosniff");
+}
+
+#[test]
+fn test_embedded_pages_use_relative_api_urls() {
+    for (name, contents) in EMBEDDED {
+        assert!(!contents.contains("localhost:8080"), "{} hard-codes the API origin", name);
+    }
+}
+
+#[test]
+fn test_matching_etag_is_not_modified() {
+    let ui = WebUi::default();
+    let response = ui.respond(&TestRequest::default().to_http_request(), "style.css");
+    let etag = response.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+    assert!(etag.starts_with('"') && etag.ends_with('"') && etag.len() == 34, "{}", etag);
+
+    for if_none_match in [etag.clone(), format!("W/{}", etag), format!("\"other\", {}", etag), "*".to_string()] {
+        let req = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, if_none_match.as_str()))
+            .to_http_request();
+        let response = ui.respond(&req, "style.css");
+        assert_eq!(response.status().as_u16(), 304, "{}", if_none_match);
+        assert_eq!(response.headers().get(header::ETAG).unwrap().to_str().unwrap(), etag);
+        assert_eq!(body(response), "");
+    }
+
+    let req = TestRequest::default().insert_header((header::IF_NONE_MATCH, "\"stale\"")).to_http_request();
+    assert_eq!(ui.respond(&req, "style.css").status().as_u16(), 200);
+}
+
+#[test]
+fn test_unknown_and_unsafe_names_are_not_found() {
+    let ui = WebUi::default();
+    for name in ["missing.html", "..", "../Cargo.toml", ".env", "main.rs", "index"] {
+        let response = ui.respond(&TestRequest::default().to_http_request(), name);
+        assert_eq!(response.status().as_u16(), 404, "{}", name);
+    }
+}
+
+#[test]
+fn test_directory_is_read_on_every_request() {
+    let dir = ui_dir("reload", &[("index.html", "<p>one</p>"), ("app.js", "let x = 1;"), (".secret.html", "no")]);
+    let ui = WebUi::directory(&dir).unwrap();
+
+    let first = ui.respond(&TestRequest::default().to_http_request(), "index.html");
+    let first_etag = first.headers().get(header::ETAG).unwrap().clone();
+    assert_eq!(body(first), "<p>one</p>");
+
+    std::fs::write(dir.join("index.html"), "<p>two</p>").unwrap();
+    let second = ui.respond(&TestRequest::default().to_http_request(), "index.html");
+    assert_ne!(second.headers().get(header::ETAG).unwrap(), &first_etag);
+    assert_eq!(body(second), "<p>two</p>");
+
+    let script = ui.respond(&TestRequest::default().to_http_request(), "app.js");
+    assert_eq!(script.headers().get(header::CONTENT_TYPE).unwrap(), "text/javascript; charset=utf-8");
+    assert_eq!(ui.respond(&TestRequest::default().to_http_request(), ".secret.html").status().as_u16(), 404);
+    assert_eq!(ui.respond(&TestRequest::default().to_http_request(), "style.css").status().as_u16(), 404);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_missing_directory_is_rejected() {
+    assert!(WebUi::directory(std::env::temp_dir().join("web_ui_missing_dir")).is_err());
+}
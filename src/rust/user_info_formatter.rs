@@ -1,56 +1,439 @@
-use crate::db::User;
+use unic_langid::LanguageIdentifier;
 
-/// Constructs a display name from user fields with prioritized fallback logic
-pub fn construct_name(
-    first_name: Option<String>,
-    last_name: Option<String>,
-    username: String,
-) -> String {
-    match (first_name, last_name) {
-        (Some(first), Some(last)) => format!("{} {}", first, last),
-        (Some(first), None) => first,
-        (None, Some(last)) => last,
-        (None, None) => username,
+use crate::db::UserProfile;
+use crate::sanitize::{is_bidi_control, is_line_break_or_tab};
+use crate::UserInfoResponse;
+
+/// Order of given and family name in a full display name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameOrder {
+    /// "Ada Lovelace"
+    GivenFirst,
+    /// "Lovelace Ada", as in Japanese, Chinese, Korean, Hungarian and Vietnamese
+    FamilyFirst,
+}
+
+impl NameOrder {
+    /// Conventional name order for `locale`'s language
+    pub fn for_locale(locale: &LanguageIdentifier) -> Self {
+        match locale.language.as_str() {
+            "ja" | "zh" | "ko" | "hu" | "vi" => NameOrder::FamilyFirst,
+            _ => NameOrder::GivenFirst,
+        }
     }
 }
 
-/// Formats a user greeting message in the format:
-/// "Hello [Title] [Name], welcome! If we hear interesting news about [Hobby], we will let you know at [Email]!"
-pub fn format_user_greeting(user: &User) -> String {
-    let (first_name, last_name, email) = user.profile.as_ref().map(|p| (p.first_name.clone(), p.last_name.clone(), p.email.clone())).unwrap_or((None, None, None));
+/// How a display name is put together from the profile's name parts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NameStyle {
+    /// "Dr. Ada Augusta Lovelace, PhD", or the profile's `display_name` when set
+    #[default]
+    Formal,
+    /// Nickname, then `display_name`, then the given name: "Ada"
+    Informal,
+    /// Given and middle names as initials: "A. A. Lovelace"
+    Initials,
+    /// Family name first for sorted listings: "Lovelace, Ada Augusta, PhD"
+    Sorted,
+}
 
-    let mut title = None;
-    let mut hobby = None;
+impl NameStyle {
+    pub const ALL: [NameStyle; 4] = [NameStyle::Formal, NameStyle::Informal, NameStyle::Initials, NameStyle::Sorted];
 
-    for m in &user.metadata {
-        if m.property == "title" {
-            title = m.value.clone();
-        } else if m.property == "hobby" {
-            hobby = m.value.clone();
+    /// Read `DISPLAY_NAME_STYLE` (`formal`, `informal`, `initials` or `sorted`, default: `formal`)
+    pub fn from_env() -> Self {
+        std::env::var("DISPLAY_NAME_STYLE")
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NameStyle::Formal => "formal",
+            NameStyle::Informal => "informal",
+            NameStyle::Initials => "initials",
+            NameStyle::Sorted => "sorted",
         }
     }
+}
+
+impl std::str::FromStr for NameStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NameStyle::ALL
+            .into_iter()
+            .find(|style| style.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown name style '{}'; expected formal, informal, initials or sorted", s))
+    }
+}
 
-    // Construct the user's display name
-    let name = construct_name(first_name, last_name, user.username.clone());
+/// Constructs a display name from the profile in `style`, joining given and family names
+/// in `order`. Falls back to the display name override, the nickname and finally the
+/// username when the profile has no given or family name.
+pub fn construct_name(profile: Option<&UserProfile>, username: &str, style: NameStyle, order: NameOrder) -> String {
+    let given = profile.and_then(|p| name_part(&p.first_name));
+    let middle = profile.and_then(|p| name_part(&p.middle_name));
+    let family = profile.and_then(|p| name_part(&p.last_name));
+    let nickname = profile.and_then(|p| name_part(&p.nickname));
+    let honorific = profile.and_then(|p| name_part(&p.honorific));
+    let suffix = profile.and_then(|p| name_part(&p.suffix));
+    let display_name = profile.and_then(|p| name_part(&p.display_name));
 
-    // Build greeting section: "Hello [Title] Name, welcome!"
-    let greeting = match title {
-        Some(t) => format!("Hello {} {}, welcome!", t, name),
-        None => format!("Hello {}, welcome!", name),
+    let fallback = || display_name.or(nickname).unwrap_or(username).to_string();
+    let join = |parts: &[Option<&str>]| parts.iter().flatten().copied().collect::<Vec<_>>().join(" ");
+    let with_suffix = |name: String| match suffix {
+        Some(suffix) => format!("{}, {}", name, suffix),
+        None => name,
     };
+    if given.is_none() && family.is_none() {
+        return match style {
+            NameStyle::Informal => nickname.or(display_name).unwrap_or(username).to_string(),
+            _ => fallback(),
+        };
+    }
 
-    // Build notification section if hobby is present
-    let notification = match hobby {
-        Some(h) => {
-            let mut msg = format!(" If we hear interesting news about {}, we will let you know", h);
-            if let Some(e) = email {
-                msg.push_str(&format!(" at {}", e));
+    match style {
+        NameStyle::Formal => match display_name {
+            Some(display_name) => display_name.to_string(),
+            None => {
+                let name = match order {
+                    NameOrder::GivenFirst => join(&[honorific, given, middle, family]),
+                    NameOrder::FamilyFirst => join(&[honorific, family, given, middle]),
+                };
+                with_suffix(name)
+            }
+        },
+        NameStyle::Informal => nickname.or(display_name).or(given).or(family).unwrap_or(username).to_string(),
+        NameStyle::Initials => {
+            let initial = |name: Option<&str>| name.and_then(|n| n.chars().next()).map(|c| format!("{}.", c));
+            let (given_initial, middle_initial) = (initial(given), initial(middle));
+            let initials = [given_initial.as_deref(), middle_initial.as_deref()];
+            match (order, family) {
+                // Initials alone would not identify anyone
+                (_, None) => join(&[given, middle]),
+                (NameOrder::GivenFirst, Some(_)) => join(&[initials[0], initials[1], family]),
+                (NameOrder::FamilyFirst, Some(_)) => join(&[family, initials[0], initials[1]]),
             }
-            msg.push('!');
-            msg
         }
-        None => String::new(), // No notification if no hobby
-    };
+        NameStyle::Sorted => match family {
+            Some(family) if given.is_some() || middle.is_some() => with_suffix(format!("{}, {}", family, join(&[given, middle]))),
+            _ => with_suffix(join(&[given, family])),
+        },
+    }
+}
+
+/// A set, non-blank name part
+fn name_part(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+/// The profile's name in every style, keyed by style name
+pub fn all_names(profile: Option<&UserProfile>, username: &str, order: NameOrder) -> Vec<(&'static str, String)> {
+    NameStyle::ALL
+        .into_iter()
+        .map(|style| (style.as_str(), construct_name(profile, username, style, order)))
+        .collect()
+}
+
+/// Representation of `GET /api/users/{user_id}`, chosen by `?format=` or `Accept`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserInfoFormat {
+    /// The rendered greeting
+    Text,
+    /// `UserInfoResponse`
+    Json,
+    /// HTML fragment with the greeting and profile fields
+    Html,
+    Markdown,
+    /// vCard 4.0 (RFC 6350)
+    VCard,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum FormatError {
+    #[error("Unknown format '{0}'; expected text, json, html, markdown or vcard")]
+    Unknown(String),
+    #[error("None of the accepted media types is available; use text/plain, application/json, text/html, text/markdown or text/vcard")]
+    NotAcceptable,
+}
+
+impl UserInfoFormat {
+    /// Formats in the order the server prefers them when `Accept` ranks several equally
+    const ALL: [UserInfoFormat; 5] = [
+        UserInfoFormat::Text,
+        UserInfoFormat::Json,
+        UserInfoFormat::Html,
+        UserInfoFormat::Markdown,
+        UserInfoFormat::VCard,
+    ];
+
+    /// The `format` query parameter wins over `Accept`; with neither the greeting is plain text
+    pub fn select(format: Option<&str>, accept: Option<&str>) -> Result<Self, FormatError> {
+        match format {
+            Some(format) => Self::from_name(format),
+            None => Self::negotiate(accept.unwrap_or("*/*")),
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self, FormatError> {
+        match name.to_ascii_lowercase().as_str() {
+            "text" | "txt" => Ok(UserInfoFormat::Text),
+            "json" => Ok(UserInfoFormat::Json),
+            "html" => Ok(UserInfoFormat::Html),
+            "markdown" | "md" => Ok(UserInfoFormat::Markdown),
+            "vcard" | "vcf" => Ok(UserInfoFormat::VCard),
+            _ => Err(FormatError::Unknown(name.to_string())),
+        }
+    }
+
+    /// Highest-quality format for an `Accept` header. Each format takes the quality of the
+    /// most specific media range matching it (`type/subtype` over `type/*` over `*/*`).
+    fn negotiate(accept: &str) -> Result<Self, FormatError> {
+        let ranges: Vec<(&str, &str, f32)> = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let (kind, subtype) = parts.next()?.trim().split_once('/')?;
+                let quality = parts
+                    .filter_map(|p| p.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                Some((kind.trim(), subtype.trim(), quality))
+            })
+            .collect();
+
+        let quality = |format: UserInfoFormat| {
+            let (kind, subtype) = format.media_type().split_once('/').expect("media type has a subtype");
+            ranges
+                .iter()
+                .filter_map(|(k, s, q)| {
+                    let specificity = match (*k, *s) {
+                        (k, s) if k.eq_ignore_ascii_case(kind) && s.eq_ignore_ascii_case(subtype) => 2,
+                        (k, "*") if k.eq_ignore_ascii_case(kind) => 1,
+                        ("*", "*") => 0,
+                        _ => return None,
+                    };
+                    Some((specificity, *q))
+                })
+                .max_by_key(|(specificity, _)| *specificity)
+                .map(|(_, q)| q)
+                .unwrap_or(0.0)
+        };
+
+        let mut best: Option<(UserInfoFormat, f32)> = None;
+        for format in Self::ALL {
+            let q = quality(format);
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((format, q));
+            }
+        }
+        best.map(|(format, _)| format).ok_or(FormatError::NotAcceptable)
+    }
+
+    fn media_type(self) -> &'static str {
+        match self {
+            UserInfoFormat::Text => "text/plain",
+            UserInfoFormat::Json => "application/json",
+            UserInfoFormat::Html => "text/html",
+            UserInfoFormat::Markdown => "text/markdown",
+            UserInfoFormat::VCard => "text/vcard",
+        }
+    }
+
+    /// `Content-Type` of the response
+    pub fn content_type(self) -> String {
+        match self {
+            UserInfoFormat::Json => self.media_type().to_string(),
+            _ => format!("{}; charset=utf-8", self.media_type()),
+        }
+    }
+
+    /// Whether the representation includes the localized greeting
+    pub fn has_greeting(self) -> bool {
+        matches!(self, UserInfoFormat::Text | UserInfoFormat::Html | UserInfoFormat::Markdown)
+    }
+}
+
+/// HTML fragment for the web UI. Every value is escaped for where it appears.
+pub fn render_html(info: &UserInfoResponse, name: &str, greeting: &str, locale: &LanguageIdentifier) -> String {
+    let mut html = format!(
+        "<section class=\"user-info\" data-user-id=\"{}\" lang=\"{}\">\n  <h2>{}</h2>\n  <p class=\"greeting\">{}</p>\n  <dl>\n",
+        info.id,
+        escape(&locale.to_string(), EscapeContext::Attribute),
+        escape(name, EscapeContext::Html),
+        escape(greeting, EscapeContext::Html),
+    );
+    for (label, value) in profile_fields(info) {
+        html.push_str(&format!("    <dt>{}</dt><dd>{}</dd>\n", label, escape(value, EscapeContext::Html)));
+    }
+    html.push_str("  </dl>\n</section>\n");
+    html
+}
+
+/// Markdown for the chat bot. Characters Markdown would interpret are backslash-escaped.
+pub fn render_markdown(info: &UserInfoResponse, name: &str, greeting: &str) -> String {
+    let mut markdown = format!("## {}\n\n{}\n\n", escape_markdown(name), escape_markdown(greeting));
+    for (label, value) in profile_fields(info) {
+        markdown.push_str(&format!("- **{}:** {}\n", label, escape_markdown(value)));
+    }
+    markdown
+}
+
+/// vCard 4.0 for address books, with CRLF line endings and lines folded at 75 octets
+pub fn render_vcard(info: &UserInfoResponse, name: &str) -> String {
+    let component = |value: &Option<String>| value.as_deref().map(escape_vcard).unwrap_or_default();
+    let mut lines = vec![
+        "BEGIN:VCARD".to_string(),
+        "VERSION:4.0".to_string(),
+        format!("FN:{}", escape_vcard(name)),
+        format!(
+            "N:{};{};{};{};{}",
+            component(&info.last_name),
+            component(&info.first_name),
+            component(&info.middle_name),
+            component(&info.honorific),
+            component(&info.suffix),
+        ),
+    ];
+    if let Some(nickname) = &info.nickname {
+        lines.push(format!("NICKNAME:{}", escape_vcard(nickname)));
+    }
+    if let Some(email) = &info.email {
+        lines.push(format!("EMAIL:{}", escape_vcard(email)));
+    }
+    for title in &info.titles {
+        lines.push(format!("TITLE:{}", escape_vcard(title)));
+    }
+    // HOBBY is defined by RFC 6715
+    for hobby in &info.hobbies {
+        lines.push(format!("HOBBY:{}", escape_vcard(hobby)));
+    }
+    lines.push("END:VCARD".to_string());
+
+    lines.iter().map(|line| fold_vcard_line(line) + "\r\n").collect()
+}
+
+/// Labelled profile fields that are set, in display order; each title and hobby is its
+/// own entry
+pub fn profile_fields(info: &UserInfoResponse) -> Vec<(&'static str, &str)> {
+    [("Username", Some(&info.username)), ("Email", info.email.as_ref())]
+        .into_iter()
+        .filter_map(|(label, value)| value.map(|v| (label, v.as_str())))
+        .chain(info.titles.iter().map(|title| ("Title", title.as_str())))
+        .chain(info.hobbies.iter().map(|hobby| ("Hobby", hobby.as_str())))
+        .collect()
+}
+
+/// Where a user-supplied value is being written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscapeContext {
+    /// Plain text: only control characters are neutralized
+    Text,
+    /// HTML element content
+    Html,
+    /// Quoted HTML attribute value
+    Attribute,
+    /// Inside a JSON string literal, safe to embed in an HTML `<script>` block as well
+    Json,
+}
+
+impl std::str::FromStr for EscapeContext {
+    type Err = String;
 
-    format!("{}{}", greeting, notification)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(EscapeContext::Text),
+            "html" => Ok(EscapeContext::Html),
+            "attr" | "attribute" => Ok(EscapeContext::Attribute),
+            "json" => Ok(EscapeContext::Json),
+            _ => Err(format!("unknown escape context '{}'; expected text, html, attr or json", s)),
+        }
+    }
+}
+
+/// Escape `value` for `context`. In every context bidi controls are dropped and control
+/// characters other than tab and line breaks are replaced with U+FFFD, so values stored
+/// before input was cleaned cannot reorder or hide surrounding text.
+pub fn escape(value: &str, context: EscapeContext) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if is_bidi_control(c) {
+            continue;
+        }
+        let c = if c.is_control() && !is_line_break_or_tab(c) { '\u{FFFD}' } else { c };
+        match (context, c) {
+            (EscapeContext::Text, _) => escaped.push(c),
+            (EscapeContext::Html | EscapeContext::Attribute, '&') => escaped.push_str("&amp;"),
+            (EscapeContext::Html | EscapeContext::Attribute, '<') => escaped.push_str("&lt;"),
+            (EscapeContext::Html | EscapeContext::Attribute, '>') => escaped.push_str("&gt;"),
+            (EscapeContext::Attribute, '"') => escaped.push_str("&quot;"),
+            (EscapeContext::Attribute, '\'') => escaped.push_str("&#39;"),
+            (EscapeContext::Attribute, '`') => escaped.push_str("&#96;"),
+            (EscapeContext::Json, '"') => escaped.push_str("\\\""),
+            (EscapeContext::Json, '\\') => escaped.push_str("\\\\"),
+            (EscapeContext::Json, '\n') => escaped.push_str("\\n"),
+            (EscapeContext::Json, '\r') => escaped.push_str("\\r"),
+            (EscapeContext::Json, '\t') => escaped.push_str("\\t"),
+            (EscapeContext::Json, '<' | '>' | '&' | '\'' | '\u{2028}' | '\u{2029}')
+            | (EscapeContext::Json, '\u{0}'..='\u{1F}') => {
+                escaped.push_str(&format!("\\u{:04x}", c as u32));
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Backslash-escape Markdown syntax characters and flatten line breaks to spaces
+pub fn escape_markdown(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in escape(value, EscapeContext::Text).chars() {
+        match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' | '~' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\r' | '\n' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape a vCard text value (RFC 6350 section 3.4)
+pub fn escape_vcard(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let value = escape(value, EscapeContext::Text);
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ',' => escaped.push_str("\\,"),
+            ';' => escaped.push_str("\\;"),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\r' | '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Split a content line into 75-octet chunks joined by CRLF and a space, never inside a
+/// UTF-8 character
+fn fold_vcard_line(line: &str) -> String {
+    const MAX_OCTETS: usize = 75;
+    let mut folded = String::with_capacity(line.len());
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > MAX_OCTETS {
+            folded.push_str("\r\n ");
+            // The leading space counts towards the continuation line's length
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded
 }
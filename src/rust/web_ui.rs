@@ -0,0 +1,126 @@
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+/// Page served for `/`
+pub const INDEX: &str = "index.html";
+
+/// The pages in `src/web/`, compiled into the binary
+pub const EMBEDDED: &[(&str, &str)] = &[
+    ("index.html", include_str!("../web/index.html")),
+    ("create-user.html", include_str!("../web/create-user.html")),
+    ("user-info.html", include_str!("../web/user-info.html")),
+    ("style.css", include_str!("../web/style.css")),
+];
+
+/// Where the web UI's files come from
+pub enum WebUi {
+    /// The files in `EMBEDDED`, with their ETags computed once
+    Embedded(Vec<(&'static str, &'static str, String)>),
+    /// Files read from a directory on every request, so edits show up without a restart
+    Directory(PathBuf),
+}
+
+impl Default for WebUi {
+    /// The embedded pages
+    fn default() -> Self {
+        WebUi::Embedded(EMBEDDED.iter().map(|(name, body)| (*name, *body, etag(body.as_bytes()))).collect())
+    }
+}
+
+impl WebUi {
+    /// Serve files from `WEB_UI_DIR` when it is set, otherwise the embedded pages
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("WEB_UI_DIR") {
+            Ok(dir) if !dir.is_empty() => Self::directory(dir),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Serve files from `dir`, which must exist
+    pub fn directory(dir: impl Into<PathBuf>) -> Result<Self, String> {
+        let dir = dir.into();
+        if !dir.is_dir() {
+            return Err(format!("{} is not a directory", dir.display()));
+        }
+        Ok(WebUi::Directory(dir))
+    }
+
+    /// Response for the file `name`: 200 with the body, or 304 when `If-None-Match` already
+    /// has its ETag. Names outside the UI (other directories, hidden files, unknown types)
+    /// are 404.
+    pub fn respond(&self, req: &HttpRequest, name: &str) -> HttpResponse {
+        let Some(content_type) = content_type(name) else {
+            return HttpResponse::NotFound().finish();
+        };
+        let (body, etag) = match self {
+            WebUi::Embedded(files) => match files.iter().find(|(file, _, _)| *file == name) {
+                Some((_, body, etag)) => (body.as_bytes().to_vec(), etag.clone()),
+                None => return HttpResponse::NotFound().finish(),
+            },
+            WebUi::Directory(dir) => match std::fs::read(dir.join(name)) {
+                Ok(body) => {
+                    let etag = etag(&body);
+                    (body, etag)
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return HttpResponse::NotFound().finish(),
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            },
+        };
+
+        let not_modified = matches_etag(req, &etag);
+        let mut response = if not_modified { HttpResponse::NotModified() } else { HttpResponse::Ok() };
+        response
+            .insert_header((header::ETAG, etag))
+            .insert_header((header::CACHE_CONTROL, cache_control(name)))
+            .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"));
+        if not_modified {
+            return response.finish();
+        }
+        response.content_type(content_type).body(body)
+    }
+}
+
+/// Media type of a UI file, or `None` for names that are not served: anything with a
+/// path separator, hidden files and unknown extensions
+fn content_type(name: &str) -> Option<&'static str> {
+    if name.starts_with('.') || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+        return None;
+    }
+    match name.rsplit_once('.')?.1 {
+        "html" => Some("text/html; charset=utf-8"),
+        "css" => Some("text/css; charset=utf-8"),
+        "js" => Some("text/javascript; charset=utf-8"),
+        "svg" => Some("image/svg+xml"),
+        "png" => Some("image/png"),
+        "ico" => Some("image/x-icon"),
+        _ => None,
+    }
+}
+
+/// Pages are revalidated on every load so a new release shows up at once; other assets
+/// may be reused for an hour
+fn cache_control(name: &str) -> &'static str {
+    if name.ends_with(".html") {
+        "no-cache"
+    } else {
+        "public, max-age=3600"
+    }
+}
+
+/// Strong ETag from the first 16 bytes of the body's SHA-256
+fn etag(body: &[u8]) -> String {
+    let hex: String = Sha256::digest(body).iter().take(16).map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+/// Whether `If-None-Match` lists `etag` (weakly compared) or is `*`
+fn matches_etag(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get_all(header::IF_NONE_MATCH)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}